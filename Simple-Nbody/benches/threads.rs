@@ -0,0 +1,64 @@
+// Times force computation serially and on every core, reporting the speedup against n
+// The pairwise direct sum always runs on one thread, so is listed to compare against direct
+// Run with: cargo bench --bench threads
+use std::time::{Duration, Instant};
+
+use simple_nbody::initial_conditions::{flatten, generate_plummer};
+use simple_nbody::parallel::thread_count;
+use simple_nbody::softening::Softening;
+use simple_nbody::{compute_acc_vectors, ForceParameters};
+
+// Each measurement repeats until it has taken at least this long, and keeps the fastest run
+const MIN_DURATION: Duration = Duration::from_millis(500);
+
+fn time_forces(n: u32, method: &str, threads: usize) -> Duration {
+    let (mass_vectors, mut position_vectors, mut velocity_vectors) =
+        generate_plummer(n, 1.0e7, 100.0, 259);
+    // The quadtree is timed on the two dimensional runs it is meant for
+    if method == "barnes_hut" {
+        flatten(&mut position_vectors, &mut velocity_vectors);
+    }
+    let mut fastest = Duration::MAX;
+    let start = Instant::now();
+    while start.elapsed() < MIN_DURATION {
+        let run_start = Instant::now();
+        let acc_vectors = compute_acc_vectors(
+            &position_vectors,
+            &mass_vectors,
+            method,
+            ForceParameters::default(),
+            Softening::default(),
+            threads,
+        );
+        fastest = fastest.min(run_start.elapsed());
+        std::hint::black_box(acc_vectors);
+    }
+
+    fastest
+}
+
+fn main() {
+    let threads = thread_count(0);
+    println!("threads: {threads}");
+    println!("method,n,serial_ms,parallel_ms,speedup");
+
+    for (method, sizes) in [
+        ("direct", vec![100, 300, 1000, 3000, 10000]),
+        ("pairwise", vec![100, 300, 1000, 3000, 10000]),
+        ("barnes_hut", vec![1000, 10000, 50000]),
+        ("octree", vec![1000, 10000, 50000]),
+    ] {
+        for n in sizes {
+            let serial = time_forces(n, method, 1);
+            let parallel = time_forces(n, method, threads);
+            println!(
+                "{},{},{:.3},{:.3},{:.2}",
+                method,
+                n,
+                serial.as_secs_f64() * 1e3,
+                parallel.as_secs_f64() * 1e3,
+                serial.as_secs_f64() / parallel.as_secs_f64()
+            );
+        }
+    }
+}
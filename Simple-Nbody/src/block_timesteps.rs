@@ -0,0 +1,233 @@
+use crate::{generate_acc_jerk_vectors, generate_acc_jerk_vectors_of, Float, Simulation};
+
+// Hierarchical block timesteps for the Hermite integrator
+// Each body steps with h / 2^level for its own level, so bodies in close encounters take
+// many small steps while the rest take few large ones. Steps are powers of two fractions of
+// h, so bodies on the same level are advanced together, and all of them line up again at
+// the end of every step of h, where output and drawing see a synchronised system
+//
+// Levels come from Aarseth's simple criterion dt = eta |a| / |j|, rounded down to the next
+// power of two fraction of h. A body may move to smaller steps at any time, but only to
+// steps twice as long when its current time is a multiple of the longer step, and all
+// levels are chosen afresh at each synchronisation
+// Source: Makino (1991), ApJ 369, 200
+
+// Smallest step is h / 2^BLOCK_MAX_LEVEL
+pub const BLOCK_MAX_LEVEL: u32 = 16;
+// Default accuracy parameter of the timestep criterion
+pub const ETA: Float = 0.02;
+
+pub fn block_level(dt: Float, h: Float) -> u32 {
+    // Smallest level whose step h / 2^level is at most dt
+    let mut level = 0;
+    let mut step = h;
+    while step > dt && level < BLOCK_MAX_LEVEL {
+        step *= 0.5;
+        level += 1;
+    }
+
+    level
+}
+
+impl Simulation {
+    pub fn update_block_hermite(&mut self) {
+        // Advances every body by h, in substeps of h / 2^BLOCK_MAX_LEVEL, counted as ticks
+        // Bodies whose steps end at the next tick are active: all bodies are predicted to
+        // that tick, forces are computed on the active ones only, and they are corrected
+        // as in update_hermite(), each with its own step
+        let n = self.position_vectors.x.len();
+        if self.jerk_vectors.x.len() != n {
+            (self.acc_vectors, self.jerk_vectors) = generate_acc_jerk_vectors(
+                &self.position_vectors,
+                &self.velocity_vectors,
+                &self.mass_vectors,
+                self.softening,
+                self.threads,
+            );
+        }
+
+        let ticks_per_step: u64 = 1 << BLOCK_MAX_LEVEL;
+        let tick_length = self.h / ticks_per_step as Float;
+        let step_ticks = |level: u32| ticks_per_step >> level;
+        self.block_levels = (0..n).map(|i| self.block_level_of(i)).collect();
+        // Tick each body was last advanced to
+        let mut body_ticks = vec![0; n];
+        let mut tick = 0;
+
+        while tick < ticks_per_step {
+            let next_tick = (0..n)
+                .map(|i| body_ticks[i] + step_ticks(self.block_levels[i]))
+                .min()
+                .unwrap_or(ticks_per_step);
+            let active: Vec<usize> = (0..n)
+                .filter(|&i| body_ticks[i] + step_ticks(self.block_levels[i]) == next_tick)
+                .collect();
+
+            // Predict all bodies to the next tick with a Taylor series to third order
+            let mut predicted_positions = self.position_vectors.clone();
+            let mut predicted_velocities = self.velocity_vectors.clone();
+            for i in 0..n {
+                let dt = (next_tick - body_ticks[i]) as Float * tick_length;
+                let (x, y, z) = self.position_vectors.at(i);
+                let (vx, vy, vz) = self.velocity_vectors.at(i);
+                let (ax, ay, az) = self.acc_vectors.at(i);
+                let (jx, jy, jz) = self.jerk_vectors.at(i);
+                predicted_positions.x[i] =
+                    x + dt * vx + dt.powi(2) / 2.0 * ax + dt.powi(3) / 6.0 * jx;
+                predicted_positions.y[i] =
+                    y + dt * vy + dt.powi(2) / 2.0 * ay + dt.powi(3) / 6.0 * jy;
+                predicted_positions.z[i] =
+                    z + dt * vz + dt.powi(2) / 2.0 * az + dt.powi(3) / 6.0 * jz;
+                predicted_velocities.x[i] = vx + dt * ax + dt.powi(2) / 2.0 * jx;
+                predicted_velocities.y[i] = vy + dt * ay + dt.powi(2) / 2.0 * jy;
+                predicted_velocities.z[i] = vz + dt * az + dt.powi(2) / 2.0 * jz;
+            }
+
+            let (acc1, jerk1) = generate_acc_jerk_vectors_of(
+                &active,
+                &predicted_positions,
+                &predicted_velocities,
+                &self.mass_vectors,
+                self.softening,
+                self.threads,
+            );
+
+            for (k, &i) in active.iter().enumerate() {
+                let dt = step_ticks(self.block_levels[i]) as Float * tick_length;
+                let (ax0, ay0, az0) = self.acc_vectors.at(i);
+                let (jx0, jy0, jz0) = self.jerk_vectors.at(i);
+                let (ax1, ay1, az1) = acc1.at(k);
+                let (jx1, jy1, jz1) = jerk1.at(k);
+                let (vx0, vy0, vz0) = self.velocity_vectors.at(i);
+
+                let vx1 = vx0 + dt / 2.0 * (ax0 + ax1) + dt.powi(2) / 12.0 * (jx0 - jx1);
+                let vy1 = vy0 + dt / 2.0 * (ay0 + ay1) + dt.powi(2) / 12.0 * (jy0 - jy1);
+                let vz1 = vz0 + dt / 2.0 * (az0 + az1) + dt.powi(2) / 12.0 * (jz0 - jz1);
+                self.position_vectors.x[i] +=
+                    dt / 2.0 * (vx0 + vx1) + dt.powi(2) / 12.0 * (ax0 - ax1);
+                self.position_vectors.y[i] +=
+                    dt / 2.0 * (vy0 + vy1) + dt.powi(2) / 12.0 * (ay0 - ay1);
+                self.position_vectors.z[i] +=
+                    dt / 2.0 * (vz0 + vz1) + dt.powi(2) / 12.0 * (az0 - az1);
+                self.velocity_vectors.x[i] = vx1;
+                self.velocity_vectors.y[i] = vy1;
+                self.velocity_vectors.z[i] = vz1;
+                self.acc_vectors.x[i] = ax1;
+                self.acc_vectors.y[i] = ay1;
+                self.acc_vectors.z[i] = az1;
+                self.jerk_vectors.x[i] = jx1;
+                self.jerk_vectors.y[i] = jy1;
+                self.jerk_vectors.z[i] = jz1;
+                body_ticks[i] = next_tick;
+
+                // Smaller steps are always allowed, and a step twice as long once aligned
+                let level = self.block_levels[i];
+                let wanted = self.block_level_of(i);
+                if wanted > level {
+                    self.block_levels[i] = wanted;
+                } else if wanted < level && next_tick % step_ticks(level - 1) == 0 {
+                    self.block_levels[i] = level - 1;
+                }
+            }
+
+            tick = next_tick;
+        }
+    }
+
+    fn block_level_of(&self, i: usize) -> u32 {
+        // Level from the criterion, with bodies feeling no change in force on level 0
+        let (ax, ay, az) = self.acc_vectors.at(i);
+        let (jx, jy, jz) = self.jerk_vectors.at(i);
+        let acc = (ax * ax + ay * ay + az * az).sqrt();
+        let jerk = (jx * jx + jy * jy + jz * jz).sqrt();
+        if jerk == 0.0 {
+            return 0;
+        }
+
+        block_level(self.eta * acc / jerk, self.h)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::block_timesteps::*;
+    use crate::softening::Softening;
+    use crate::VectorArray;
+
+    fn hierarchical_system(integrator: &str, h: Float) -> Simulation {
+        // A tight eccentric binary at the origin, orbited by two light bodies far out
+        let mass_vectors = vec![5_000.0, 5_000.0, 1.0, 1.0];
+        let position_vectors = VectorArray {
+            x: vec![-5.0, 5.0, 200.0, -300.0],
+            y: vec![0.0, 0.0, 0.0, 0.0],
+            z: vec![0.0, 0.0, 0.0, 0.0],
+        };
+        // The binary's relative speed is a third of the circular speed, so it has an
+        // eccentricity of 0.9 and a pericentre of about 0.5, with a period of about 0.76
+        let velocity_vectors = VectorArray {
+            x: vec![0.0, 0.0, 0.0, 0.0],
+            y: vec![-5.0, 5.0, 7.07, -5.77],
+            z: vec![0.0, 0.0, 0.0, 0.0],
+        };
+        let mut simulation = Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            "direct",
+            0.0,
+            integrator,
+            Softening::None,
+        );
+        simulation.h = h;
+
+        simulation
+    }
+
+    #[test]
+    fn test_block_level() {
+        assert_eq!(block_level(2.0, 1.0), 0);
+        assert_eq!(block_level(1.0, 1.0), 0);
+        assert_eq!(block_level(0.3, 1.0), 2);
+        assert_eq!(block_level(0.25, 1.0), 2);
+        assert_eq!(block_level(0.0, 1.0), BLOCK_MAX_LEVEL);
+    }
+
+    #[test]
+    fn test_binary_takes_smaller_steps() {
+        let mut simulation = hierarchical_system("block_hermite", 1.0);
+        simulation.update();
+
+        assert_eq!(simulation.t, 1.0);
+        let binary_level = simulation.block_levels[0].min(simulation.block_levels[1]);
+        let outer_level = simulation.block_levels[2].max(simulation.block_levels[3]);
+        assert!(
+            binary_level > outer_level + 2,
+            "{:?}",
+            simulation.block_levels
+        );
+    }
+
+    #[test]
+    fn test_block_hermite_energy() {
+        // With steps of h, the global Hermite integrator cannot follow the binary's close
+        // pericentre passages, while block steps keep the error small
+        let max_energy_error = |integrator| {
+            let mut simulation = hierarchical_system(integrator, 0.5);
+            let initial = simulation.diagnostics();
+            let mut max_error: Float = 0.0;
+            for _i in 0..100 {
+                simulation.update();
+                max_error = max_error.max(simulation.diagnostics().energy_drift(&initial).abs());
+            }
+            println!("{integrator} maximum relative energy error: {max_error}");
+
+            max_error
+        };
+
+        // Over 60 binary orbits, the error in single precision is mostly round-off from
+        // the many short steps
+        let block_error = max_energy_error("block_hermite");
+        assert!(block_error < 1e-3);
+        assert!(block_error < 0.01 * max_energy_error("hermite"));
+    }
+}
@@ -0,0 +1,107 @@
+// Perspective camera for drawing three dimensional runs
+// The camera orbits the simulation origin, looking at it from distance along the rotated z
+// axis. With no rotation the x-y plane faces the screen, and points in that plane are drawn
+// at the same scale as two dimensional runs
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Camera {
+    // Rotation about the vertical axis, then about the horizontal axis, in radians
+    pub yaw: f32,
+    pub pitch: f32,
+    pub distance: f32,
+    // Screen distance, so a point at the origin is drawn focal_length / distance times larger
+    pub focal_length: f32,
+}
+
+// Points closer than this to the camera plane are not drawn
+pub const NEAR_PLANE: f32 = 1.0;
+pub const MIN_DISTANCE: f32 = 10.0;
+
+impl Camera {
+    pub fn new(distance: f32) -> Camera {
+        Camera {
+            yaw: 0.0,
+            pitch: 0.0,
+            distance,
+            focal_length: distance,
+        }
+    }
+
+    pub fn rotate(&mut self, d_yaw: f32, d_pitch: f32) {
+        // Pitch stops short of looking straight down, where yaw would flip the view
+        let limit = 0.49 * std::f32::consts::PI;
+        self.yaw += d_yaw;
+        self.pitch = (self.pitch + d_pitch).clamp(-limit, limit);
+    }
+
+    pub fn zoom(&mut self, factor: f32) {
+        self.distance = (self.distance * factor).max(MIN_DISTANCE);
+    }
+
+    pub fn project(&self, position: (f32, f32, f32)) -> Option<(f32, f32, f32)> {
+        // Returns screen offsets from the centre, with y upwards, and the scale at that depth
+        let (x, y, z) = position;
+        let (sin_yaw, cos_yaw) = self.yaw.sin_cos();
+        let (sin_pitch, cos_pitch) = self.pitch.sin_cos();
+
+        let x_rotated = cos_yaw * x + sin_yaw * z;
+        let z_yawed = -sin_yaw * x + cos_yaw * z;
+        let y_rotated = cos_pitch * y - sin_pitch * z_yawed;
+        let z_rotated = sin_pitch * y + cos_pitch * z_yawed;
+
+        let depth = self.distance - z_rotated;
+        if depth < NEAR_PLANE {
+            return None;
+        }
+        let scale = self.focal_length / depth;
+
+        Some((scale * x_rotated, scale * y_rotated, scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::camera::Camera;
+
+    fn assert_close(a: (f32, f32, f32), b: (f32, f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-3 && (a.1 - b.1).abs() < 1e-3 && (a.2 - b.2).abs() < 1e-5,
+            "{a:?} and {b:?} differ"
+        );
+    }
+
+    #[test]
+    fn test_project() {
+        let mut camera = Camera::new(1000.0);
+
+        // The x-y plane is drawn as in two dimensions, nearer points larger
+        assert_close(
+            camera.project((30.0, -40.0, 0.0)).unwrap(),
+            (30.0, -40.0, 1.0),
+        );
+        assert_close(
+            camera.project((30.0, -40.0, 500.0)).unwrap(),
+            (60.0, -80.0, 2.0),
+        );
+        assert_close(
+            camera.project((30.0, -40.0, -1000.0)).unwrap(),
+            (15.0, -20.0, 0.5),
+        );
+        assert!(camera.project((0.0, 0.0, 1500.0)).is_none());
+
+        // Turning a quarter about the vertical axis looks along the x axis
+        camera.rotate(std::f32::consts::FRAC_PI_2, 0.0);
+        assert_close(
+            camera.project((0.0, 0.0, 100.0)).unwrap(),
+            (100.0, 0.0, 1.0),
+        );
+        assert_close(camera.project((-500.0, 0.0, 0.0)).unwrap(), (0.0, 0.0, 2.0));
+
+        // Zooming out shrinks everything
+        let mut camera = Camera::new(1000.0);
+        camera.zoom(2.0);
+        assert_close(
+            camera.project((30.0, -40.0, 0.0)).unwrap(),
+            (15.0, -20.0, 0.5),
+        );
+    }
+}
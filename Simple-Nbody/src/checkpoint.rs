@@ -0,0 +1,580 @@
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::collisions::Collisions;
+use crate::external::ExternalPotential;
+use crate::fmm::valid_order;
+use crate::particle_mesh::{valid_grid, GreenCache};
+use crate::post_newtonian::PostNewtonian;
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use crate::{Float, Simulation, VectorArray};
+
+// Binary checkpoints hold everything needed to continue a run bit-for-bit
+// Unlike snapshots, the cached accelerations (and jerks for Hermite) are stored too,
+// as Hermite's are evaluated at predicted rather than current positions
+// Checkpoints written in the other precision are converted on reading, so a restart only
+// continues bit-for-bit in the precision the checkpoint was written in
+//
+// Layout, all little-endian, with Float either f32 or f64 as given by float size:
+// magic            8 bytes, "NBODYCHK"
+// version          u32
+// float size       u32, 4 or 8 bytes
+// n                u64
+// iteration        u64
+// seed             u64
+// t                Float
+// h                Float
+// theta            Float
+// integrator       u64 length, then utf-8 bytes
+// force_method     u64 length, then utf-8 bytes
+// softening        u64 length, then utf-8 bytes
+// softening length Float
+// dimensions       u32
+// units            u64 length, then utf-8 bytes
+// collisions       u64 length, then utf-8 bytes
+// restitution      Float
+// density          Float
+// eta              Float
+// box size         Float, 0 for open boundaries
+// pm grid          u64
+// fmm order        u64
+// external         u64 length, then utf-8 bytes, empty for none
+// post-Newtonian   u64 length, then utf-8 bytes
+// speed of light   Float
+// mass_vectors     n Float
+// position x, y, z n Float each
+// velocity x, y, z n Float each
+// acc x, y, z      n Float each
+// jerk length      u64, either 0 or n
+// jerk x, y, z     jerk length Float each
+
+pub const CHECKPOINT_MAGIC: &[u8; 8] = b"NBODYCHK";
+pub const CHECKPOINT_VERSION: u32 = 1;
+// Longest string a checkpoint may hold, so a corrupt length is rejected before allocating
+const CHECKPOINT_MAX_STRING: usize = 1 << 16;
+
+pub fn checkpoint_path(output_dir: &Path, iteration: u64) -> PathBuf {
+    output_dir.join(format!("checkpoint_{:08}.bin", iteration))
+}
+
+pub fn write_checkpoint(path: &Path, simulation: &Simulation) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    let n = simulation.mass_vectors.len();
+
+    file.write_all(CHECKPOINT_MAGIC)?;
+    file.write_all(&CHECKPOINT_VERSION.to_le_bytes())?;
+    file.write_all(&(std::mem::size_of::<Float>() as u32).to_le_bytes())?;
+    file.write_all(&(n as u64).to_le_bytes())?;
+    file.write_all(&simulation.iteration.to_le_bytes())?;
+    file.write_all(&simulation.seed.to_le_bytes())?;
+    file.write_all(&simulation.t.to_le_bytes())?;
+    file.write_all(&simulation.h.to_le_bytes())?;
+    file.write_all(&simulation.theta.to_le_bytes())?;
+    write_string(&mut file, &simulation.integrator)?;
+    write_string(&mut file, &simulation.force_method)?;
+    write_string(&mut file, simulation.softening.name())?;
+    file.write_all(&simulation.softening.length().to_le_bytes())?;
+    file.write_all(&simulation.dimensions.to_le_bytes())?;
+    write_string(&mut file, simulation.units.name)?;
+    write_string(&mut file, simulation.collisions.name())?;
+    file.write_all(&simulation.collisions.restitution().to_le_bytes())?;
+    file.write_all(&simulation.density.to_le_bytes())?;
+    file.write_all(&simulation.eta.to_le_bytes())?;
+    file.write_all(&simulation.box_size.unwrap_or(0.0).to_le_bytes())?;
+    file.write_all(&(simulation.pm_grid as u64).to_le_bytes())?;
+    file.write_all(&(simulation.fmm_order as u64).to_le_bytes())?;
+    let external = simulation.external.as_ref().map(|external| external.spec());
+    write_string(&mut file, external.as_deref().unwrap_or(""))?;
+    write_string(&mut file, simulation.post_newtonian.name())?;
+    file.write_all(&simulation.post_newtonian.speed_of_light().to_le_bytes())?;
+
+    write_floats(&mut file, &simulation.mass_vectors)?;
+    write_vector_array(&mut file, &simulation.position_vectors)?;
+    write_vector_array(&mut file, &simulation.velocity_vectors)?;
+    write_vector_array(&mut file, &simulation.acc_vectors)?;
+    file.write_all(&(simulation.jerk_vectors.x.len() as u64).to_le_bytes())?;
+    write_vector_array(&mut file, &simulation.jerk_vectors)?;
+
+    file.flush()
+}
+
+pub fn read_checkpoint(path: &Path) -> io::Result<Simulation> {
+    let mut file = BufReader::new(File::open(path)?);
+
+    let mut magic = [0; 8];
+    file.read_exact(&mut magic)?;
+    if &magic != CHECKPOINT_MAGIC {
+        return Err(invalid_data("Not a checkpoint file"));
+    }
+    let version = u32::from_le_bytes(read_bytes(&mut file)?);
+    if version != CHECKPOINT_VERSION {
+        return Err(invalid_data(&format!(
+            "Unsupported checkpoint version {version}, expected {CHECKPOINT_VERSION}"
+        )));
+    }
+    let float_size = u32::from_le_bytes(read_bytes(&mut file)?);
+    if float_size != 4 && float_size != 8 {
+        return Err(invalid_data(&format!(
+            "Unsupported float size {float_size}"
+        )));
+    }
+
+    let n = u64::from_le_bytes(read_bytes(&mut file)?);
+    // Masses alone take n floats, so a corrupt count is caught before allocating for it
+    let file_size = file.get_ref().metadata()?.len();
+    if n.saturating_mul(float_size as u64) > file_size {
+        return Err(invalid_data(&format!(
+            "Checkpoint of {file_size} bytes is too small for {n} bodies"
+        )));
+    }
+    let n = n as usize;
+    let iteration = u64::from_le_bytes(read_bytes(&mut file)?);
+    let seed = u64::from_le_bytes(read_bytes(&mut file)?);
+    let t = read_float(&mut file, float_size)?;
+    let h = read_float(&mut file, float_size)?;
+    let theta = read_float(&mut file, float_size)?;
+    let integrator = read_string(&mut file)?;
+    let force_method = read_string(&mut file)?;
+    let name = read_string(&mut file)?;
+    let length = read_float(&mut file, float_size)?;
+    let softening = Softening::from_name(&name, length).map_err(|error| invalid_data(&error))?;
+    let dimensions = u32::from_le_bytes(read_bytes(&mut file)?);
+    let name = read_string(&mut file)?;
+    let units = UnitSystem::from_name(&name).map_err(|error| invalid_data(&error))?;
+    let name = read_string(&mut file)?;
+    let restitution = read_float(&mut file, float_size)?;
+    let collisions =
+        Collisions::from_name(&name, restitution).map_err(|error| invalid_data(&error))?;
+    let density = read_float(&mut file, float_size)?;
+    let eta = read_float(&mut file, float_size)?;
+    let size = read_float(&mut file, float_size)?;
+    let box_size = (size > 0.0).then_some(size);
+    let pm_grid = u64::from_le_bytes(read_bytes(&mut file)?) as usize;
+    if !valid_grid(pm_grid) {
+        return Err(invalid_data(&format!(
+            "Invalid particle mesh grid {pm_grid}"
+        )));
+    }
+    let fmm_order = u64::from_le_bytes(read_bytes(&mut file)?) as usize;
+    if !valid_order(fmm_order) {
+        return Err(invalid_data(&format!("Invalid FMM order {fmm_order}")));
+    }
+    // Stored in simulation units, so masses are already converted
+    let spec = read_string(&mut file)?;
+    let mut external = None;
+    if !spec.is_empty() {
+        external = Some(
+            ExternalPotential::from_spec(&spec, &UnitSystem::NBODY)
+                .map_err(|error| invalid_data(&error))?,
+        );
+    }
+    let name = read_string(&mut file)?;
+    let speed_of_light = read_float(&mut file, float_size)?;
+    let post_newtonian =
+        PostNewtonian::from_name(&name, speed_of_light).map_err(|error| invalid_data(&error))?;
+
+    let mass_vectors = read_floats(&mut file, n, float_size)?;
+    let position_vectors = read_vector_array(&mut file, n, float_size)?;
+    let velocity_vectors = read_vector_array(&mut file, n, float_size)?;
+    let acc_vectors = read_vector_array(&mut file, n, float_size)?;
+    let jerk_length = u64::from_le_bytes(read_bytes(&mut file)?) as usize;
+    if jerk_length != 0 && jerk_length != n {
+        return Err(invalid_data(&format!(
+            "Jerk length {jerk_length} in checkpoint of {n} bodies"
+        )));
+    }
+    let jerk_vectors = read_vector_array(&mut file, jerk_length, float_size)?;
+
+    let simulation = Simulation {
+        mass_vectors,
+        position_vectors,
+        velocity_vectors,
+        acc_vectors,
+        jerk_vectors,
+        force_method,
+        theta,
+        pm_grid,
+        pm_green: GreenCache::default(),
+        fmm_order,
+        softening,
+        post_newtonian,
+        collisions,
+        density,
+        integrator,
+        h,
+        eta,
+        block_levels: Vec::new(),
+        iteration,
+        t,
+        seed,
+        dimensions,
+        box_size,
+        external,
+        units,
+        threads: 1,
+    };
+    simulation
+        .validate()
+        .map_err(|error| invalid_data(&error))?;
+
+    Ok(simulation)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_bytes<const N: usize>(file: &mut impl Read) -> io::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    file.read_exact(&mut bytes)?;
+
+    Ok(bytes)
+}
+
+fn write_string(file: &mut impl Write, string: &str) -> io::Result<()> {
+    file.write_all(&(string.len() as u64).to_le_bytes())?;
+    file.write_all(string.as_bytes())
+}
+
+fn read_string(file: &mut impl Read) -> io::Result<String> {
+    let length = u64::from_le_bytes(read_bytes(file)?);
+    if length > CHECKPOINT_MAX_STRING as u64 {
+        return Err(invalid_data(&format!(
+            "String of {length} bytes in checkpoint"
+        )));
+    }
+    let mut bytes = vec![0; length as usize];
+    file.read_exact(&mut bytes)?;
+
+    String::from_utf8(bytes).map_err(|_| invalid_data("Invalid string in checkpoint"))
+}
+
+fn write_floats(file: &mut impl Write, values: &[Float]) -> io::Result<()> {
+    for value in values {
+        file.write_all(&value.to_le_bytes())?;
+    }
+
+    Ok(())
+}
+
+fn read_float(file: &mut impl Read, float_size: u32) -> io::Result<Float> {
+    // Reads a value of float_size bytes, converting it to Float if needed
+    if float_size == 8 {
+        Ok(f64::from_le_bytes(read_bytes(file)?) as Float)
+    } else {
+        Ok(f32::from_le_bytes(read_bytes(file)?) as Float)
+    }
+}
+
+fn read_floats(file: &mut impl Read, n: usize, float_size: u32) -> io::Result<Vec<Float>> {
+    // n comes from the file, so is not trusted with an allocation up front
+    let mut values = Vec::new();
+    for _i in 0..n {
+        values.push(read_float(file, float_size)?);
+    }
+
+    Ok(values)
+}
+
+fn write_vector_array(file: &mut impl Write, vector_array: &VectorArray) -> io::Result<()> {
+    write_floats(file, &vector_array.x)?;
+    write_floats(file, &vector_array.y)?;
+    write_floats(file, &vector_array.z)
+}
+
+fn read_vector_array(file: &mut impl Read, n: usize, float_size: u32) -> io::Result<VectorArray> {
+    let x = read_floats(file, n, float_size)?;
+    let y = read_floats(file, n, float_size)?;
+    let z = read_floats(file, n, float_size)?;
+
+    Ok(VectorArray { x, y, z })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::checkpoint::*;
+    use crate::collisions::Collisions;
+    use crate::initial_conditions::*;
+    use crate::softening::Softening;
+    use crate::units::UnitSystem;
+    use crate::{Simulation, VectorArray};
+
+    fn random_simulation(
+        force_method: &str,
+        integrator: &str,
+        softening: Softening,
+        collisions: Collisions,
+    ) -> Simulation {
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            generate_uniform_box(50, 640.0, 360.0, 360.0, 7);
+        let mut simulation = Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            force_method,
+            0.5,
+            integrator,
+            softening,
+        );
+        simulation.h = 0.05;
+        simulation.seed = 7;
+        simulation.dimensions = 3;
+        simulation.units = UnitSystem::GALACTIC;
+        // Low density gives large bodies, so collisions happen within the test
+        simulation.collisions = collisions;
+        simulation.density = 1.0;
+
+        simulation
+    }
+
+    fn assert_identical(a: &VectorArray, b: &VectorArray) {
+        // Compares bit patterns, so -0.0 and 0.0 would count as different
+        for i in 0..a.x.len() {
+            assert_eq!(a.x[i].to_bits(), b.x[i].to_bits());
+            assert_eq!(a.y[i].to_bits(), b.y[i].to_bits());
+            assert_eq!(a.z[i].to_bits(), b.z[i].to_bits());
+        }
+    }
+
+    #[test]
+    fn test_restart_is_bit_for_bit() {
+        let output_dir = std::env::temp_dir().join("simple_nbody_test_restart");
+        fs::create_dir_all(&output_dir).unwrap();
+
+        let none = Collisions::None;
+        for (force_method, integrator, softening, collisions) in [
+            ("direct", "leap_frog", Softening::default(), none),
+            ("barnes_hut", "yoshida", Softening::default(), none),
+            ("pairwise", "rk4", Softening::default(), none),
+            ("direct", "hermite", Softening::default(), none),
+            ("barnes_hut", "leap_frog", Softening::Spline(10.0), none),
+            ("octree_quadrupole", "leap_frog", Softening::default(), none),
+            ("fmm", "yoshida", Softening::Spline(10.0), none),
+            ("direct", "hermite", Softening::default(), Collisions::Merge),
+            (
+                "direct",
+                "leap_frog",
+                Softening::default(),
+                Collisions::Bounce(0.5),
+            ),
+            ("direct", "block_hermite", Softening::default(), none),
+        ] {
+            let mut uninterrupted =
+                random_simulation(force_method, integrator, softening, collisions);
+            if force_method == "fmm" {
+                // FMM only supports planar runs
+                let simulation = &mut uninterrupted;
+                flatten(
+                    &mut simulation.position_vectors,
+                    &mut simulation.velocity_vectors,
+                );
+                simulation.dimensions = 2;
+                simulation.acc_vectors = simulation.compute_acc_vectors();
+            }
+            let mut interrupted = uninterrupted.clone();
+            for _i in 0..40 {
+                uninterrupted.update();
+                interrupted.update();
+            }
+
+            let path = checkpoint_path(&output_dir, interrupted.iteration);
+            write_checkpoint(&path, &interrupted).unwrap();
+            drop(interrupted);
+            let mut restarted = read_checkpoint(&path).unwrap();
+
+            for _i in 0..60 {
+                uninterrupted.update();
+                restarted.update();
+            }
+
+            assert_eq!(restarted.iteration, 100);
+            assert_eq!(restarted.t.to_bits(), uninterrupted.t.to_bits());
+            assert_eq!(restarted.seed, 7);
+            assert_eq!(restarted.integrator, integrator);
+            assert_eq!(restarted.force_method, force_method);
+            assert_eq!(restarted.softening, softening);
+            assert_eq!(restarted.dimensions, uninterrupted.dimensions);
+            assert_eq!(restarted.units, UnitSystem::GALACTIC);
+            assert_eq!(restarted.collisions, collisions);
+            assert_eq!(restarted.density, 1.0);
+            assert_eq!(restarted.mass_vectors, uninterrupted.mass_vectors);
+            if collisions == Collisions::Merge {
+                assert!(restarted.mass_vectors.len() < 50);
+            }
+            assert_identical(&restarted.position_vectors, &uninterrupted.position_vectors);
+            assert_identical(&restarted.velocity_vectors, &uninterrupted.velocity_vectors);
+        }
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_periodic_restart() {
+        let path = std::env::temp_dir().join("simple_nbody_test_periodic_restart.bin");
+        for force_method in ["direct", "pm"] {
+            let mut uninterrupted = random_simulation(
+                force_method,
+                "leap_frog",
+                Softening::default(),
+                Collisions::None,
+            );
+            uninterrupted.pm_grid = 16;
+            uninterrupted.make_periodic(500.0);
+            for _i in 0..20 {
+                uninterrupted.update();
+            }
+
+            write_checkpoint(&path, &uninterrupted).unwrap();
+            let mut restarted = read_checkpoint(&path).unwrap();
+            fs::remove_file(&path).unwrap();
+            for _i in 0..30 {
+                uninterrupted.update();
+                restarted.update();
+            }
+
+            assert_eq!(restarted.box_size, Some(500.0));
+            assert_eq!(restarted.pm_grid, 16);
+            assert_identical(&restarted.position_vectors, &uninterrupted.position_vectors);
+            assert_identical(&restarted.velocity_vectors, &uninterrupted.velocity_vectors);
+        }
+    }
+
+    #[test]
+    fn test_external_restart() {
+        // The rotating bar depends on time, which has to carry over as well
+        let path = std::env::temp_dir().join("simple_nbody_test_external_restart.bin");
+        let mut uninterrupted = random_simulation(
+            "barnes_hut",
+            "yoshida",
+            Softening::default(),
+            Collisions::None,
+        );
+        uninterrupted.external = Some(
+            ExternalPotential::Nfw {
+                mass: 1.0e8,
+                scale_radius: 200.0,
+            } + ExternalPotential::Bar {
+                strength: 1.0e4,
+                radius: 100.0,
+                pattern_speed: 0.3,
+            },
+        );
+        uninterrupted.acc_vectors = uninterrupted.compute_acc_vectors();
+        for _i in 0..20 {
+            uninterrupted.update();
+        }
+
+        write_checkpoint(&path, &uninterrupted).unwrap();
+        let mut restarted = read_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        for _i in 0..30 {
+            uninterrupted.update();
+            restarted.update();
+        }
+
+        assert_eq!(restarted.external, uninterrupted.external);
+        assert_identical(&restarted.position_vectors, &uninterrupted.position_vectors);
+        assert_identical(&restarted.velocity_vectors, &uninterrupted.velocity_vectors);
+    }
+
+    #[test]
+    fn test_post_newtonian_restart() {
+        // Corrections depend on velocities, which rk4 passes through its stages
+        let path = std::env::temp_dir().join("simple_nbody_test_post_newtonian_restart.bin");
+        let mut uninterrupted =
+            random_simulation("direct", "rk4", Softening::None, Collisions::None);
+        uninterrupted.post_newtonian = PostNewtonian::RadiationReaction(20.0);
+        uninterrupted.acc_vectors = uninterrupted.compute_acc_vectors();
+        for _i in 0..20 {
+            uninterrupted.update();
+        }
+
+        write_checkpoint(&path, &uninterrupted).unwrap();
+        let mut restarted = read_checkpoint(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        for _i in 0..30 {
+            uninterrupted.update();
+            restarted.update();
+        }
+
+        assert_eq!(
+            restarted.post_newtonian,
+            PostNewtonian::RadiationReaction(20.0)
+        );
+        assert_identical(&restarted.position_vectors, &uninterrupted.position_vectors);
+        assert_identical(&restarted.velocity_vectors, &uninterrupted.velocity_vectors);
+    }
+
+    #[test]
+    fn test_read_float_converts_precision() {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&1.5_f32.to_le_bytes());
+        bytes.extend_from_slice(&(-0.25_f64).to_le_bytes());
+        let mut file = bytes.as_slice();
+
+        assert_eq!(read_float(&mut file, 4).unwrap(), 1.5);
+        assert_eq!(read_float(&mut file, 8).unwrap(), -0.25);
+    }
+
+    #[test]
+    fn test_read_checkpoint_rejects_corrupt_lengths() {
+        // Huge body counts and string lengths are errors, rather than aborting the process
+        // by trying to allocate for them
+        let path = std::env::temp_dir().join("simple_nbody_test_corrupt_checkpoint.bin");
+        let simulation = random_simulation(
+            "direct",
+            "leap_frog",
+            Softening::default(),
+            Collisions::None,
+        );
+        write_checkpoint(&path, &simulation).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        // After the magic, version and float size, then n, iteration, seed, t, h and theta
+        let n_offset = 16;
+        let integrator_offset = 40 + 3 * std::mem::size_of::<Float>();
+
+        for offset in [n_offset, integrator_offset] {
+            let mut corrupt = bytes.clone();
+            corrupt[offset..offset + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+            fs::write(&path, &corrupt).unwrap();
+            let error = read_checkpoint(&path).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_checkpoint_rejects_invalid_solver_settings() {
+        // Particle mesh grids and FMM orders are checked as on the command line, before the
+        // solvers allocate for them
+        let path = std::env::temp_dir().join("simple_nbody_test_invalid_settings.bin");
+        for (pm_grid, fmm_order) in [(48, 8), (1 << 40, 8), (64, 0), (64, 1000)] {
+            let mut simulation = random_simulation(
+                "direct",
+                "leap_frog",
+                Softening::default(),
+                Collisions::None,
+            );
+            simulation.pm_grid = pm_grid;
+            simulation.fmm_order = fmm_order;
+            write_checkpoint(&path, &simulation).unwrap();
+            let error = read_checkpoint(&path).err().unwrap();
+            assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_read_checkpoint_rejects_other_files() {
+        let path = std::env::temp_dir().join("simple_nbody_test_not_a_checkpoint.bin");
+        fs::write(&path, b"index,mass,x,y,vx,vy\n").unwrap();
+
+        let error = read_checkpoint(&path).err().unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+}
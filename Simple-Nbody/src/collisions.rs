@@ -0,0 +1,321 @@
+use crate::{float_consts, Float, Simulation, VectorArray};
+
+// Collisions between bodies, treated as uniform spheres with radii given by their mass and
+// a common density. After each step, every pair of overlapping bodies is resolved
+//
+// None: bodies pass through each other, with only softening to keep forces finite
+// Merge: the pair becomes a single body at their centre of mass, with their total mass and
+//        momentum. The lower index survives, and the arrays are compacted
+// Bounce(restitution): the pair exchanges momentum along the line between their centres,
+//        keeping restitution times their approach speed as separation speed. 1 is elastic,
+//        and 0 leaves them moving together along that line
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Collisions {
+    #[default]
+    None,
+    Merge,
+    Bounce(Float),
+}
+
+impl Collisions {
+    pub fn from_name(name: &str, restitution: Float) -> Result<Collisions, String> {
+        // Mode names as given on the command line and stored in checkpoints
+        if name == "none" {
+            Ok(Collisions::None)
+        } else if name == "merge" {
+            Ok(Collisions::Merge)
+        } else if name == "bounce" {
+            Ok(Collisions::Bounce(restitution))
+        } else {
+            Err(format!("Invalid collision mode {name}"))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Collisions::None => "none",
+            Collisions::Merge => "merge",
+            Collisions::Bounce(_) => "bounce",
+        }
+    }
+
+    pub fn restitution(&self) -> Float {
+        match *self {
+            Collisions::Bounce(restitution) => restitution,
+            _ => 0.0,
+        }
+    }
+}
+
+pub fn compute_radii(mass_vectors: &[Float], density: Float) -> Vec<Float> {
+    // Radii of uniform spheres, with density in the same G times mass units as mass_vectors
+    mass_vectors
+        .iter()
+        .map(|mass| (3.0 * mass / (4.0 * float_consts::PI * density)).cbrt())
+        .collect()
+}
+
+pub fn find_collisions(position_vectors: &VectorArray, radii: &[Float]) -> Vec<(usize, usize)> {
+    // Sweep and prune: bodies are sorted by the lower edge of their extent in x, and each is
+    // only compared with those whose extent in x starts before its own ends
+    // Pairs are returned with the lower index first, ordered by the sweep
+    let n = radii.len();
+    let mut order: Vec<usize> = (0..n).collect();
+    order.sort_by(|&a, &b| {
+        let a_start = position_vectors.x[a] - radii[a];
+        let b_start = position_vectors.x[b] - radii[b];
+        a_start.total_cmp(&b_start)
+    });
+
+    let mut pairs = Vec::new();
+    for (k, &i) in order.iter().enumerate() {
+        let (x_i, y_i, z_i) = position_vectors.at(i);
+        let x_end = x_i + radii[i];
+        for &j in &order[k + 1..] {
+            if position_vectors.x[j] - radii[j] > x_end {
+                break;
+            }
+            let (x_j, y_j, z_j) = position_vectors.at(j);
+            let r_sq = (x_j - x_i).powi(2) + (y_j - y_i).powi(2) + (z_j - z_i).powi(2);
+            if r_sq < (radii[i] + radii[j]).powi(2) {
+                pairs.push((i.min(j), i.max(j)));
+            }
+        }
+    }
+
+    pairs
+}
+
+impl Simulation {
+    pub fn resolve_collisions(&mut self) -> usize {
+        // Resolves every overlapping pair, returning how many there were
+        // Cached accelerations are recomputed after merging, and jerks are dropped for
+        // Hermite to recompute, as they depend on the changed bodies
+        if self.collisions == Collisions::None {
+            return 0;
+        }
+        let radii = compute_radii(&self.mass_vectors, self.density);
+        let pairs = find_collisions(&self.position_vectors, &radii);
+        if pairs.is_empty() {
+            return 0;
+        }
+
+        match self.collisions {
+            Collisions::Merge => {
+                merge_bodies(
+                    &pairs,
+                    &mut self.mass_vectors,
+                    &mut self.position_vectors,
+                    &mut self.velocity_vectors,
+                );
+                self.acc_vectors = self.compute_acc_vectors();
+            }
+            Collisions::Bounce(restitution) => bounce_bodies(
+                &pairs,
+                restitution,
+                &self.mass_vectors,
+                &self.position_vectors,
+                &mut self.velocity_vectors,
+            ),
+            Collisions::None => {}
+        }
+        self.jerk_vectors = VectorArray::zeros(0);
+
+        pairs.len()
+    }
+}
+
+pub fn merge_bodies(
+    pairs: &[(usize, usize)],
+    mass_vectors: &mut Vec<Float>,
+    position_vectors: &mut VectorArray,
+    velocity_vectors: &mut VectorArray,
+) {
+    // Merges the second body of each pair into the first, then removes the merged bodies
+    // A pair with a body already merged away this step is skipped. If the survivor still
+    // overlaps the other body, it is merged on the next step
+    let n = mass_vectors.len();
+    let mut keep = vec![true; n];
+
+    for &(i, j) in pairs {
+        if !keep[i] || !keep[j] {
+            continue;
+        }
+        let (m_i, m_j) = (mass_vectors[i], mass_vectors[j]);
+        let mass = m_i + m_j;
+        if mass > 0.0 {
+            let weighted = |a: Float, b: Float| (m_i * a + m_j * b) / mass;
+            position_vectors.x[i] = weighted(position_vectors.x[i], position_vectors.x[j]);
+            position_vectors.y[i] = weighted(position_vectors.y[i], position_vectors.y[j]);
+            position_vectors.z[i] = weighted(position_vectors.z[i], position_vectors.z[j]);
+            velocity_vectors.x[i] = weighted(velocity_vectors.x[i], velocity_vectors.x[j]);
+            velocity_vectors.y[i] = weighted(velocity_vectors.y[i], velocity_vectors.y[j]);
+            velocity_vectors.z[i] = weighted(velocity_vectors.z[i], velocity_vectors.z[j]);
+        }
+        mass_vectors[i] = mass;
+        keep[j] = false;
+    }
+
+    compact(mass_vectors, &keep);
+    for vector_array in [position_vectors, velocity_vectors] {
+        compact(&mut vector_array.x, &keep);
+        compact(&mut vector_array.y, &keep);
+        compact(&mut vector_array.z, &keep);
+    }
+}
+
+pub fn bounce_bodies(
+    pairs: &[(usize, usize)],
+    restitution: Float,
+    mass_vectors: &[Float],
+    position_vectors: &VectorArray,
+    velocity_vectors: &mut VectorArray,
+) {
+    // Applies equal and opposite impulses along the line of centres to pairs that are still
+    // approaching, so pairs already separating after an earlier bounce are left alone
+    // normal_speed is their relative speed along the line of centres, negative when approaching
+    for &(i, j) in pairs {
+        let (m_i, m_j) = (mass_vectors[i], mass_vectors[j]);
+        let mass = m_i + m_j;
+        let (x_i, y_i, z_i) = position_vectors.at(i);
+        let (x_j, y_j, z_j) = position_vectors.at(j);
+        let (rx, ry, rz) = (x_j - x_i, y_j - y_i, z_j - z_i);
+        let r = (rx * rx + ry * ry + rz * rz).sqrt();
+        if mass <= 0.0 || r == 0.0 {
+            continue;
+        }
+        let normal = (rx / r, ry / r, rz / r);
+
+        let (vx_i, vy_i, vz_i) = velocity_vectors.at(i);
+        let (vx_j, vy_j, vz_j) = velocity_vectors.at(j);
+        let normal_speed =
+            (vx_j - vx_i) * normal.0 + (vy_j - vy_i) * normal.1 + (vz_j - vz_i) * normal.2;
+        if normal_speed >= 0.0 {
+            continue;
+        }
+
+        let change = (1.0 + restitution) * normal_speed;
+        let k_i = change * m_j / mass;
+        let k_j = change * m_i / mass;
+        velocity_vectors.x[i] += k_i * normal.0;
+        velocity_vectors.y[i] += k_i * normal.1;
+        velocity_vectors.z[i] += k_i * normal.2;
+        velocity_vectors.x[j] -= k_j * normal.0;
+        velocity_vectors.y[j] -= k_j * normal.1;
+        velocity_vectors.z[j] -= k_j * normal.2;
+    }
+}
+
+fn compact(values: &mut Vec<Float>, keep: &[bool]) {
+    let mut index = 0;
+    values.retain(|_| {
+        index += 1;
+        keep[index - 1]
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::collisions::*;
+    use crate::diagnostics::momentum;
+    use crate::initial_conditions::generate_uniform_box;
+
+    fn two_bodies(velocity_a: Float, velocity_b: Float) -> (Vec<Float>, VectorArray, VectorArray) {
+        // Overlapping bodies on the x axis, a to the left of b
+        (
+            vec![3.0, 1.0],
+            VectorArray {
+                x: vec![-0.5, 0.5],
+                y: vec![0.0, 0.0],
+                z: vec![0.0, 0.0],
+            },
+            VectorArray {
+                x: vec![velocity_a, velocity_b],
+                y: vec![1.0, -2.0],
+                z: vec![0.0, 0.0],
+            },
+        )
+    }
+
+    #[test]
+    fn test_find_collisions_matches_all_pairs() {
+        let (mass_vectors, position_vectors, _) = generate_uniform_box(400, 200.0, 200.0, 50.0, 4);
+        let radii = compute_radii(&mass_vectors, 1.0e3);
+
+        let mut expected = Vec::new();
+        for i in 0..mass_vectors.len() {
+            for j in i + 1..mass_vectors.len() {
+                let (x_i, y_i, z_i) = position_vectors.at(i);
+                let (x_j, y_j, z_j) = position_vectors.at(j);
+                let r_sq = (x_j - x_i).powi(2) + (y_j - y_i).powi(2) + (z_j - z_i).powi(2);
+                if r_sq < (radii[i] + radii[j]).powi(2) {
+                    expected.push((i, j));
+                }
+            }
+        }
+        let mut pairs = find_collisions(&position_vectors, &radii);
+        pairs.sort();
+
+        assert!(!expected.is_empty());
+        assert_eq!(pairs, expected);
+    }
+
+    #[test]
+    fn test_merge_conserves_mass_and_momentum() {
+        let (mut mass_vectors, mut position_vectors, mut velocity_vectors) = two_bodies(2.0, -4.0);
+        mass_vectors.push(5.0);
+        position_vectors.push((100.0, 0.0, 0.0));
+        velocity_vectors.push((0.0, 0.0, 1.0));
+        let initial_momentum = momentum(&velocity_vectors, &mass_vectors);
+
+        merge_bodies(
+            &[(0, 1)],
+            &mut mass_vectors,
+            &mut position_vectors,
+            &mut velocity_vectors,
+        );
+
+        // The body after the merged one moves down into its place
+        assert_eq!(mass_vectors, [4.0, 5.0]);
+        assert_eq!(position_vectors.at(0), (-0.25, 0.0, 0.0));
+        assert_eq!(position_vectors.at(1), (100.0, 0.0, 0.0));
+        assert_eq!(velocity_vectors.at(0), (0.5, 0.25, 0.0));
+        assert_eq!(momentum(&velocity_vectors, &mass_vectors), initial_momentum);
+    }
+
+    #[test]
+    fn test_bounce_restitution() {
+        for restitution in [0.0, 0.5, 1.0] {
+            let (mass_vectors, position_vectors, mut velocity_vectors) = two_bodies(2.0, -4.0);
+            let initial_momentum = momentum(&velocity_vectors, &mass_vectors);
+
+            bounce_bodies(
+                &[(0, 1)],
+                restitution,
+                &mass_vectors,
+                &position_vectors,
+                &mut velocity_vectors,
+            );
+
+            // Separation speed along the line of centres is restitution times the approach
+            // speed of 6, and the perpendicular velocities are unchanged
+            let separation_speed = velocity_vectors.x[1] - velocity_vectors.x[0];
+            assert!((separation_speed - 6.0 * restitution).abs() < 1e-5);
+            assert_eq!(velocity_vectors.y, [1.0, -2.0]);
+            let (px, py, _) = momentum(&velocity_vectors, &mass_vectors);
+            assert!((px - initial_momentum.0).abs() < 1e-5);
+            assert_eq!(py, initial_momentum.1);
+
+            // Bodies already separating are left alone
+            let before = velocity_vectors.clone();
+            bounce_bodies(
+                &[(0, 1)],
+                restitution,
+                &mass_vectors,
+                &position_vectors,
+                &mut velocity_vectors,
+            );
+            assert_eq!(velocity_vectors.x, before.x);
+        }
+    }
+}
@@ -0,0 +1,407 @@
+use crate::block_timesteps::ETA;
+use crate::fmm::{valid_order, FMM_ORDER};
+use crate::particle_mesh::{valid_grid, PM_GRID};
+use crate::{Float, DENSITY, EPSILON, H};
+
+pub const USAGE: &str = "Usage: simple-nbody [options]
+
+Options:
+    --headless              Run without a window, writing snapshots to disk
+    --n <bodies>            Number of bodies (default 500)
+    --dimensions <2|3>      Simulate in the plane or in space (default 2)
+    --integrator <name>     euler, leap_frog, rk4, yoshida, hermite or block_hermite
+                            (default leap_frog)
+    --force-method <name>   direct, pairwise, barnes_hut, octree, octree_quadrupole, pm or fmm
+                            (default direct)
+    --theta <angle>         Tree opening angle (default 0.5)
+    --fmm-order <order>     Order of the FMM expansions, from 1 to 30 (default 8)
+    --pm-grid <cells>       Particle mesh cells along each side, a power of two from 8 to 512
+                            (default 64)
+    --threads <count>       Threads to compute forces on, 0 for one per core (default 0)
+    --h <step>              Step size, the longest step for block_hermite (default 0.01)
+    --eta <accuracy>        Timestep accuracy parameter for block_hermite (default 0.02)
+    --softening <kernel>    none, plummer or spline (default plummer)
+    --epsilon <length>      Softening length, the support radius for spline (default 5)
+    --periodic <size>       Side of a periodic box centred on the origin (default 0, open)
+    --post-newtonian <order>
+                            none, 1pn or 2.5pn corrections to the pair force (default none)
+    --speed-of-light <c>    Speed of light for post-Newtonian corrections in the chosen units,
+                            0 for the physical value (default 0)
+    --external <potentials> Background potential, such as nfw:1e8,50+bar:1e4,20,0.5
+                            (default none)
+    --collisions <mode>     none, merge or bounce (default none)
+    --restitution <e>       Fraction of approach speed kept by bouncing bodies (default 0.5)
+    --density <density>     Density radii of colliding bodies follow from (default 1e4)
+    --units <name>          nbody, si, astronomical or galactic (default nbody)
+    --seed <seed>           Seed for initial conditions (default random, printed at start)
+    --initial-conditions <name>
+                            box, plummer, hernquist, toomre or solar_system (default box)
+    --ephemeris <file>      Bodies for solar_system (default data/solar_system.csv)
+    --total-mass <mass>     Total mass of plummer and hernquist models in the chosen units
+                            (default 1e7)
+    --scale-radius <radius> Scale radius of plummer and hernquist models (default 100)
+    --test-particles <count>
+                            Massless tracers on circular orbits in the x-y plane (default 0)
+    --test-radius <radius>  Outer radius of the tracer disk (default 300)
+    --hide-test-particles   Neither draw test particles nor write them to snapshots
+    --steps <count>         Iteration to run to in headless mode (default 1000)
+    --snapshot-every <k>    Write a snapshot every k steps in headless mode (default 100)
+    --output <directory>    Directory for snapshots (default ./snapshots)
+    --checkpoint-every <k>  Write a checkpoint every k steps in headless mode (default 0, never)
+    --restart <file>        Continue from a checkpoint, ignoring initial condition options
+    --help                  Print this message";
+
+// Simulation options, set from command line arguments
+pub struct Config {
+    pub headless: bool,
+    pub n: u32,
+    // 2 or 3, with two dimensional runs confined to the x-y plane
+    pub dimensions: u32,
+    pub integrator: String,
+    pub force_method: String,
+    pub theta: Float,
+    // Power of two from 8 to PM_MAX_GRID, see particle_mesh.rs
+    pub pm_grid: usize,
+    // From 1 to FMM_MAX_ORDER, see fmm.rs
+    pub fmm_order: usize,
+    // 0 for one thread per core
+    pub threads: usize,
+    pub h: Float,
+    pub eta: Float,
+    // Kernel name and length, see softening.rs
+    pub softening: String,
+    pub epsilon: Float,
+    // Side of the periodic box, or 0 for open boundaries
+    pub periodic: Float,
+    // Order name and speed of light, 0 for the physical value, see post_newtonian.rs
+    pub post_newtonian: String,
+    pub speed_of_light: Float,
+    // External potential spec, empty for none, see ExternalPotential::from_spec()
+    pub external: String,
+    // Collision mode name, restitution for bounce, and density in the chosen units,
+    // see collisions.rs
+    pub collisions: String,
+    pub restitution: Float,
+    pub density: Float,
+    // Unit system name, see units.rs
+    pub units: String,
+    // None picks a random seed, which is still recorded so the run can be reproduced
+    pub seed: Option<u64>,
+    pub initial_conditions: String,
+    // csv of bodies for the solar_system initial conditions, see ephemeris.rs
+    pub ephemeris: String,
+    // Spherical models only, in the chosen units, so with G included for nbody units
+    pub total_mass: Float,
+    pub scale_radius: Float,
+    // Massless tracers added on circular orbits, see test_particles.rs
+    pub test_particles: u32,
+    pub test_radius: Float,
+    // Whether test particles are drawn and written to snapshots
+    pub show_test_particles: bool,
+    pub steps: u64,
+    pub snapshot_every: u64,
+    pub output_dir: String,
+    pub checkpoint_every: u64,
+    // Checkpoint to continue from
+    pub restart: Option<String>,
+    // Size of the box bodies are initially placed in, in simulation units
+    pub box_width: Float,
+    pub box_height: Float,
+    // Only used in three dimensions
+    pub box_depth: Float,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            headless: false,
+            n: 500,
+            dimensions: 2,
+            integrator: "leap_frog".to_owned(),
+            force_method: "direct".to_owned(),
+            theta: 0.5,
+            pm_grid: PM_GRID,
+            fmm_order: FMM_ORDER,
+            threads: 0,
+            h: H,
+            eta: ETA,
+            softening: "plummer".to_owned(),
+            epsilon: EPSILON,
+            periodic: 0.0,
+            post_newtonian: "none".to_owned(),
+            speed_of_light: 0.0,
+            external: String::new(),
+            collisions: "none".to_owned(),
+            restitution: 0.5,
+            density: DENSITY,
+            units: "nbody".to_owned(),
+            seed: None,
+            initial_conditions: "box".to_owned(),
+            ephemeris: "data/solar_system.csv".to_owned(),
+            total_mass: 1.0e7,
+            scale_radius: 100.0,
+            test_particles: 0,
+            test_radius: 300.0,
+            show_test_particles: true,
+            steps: 1000,
+            snapshot_every: 100,
+            output_dir: "./snapshots".to_owned(),
+            checkpoint_every: 0,
+            restart: None,
+            box_width: 640.0,
+            box_height: 360.0,
+            box_depth: 360.0,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_args(args: &[String]) -> Result<Config, String> {
+        // args excludes the program name
+        let mut config = Config::default();
+        let mut args = args.iter();
+
+        while let Some(arg) = args.next() {
+            if arg == "--headless" {
+                config.headless = true;
+                continue;
+            }
+            if arg == "--hide-test-particles" {
+                config.show_test_particles = false;
+                continue;
+            }
+            if arg == "--help" {
+                return Err(USAGE.to_owned());
+            }
+
+            let value = args
+                .next()
+                .ok_or(format!("Missing value for {arg}\n\n{USAGE}"));
+            if arg == "--n" {
+                config.n = parse_value(arg, value?)?;
+            } else if arg == "--dimensions" {
+                config.dimensions = parse_value(arg, value?)?;
+                if config.dimensions != 2 && config.dimensions != 3 {
+                    return Err(format!("Invalid value {} for {arg}", config.dimensions));
+                }
+            } else if arg == "--integrator" {
+                config.integrator = value?.to_owned();
+            } else if arg == "--force-method" {
+                config.force_method = value?.to_owned();
+            } else if arg == "--theta" {
+                config.theta = parse_value(arg, value?)?;
+                if config.theta < 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.theta));
+                }
+            } else if arg == "--fmm-order" {
+                config.fmm_order = parse_value(arg, value?)?;
+                if !valid_order(config.fmm_order) {
+                    return Err(format!("Invalid value {} for {arg}", config.fmm_order));
+                }
+            } else if arg == "--pm-grid" {
+                config.pm_grid = parse_value(arg, value?)?;
+                if !valid_grid(config.pm_grid) {
+                    return Err(format!("Invalid value {} for {arg}", config.pm_grid));
+                }
+            } else if arg == "--threads" {
+                config.threads = parse_value(arg, value?)?;
+            } else if arg == "--h" {
+                config.h = parse_value(arg, value?)?;
+                if config.h <= 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.h));
+                }
+            } else if arg == "--eta" {
+                config.eta = parse_value(arg, value?)?;
+                if config.eta <= 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.eta));
+                }
+            } else if arg == "--softening" {
+                config.softening = value?.to_owned();
+            } else if arg == "--epsilon" {
+                config.epsilon = parse_value(arg, value?)?;
+                if config.epsilon < 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.epsilon));
+                }
+            } else if arg == "--periodic" {
+                config.periodic = parse_value(arg, value?)?;
+            } else if arg == "--post-newtonian" {
+                config.post_newtonian = value?.to_owned();
+            } else if arg == "--speed-of-light" {
+                config.speed_of_light = parse_value(arg, value?)?;
+            } else if arg == "--external" {
+                config.external = value?.to_owned();
+            } else if arg == "--collisions" {
+                config.collisions = value?.to_owned();
+            } else if arg == "--restitution" {
+                config.restitution = parse_value(arg, value?)?;
+                if !(0.0..=1.0).contains(&config.restitution) {
+                    return Err(format!("Invalid value {} for {arg}", config.restitution));
+                }
+            } else if arg == "--density" {
+                config.density = parse_value(arg, value?)?;
+                if config.density <= 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.density));
+                }
+            } else if arg == "--units" {
+                config.units = value?.to_owned();
+            } else if arg == "--seed" {
+                config.seed = Some(parse_value(arg, value?)?);
+            } else if arg == "--initial-conditions" {
+                config.initial_conditions = value?.to_owned();
+            } else if arg == "--ephemeris" {
+                config.ephemeris = value?.to_owned();
+            } else if arg == "--total-mass" {
+                config.total_mass = parse_value(arg, value?)?;
+            } else if arg == "--scale-radius" {
+                config.scale_radius = parse_value(arg, value?)?;
+            } else if arg == "--test-particles" {
+                config.test_particles = parse_value(arg, value?)?;
+            } else if arg == "--test-radius" {
+                config.test_radius = parse_value(arg, value?)?;
+                if config.test_radius <= 0.0 {
+                    return Err(format!("Invalid value {} for {arg}", config.test_radius));
+                }
+            } else if arg == "--steps" {
+                config.steps = parse_value(arg, value?)?;
+            } else if arg == "--snapshot-every" {
+                config.snapshot_every = parse_value(arg, value?)?;
+            } else if arg == "--output" {
+                config.output_dir = value?.to_owned();
+            } else if arg == "--checkpoint-every" {
+                config.checkpoint_every = parse_value(arg, value?)?;
+            } else if arg == "--restart" {
+                config.restart = Some(value?.to_owned());
+            } else {
+                return Err(format!("Unknown option {arg}\n\n{USAGE}"));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+fn parse_value<T: std::str::FromStr>(arg: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("Invalid value {value} for {arg}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::Config;
+
+    fn args(list: &[&str]) -> Vec<String> {
+        list.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_from_args() {
+        let config = Config::from_args(&args(&[
+            "--headless",
+            "--n",
+            "2000",
+            "--dimensions",
+            "3",
+            "--integrator",
+            "yoshida",
+            "--softening",
+            "spline",
+            "--threads",
+            "4",
+            "--pm-grid",
+            "32",
+            "--fmm-order",
+            "12",
+            "--eta",
+            "0.01",
+            "--units",
+            "galactic",
+            "--periodic",
+            "400",
+            "--post-newtonian",
+            "2.5pn",
+            "--external",
+            "kepler:1e9+logarithmic:200,10,0.9",
+            "--collisions",
+            "bounce",
+            "--restitution",
+            "0.8",
+            "--seed",
+            "259",
+            "--initial-conditions",
+            "plummer",
+            "--scale-radius",
+            "50",
+            "--test-particles",
+            "1000",
+            "--hide-test-particles",
+            "--steps",
+            "50",
+            "--snapshot-every",
+            "10",
+            "--output",
+            "runs/a",
+            "--restart",
+            "runs/a/checkpoint_00000020.bin",
+        ]))
+        .unwrap();
+
+        assert!(config.headless);
+        assert_eq!(config.n, 2000);
+        assert_eq!(config.dimensions, 3);
+        assert_eq!(config.integrator, "yoshida");
+        assert_eq!(config.force_method, "direct");
+        assert_eq!(config.softening, "spline");
+        assert_eq!(config.epsilon, 5.0);
+        assert_eq!(config.threads, 4);
+        assert_eq!(config.pm_grid, 32);
+        assert_eq!(config.fmm_order, 12);
+        assert_eq!(config.eta, 0.01);
+        assert_eq!(config.units, "galactic");
+        assert_eq!(config.periodic, 400.0);
+        assert_eq!(config.post_newtonian, "2.5pn");
+        assert_eq!(config.speed_of_light, 0.0);
+        assert_eq!(config.external, "kepler:1e9+logarithmic:200,10,0.9");
+        assert_eq!(config.collisions, "bounce");
+        assert_eq!(config.restitution, 0.8);
+        assert_eq!(config.density, 1.0e4);
+        assert_eq!(config.seed, Some(259));
+        assert_eq!(config.initial_conditions, "plummer");
+        assert_eq!(config.scale_radius, 50.0);
+        assert_eq!(config.ephemeris, "data/solar_system.csv");
+        assert_eq!(config.total_mass, 1.0e7);
+        assert_eq!(config.test_particles, 1000);
+        assert_eq!(config.test_radius, 300.0);
+        assert!(!config.show_test_particles);
+        assert_eq!(config.steps, 50);
+        assert_eq!(config.snapshot_every, 10);
+        assert_eq!(config.output_dir, "runs/a");
+        assert_eq!(config.checkpoint_every, 0);
+        assert_eq!(
+            config.restart.as_deref(),
+            Some("runs/a/checkpoint_00000020.bin")
+        );
+    }
+
+    #[test]
+    fn test_from_args_errors() {
+        assert!(Config::from_args(&args(&["--n"])).is_err());
+        assert!(Config::from_args(&args(&["--n", "many"])).is_err());
+        assert!(Config::from_args(&args(&["--restitution", "1.5"])).is_err());
+        assert!(Config::from_args(&args(&["--restitution", "-0.1"])).is_err());
+        assert!(Config::from_args(&args(&["--density", "0"])).is_err());
+        assert!(Config::from_args(&args(&["--epsilon", "-1"])).is_err());
+        assert!(Config::from_args(&args(&["--theta", "-0.5"])).is_err());
+        assert!(Config::from_args(&args(&["--h", "0"])).is_err());
+        assert!(Config::from_args(&args(&["--h", "-0.01"])).is_err());
+        assert!(Config::from_args(&args(&["--eta", "0"])).is_err());
+        assert!(Config::from_args(&args(&["--test-radius", "0"])).is_err());
+        assert!(Config::from_args(&args(&["--dimensions", "4"])).is_err());
+        assert!(Config::from_args(&args(&["--pm-grid", "48"])).is_err());
+        assert!(Config::from_args(&args(&["--fmm-order", "0"])).is_err());
+        assert!(Config::from_args(&args(&["--fast"]))
+            .err()
+            .unwrap()
+            .starts_with("Unknown option --fast"));
+    }
+}
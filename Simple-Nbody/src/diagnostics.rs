@@ -0,0 +1,400 @@
+use crate::periodic::energy_potential_ewald;
+use crate::softening::Softening;
+use crate::{active_count, Float, Simulation, VectorArray};
+
+// Conserved quantities of the system, used to judge integrators
+// mass_vectors already include G, so energies, momenta and angular momenta come out
+// multiplied by G. This has no effect on the relative drifts, which are what is reported
+#[derive(Clone, Debug)]
+pub struct Diagnostics {
+    pub kinetic_energy: Float,
+    pub potential_energy: Float,
+    pub total_energy: Float,
+    pub momentum: (Float, Float, Float),
+    // About the origin. Only the z component is non zero in two dimensions
+    pub angular_momentum: (Float, Float, Float),
+    pub centre_of_mass: (Float, Float, Float),
+}
+
+impl Diagnostics {
+    pub fn new(
+        position_vectors: &VectorArray,
+        velocity_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        softening: Softening,
+    ) -> Diagnostics {
+        let potential_energy = energy_potential(position_vectors, mass_vectors, softening);
+        Diagnostics::with_potential_energy(
+            position_vectors,
+            velocity_vectors,
+            mass_vectors,
+            potential_energy,
+        )
+    }
+
+    pub fn with_potential_energy(
+        position_vectors: &VectorArray,
+        velocity_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        potential_energy: Float,
+    ) -> Diagnostics {
+        // For potentials other than the open boundary sum, such as periodic boundaries
+        let kinetic_energy = energy_kinetic(velocity_vectors, mass_vectors);
+
+        Diagnostics {
+            kinetic_energy,
+            potential_energy,
+            total_energy: kinetic_energy + potential_energy,
+            momentum: momentum(velocity_vectors, mass_vectors),
+            angular_momentum: angular_momentum(position_vectors, velocity_vectors, mass_vectors),
+            centre_of_mass: centre_of_mass(position_vectors, mass_vectors),
+        }
+    }
+
+    pub fn energy_drift(&self, initial: &Diagnostics) -> Float {
+        relative_drift(self.total_energy, initial.total_energy)
+    }
+
+    pub fn momentum_drift(&self, initial: &Diagnostics) -> Float {
+        vector_drift(self.momentum, initial.momentum)
+    }
+
+    pub fn angular_momentum_drift(&self, initial: &Diagnostics) -> Float {
+        vector_drift(self.angular_momentum, initial.angular_momentum)
+    }
+
+    pub fn virial_ratio(&self) -> Float {
+        // 2K / |W|, which is 1 for a system in equilibrium
+        2.0 * self.kinetic_energy / self.potential_energy.abs()
+    }
+
+    pub fn log_header() -> String {
+        "iteration,t,kinetic_energy,potential_energy,total_energy,energy_drift,momentum_drift,angular_momentum_drift".to_owned()
+    }
+
+    pub fn log_line(&self, iteration: u64, t: Float, initial: &Diagnostics) -> String {
+        format!(
+            "{},{},{},{},{},{},{},{}",
+            iteration,
+            t,
+            self.kinetic_energy,
+            self.potential_energy,
+            self.total_energy,
+            self.energy_drift(initial),
+            self.momentum_drift(initial),
+            self.angular_momentum_drift(initial),
+        )
+    }
+}
+
+impl Simulation {
+    pub fn diagnostics(&self) -> Diagnostics {
+        // Potential energy includes the external potential, if there is one
+        let mut potential_energy = match self.box_size {
+            Some(box_size) => energy_potential_ewald(
+                &self.position_vectors,
+                &self.mass_vectors,
+                box_size,
+                self.softening,
+            ),
+            None => energy_potential(&self.position_vectors, &self.mass_vectors, self.softening),
+        };
+        if let Some(external) = &self.external {
+            potential_energy += external.energy(&self.position_vectors, &self.mass_vectors, self.t);
+        }
+
+        Diagnostics::with_potential_energy(
+            &self.position_vectors,
+            &self.velocity_vectors,
+            &self.mass_vectors,
+            potential_energy,
+        )
+    }
+}
+
+fn relative_drift(value: Float, initial: Float) -> Float {
+    // Falls back to the absolute change when the initial value is zero
+    if initial == 0.0 {
+        value
+    } else {
+        (value - initial) / initial.abs()
+    }
+}
+
+fn vector_drift(value: (Float, Float, Float), initial: (Float, Float, Float)) -> Float {
+    // Magnitude of the change in a vector, relative to its initial magnitude
+    // Falls back to the absolute change when the initial magnitude is zero
+    let change = ((value.0 - initial.0).powi(2)
+        + (value.1 - initial.1).powi(2)
+        + (value.2 - initial.2).powi(2))
+    .sqrt();
+    let initial_magnitude = (initial.0.powi(2) + initial.1.powi(2) + initial.2.powi(2)).sqrt();
+
+    if initial_magnitude == 0.0 {
+        change
+    } else {
+        change / initial_magnitude
+    }
+}
+
+pub fn energy_kinetic(velocity_vectors: &VectorArray, mass_vectors: &[Float]) -> Float {
+    let mut kinetic_energy = 0.0;
+    for i in 0..mass_vectors.len() {
+        kinetic_energy += 0.5
+            * mass_vectors[i]
+            * (velocity_vectors.x[i].powi(2)
+                + velocity_vectors.y[i].powi(2)
+                + velocity_vectors.z[i].powi(2));
+    }
+
+    kinetic_energy
+}
+
+pub fn energy_potential(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+) -> Float {
+    // Sum over each pair once, O(n^2) like the direct force sum
+    // Pairs of test particles have no energy, so only pairs with a massive body are summed
+    let mut potential_energy = 0.0;
+    let n = mass_vectors.len();
+    for i in 0..active_count(mass_vectors) {
+        for j in (i + 1)..n {
+            potential_energy += compute_pair_potential(
+                mass_vectors[i],
+                mass_vectors[j],
+                position_vectors.at(i),
+                position_vectors.at(j),
+                softening,
+            );
+        }
+    }
+
+    potential_energy
+}
+
+pub fn compute_pair_potential(
+    m1: Float,
+    m2: Float,
+    position1: (Float, Float, Float),
+    position2: (Float, Float, Float),
+    softening: Softening,
+) -> Float {
+    // Softened potential, whose negative gradient is the force in compute_m_by_r_sq()
+    let rmag_sq = (position2.0 - position1.0).powi(2)
+        + (position2.1 - position1.1).powi(2)
+        + (position2.2 - position1.2).powi(2);
+
+    m1 * m2 * softening.potential(rmag_sq)
+}
+
+pub fn momentum(velocity_vectors: &VectorArray, mass_vectors: &[Float]) -> (Float, Float, Float) {
+    let mut momentum_x = 0.0;
+    let mut momentum_y = 0.0;
+    let mut momentum_z = 0.0;
+    for i in 0..mass_vectors.len() {
+        momentum_x += mass_vectors[i] * velocity_vectors.x[i];
+        momentum_y += mass_vectors[i] * velocity_vectors.y[i];
+        momentum_z += mass_vectors[i] * velocity_vectors.z[i];
+    }
+
+    (momentum_x, momentum_y, momentum_z)
+}
+
+pub fn angular_momentum(
+    position_vectors: &VectorArray,
+    velocity_vectors: &VectorArray,
+    mass_vectors: &[Float],
+) -> (Float, Float, Float) {
+    // About the origin, sum of m r x v. +ve z is counter-clockwise seen from above
+    let mut angular_momentum = (0.0, 0.0, 0.0);
+    for i in 0..mass_vectors.len() {
+        let (x, y, z) = position_vectors.at(i);
+        let (vx, vy, vz) = velocity_vectors.at(i);
+        angular_momentum.0 += mass_vectors[i] * (y * vz - z * vy);
+        angular_momentum.1 += mass_vectors[i] * (z * vx - x * vz);
+        angular_momentum.2 += mass_vectors[i] * (x * vy - y * vx);
+    }
+
+    angular_momentum
+}
+
+pub fn centre_of_mass(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+) -> (Float, Float, Float) {
+    let mut mass = 0.0;
+    let mut com_x = 0.0;
+    let mut com_y = 0.0;
+    let mut com_z = 0.0;
+    for i in 0..mass_vectors.len() {
+        mass += mass_vectors[i];
+        com_x += mass_vectors[i] * position_vectors.x[i];
+        com_y += mass_vectors[i] * position_vectors.y[i];
+        com_z += mass_vectors[i] * position_vectors.z[i];
+    }
+    if mass == 0.0 {
+        return (0.0, 0.0, 0.0);
+    }
+
+    (com_x / mass, com_y / mass, com_z / mass)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::*;
+    use crate::{compute_m_by_r_sq, Simulation, VectorArray, EPSILON};
+
+    const FLOAT_TOLERANCE: Float = 0.000_1;
+
+    fn two_bodies() -> (VectorArray, VectorArray, Vec<Float>) {
+        let position_vectors = VectorArray {
+            x: vec![0.0, 30.0],
+            y: vec![0.0, 40.0],
+            z: vec![0.0, 0.0],
+        };
+        let velocity_vectors = VectorArray {
+            x: vec![1.0, -2.0],
+            y: vec![0.0, 1.0],
+            z: vec![0.0, 0.0],
+        };
+        let mass_vectors = vec![2.0, 3.0];
+
+        (position_vectors, velocity_vectors, mass_vectors)
+    }
+
+    #[test]
+    fn test_energy_kinetic() {
+        let (_, velocity_vectors, mass_vectors) = two_bodies();
+
+        assert!((energy_kinetic(&velocity_vectors, &mass_vectors) - 8.5).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_energy_potential() {
+        let (position_vectors, _, mass_vectors) = two_bodies();
+        let expected = -6.0 / (2500.0 + EPSILON.powi(2)).sqrt();
+
+        assert!(
+            (energy_potential(&position_vectors, &mass_vectors, Softening::default()) - expected)
+                .abs()
+                < FLOAT_TOLERANCE
+        );
+    }
+
+    #[test]
+    fn test_pair_potential_gradient() {
+        // Force from compute_m_by_r_sq() should be minus the gradient of the potential
+        // The bodies are 11 apart, inside the support of the first spline kernel
+        let dx = 0.01;
+        for softening in [
+            Softening::None,
+            Softening::default(),
+            Softening::Spline(20.0),
+            Softening::Spline(5.0),
+        ] {
+            let other = (2.0, -1.0, 4.0);
+            let potential = |position| compute_pair_potential(1.0, 3.0, position, other, softening);
+            let force = compute_m_by_r_sq(3.0, (10.0, 5.0, 0.0), other, softening);
+            let gradient_x =
+                (potential((10.0 + dx, 5.0, 0.0)) - potential((10.0 - dx, 5.0, 0.0))) / (2.0 * dx);
+            let gradient_y =
+                (potential((10.0, 5.0 + dx, 0.0)) - potential((10.0, 5.0 - dx, 0.0))) / (2.0 * dx);
+            let gradient_z =
+                (potential((10.0, 5.0, dx)) - potential((10.0, 5.0, -dx))) / (2.0 * dx);
+
+            assert!((force.0 + gradient_x).abs() < 0.01 * force.0.abs());
+            assert!((force.1 + gradient_y).abs() < 0.01 * force.1.abs());
+            assert!((force.2 + gradient_z).abs() < 0.01 * force.2.abs());
+        }
+    }
+
+    #[test]
+    fn test_momentum() {
+        let (_, velocity_vectors, mass_vectors) = two_bodies();
+        let (momentum_x, momentum_y, _) = momentum(&velocity_vectors, &mass_vectors);
+
+        assert!((momentum_x - -4.0).abs() < FLOAT_TOLERANCE);
+        assert!((momentum_y - 3.0).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_angular_momentum() {
+        let (position_vectors, velocity_vectors, mass_vectors) = two_bodies();
+        // Only the second body has a lever arm: 3 * (30 * 1 - 40 * -2)
+        let expected = 330.0;
+        let (l_x, l_y, l_z) = angular_momentum(&position_vectors, &velocity_vectors, &mass_vectors);
+
+        assert!((l_z - expected).abs() < FLOAT_TOLERANCE);
+        assert_eq!((l_x, l_y), (0.0, 0.0));
+
+        // Lifting the second body out of the plane adds components along x and y,
+        // 3 * (-10 * 1, 10 * -2) from z = 10, then 3 * (40, -30) * 0.5 from vz = 0.5
+        let mut position_vectors = position_vectors;
+        let mut velocity_vectors = velocity_vectors;
+        position_vectors.z[1] = 10.0;
+        let (l_x, l_y, _) = angular_momentum(&position_vectors, &velocity_vectors, &mass_vectors);
+        assert!((l_x - -30.0).abs() < FLOAT_TOLERANCE);
+        assert!((l_y - -60.0).abs() < FLOAT_TOLERANCE);
+        velocity_vectors.z[1] = 0.5;
+        let (l_x, l_y, _) = angular_momentum(&position_vectors, &velocity_vectors, &mass_vectors);
+        assert!((l_x - (-30.0 + 3.0 * 40.0 * 0.5)).abs() < FLOAT_TOLERANCE);
+        assert!((l_y - (-60.0 - 3.0 * 30.0 * 0.5)).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_centre_of_mass() {
+        let (position_vectors, _, mass_vectors) = two_bodies();
+        let (com_x, com_y, _) = centre_of_mass(&position_vectors, &mass_vectors);
+
+        assert!((com_x - 18.0).abs() < FLOAT_TOLERANCE);
+        assert!((com_y - 24.0).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_conservation_leap_frog() {
+        // Direct forces are equal and opposite, so momentum is conserved to round-off
+        // Leapfrog conserves angular momentum exactly for central forces
+        // With the wide spline kernel, the bodies stay within its support
+        for softening in [Softening::default(), Softening::Spline(100.0)] {
+            let (position_vectors, velocity_vectors, mass_vectors) = two_bodies();
+            let mut simulation = Simulation::new(
+                mass_vectors.iter().map(|m| m * 1000.0).collect(),
+                position_vectors,
+                velocity_vectors,
+                "direct",
+                0.0,
+                "leap_frog",
+                softening,
+            );
+            let initial = simulation.diagnostics();
+            for _i in 0..1000 {
+                simulation.update();
+            }
+            let diagnostics = simulation.diagnostics();
+
+            assert!(diagnostics.energy_drift(&initial).abs() < 0.001);
+            assert!(diagnostics.momentum_drift(&initial) < 0.001);
+            assert!(diagnostics.angular_momentum_drift(&initial).abs() < 0.001);
+        }
+    }
+
+    #[test]
+    fn test_log_line() {
+        let (position_vectors, velocity_vectors, mass_vectors) = two_bodies();
+        let diagnostics = Diagnostics::new(
+            &position_vectors,
+            &velocity_vectors,
+            &mass_vectors,
+            Softening::default(),
+        );
+        let line = diagnostics.log_line(3, 0.03, &diagnostics);
+
+        assert_eq!(
+            line.split(',').count(),
+            Diagnostics::log_header().split(',').count()
+        );
+        assert!(line.ends_with(",0,0,0"));
+    }
+}
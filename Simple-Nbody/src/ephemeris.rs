@@ -0,0 +1,174 @@
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+
+use crate::units::{UnitSystem, ASTRONOMICAL_UNIT, DAY};
+use crate::{Float, VectorArray};
+
+// Ephemerides are csv files of named bodies at a single epoch, with lines starting with '#'
+// as comments, and a column header as written by JPL Horizons for vector tables:
+//
+// # Positions in au, velocities in au/day and masses in kg
+// name,mass,x,y,z,vx,vy,vz
+// Sun,1.9884e30,0,0,0,0,0,0
+// Earth,6.0458e24,-0.1772,0.9672,0,-0.0172,-0.00316,0
+// ...
+//
+// data/solar_system.csv holds the Sun and planets at J2000
+
+// Units ephemeris files are written in
+pub const EPHEMERIS_UNITS: UnitSystem = UnitSystem {
+    name: "ephemeris",
+    length: ASTRONOMICAL_UNIT,
+    time: DAY,
+    mass: 1.0,
+};
+
+pub struct Ephemeris {
+    pub names: Vec<String>,
+    // In the simulation's units, with G multiplied into the masses as for mass_vectors
+    pub mass_vectors: Vec<Float>,
+    pub position_vectors: VectorArray,
+    pub velocity_vectors: VectorArray,
+}
+
+impl Ephemeris {
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|body| body == name)
+    }
+}
+
+pub fn read_ephemeris(path: &Path, units: &UnitSystem) -> io::Result<Ephemeris> {
+    // Values are converted in double precision, and only rounded to Float at the end
+    let file = BufReader::new(File::open(path)?);
+    let mut ephemeris = Ephemeris {
+        names: Vec::new(),
+        mass_vectors: Vec::new(),
+        position_vectors: VectorArray::default(),
+        velocity_vectors: VectorArray::default(),
+    };
+    let length = |value: f64| EPHEMERIS_UNITS.convert_length(value, units) as Float;
+    let velocity = |value: f64| EPHEMERIS_UNITS.convert_velocity(value, units) as Float;
+
+    for line in file.lines() {
+        let line = line?;
+        if line.starts_with('#') || line.starts_with("name") || line.trim().is_empty() {
+            continue;
+        }
+
+        let mut columns = line.split(',');
+        let name = columns.next().unwrap_or("").trim().to_owned();
+        let values: Vec<f64> = columns
+            .map(|value| value.trim().parse().map_err(invalid_data))
+            .collect::<io::Result<Vec<f64>>>()?;
+        if values.len() != 7 {
+            return Err(invalid_data(format!("Expected 8 columns in row: {line}")));
+        }
+
+        let mass = EPHEMERIS_UNITS.convert_mass(values[0], units);
+        ephemeris.names.push(name);
+        ephemeris
+            .mass_vectors
+            .push((units.gravitational_constant() * mass) as Float);
+        ephemeris
+            .position_vectors
+            .push((length(values[1]), length(values[2]), length(values[3])));
+        ephemeris.velocity_vectors.push((
+            velocity(values[4]),
+            velocity(values[5]),
+            velocity(values[6]),
+        ));
+    }
+
+    Ok(ephemeris)
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::ephemeris::*;
+    use crate::scenario::solar_system;
+    use crate::softening::Softening;
+    use crate::{float_consts, Simulation};
+
+    fn solar_system_path() -> &'static Path {
+        Path::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/data/solar_system.csv"
+        ))
+    }
+
+    #[test]
+    fn test_read_ephemeris() {
+        let ephemeris = read_ephemeris(solar_system_path(), &UnitSystem::ASTRONOMICAL).unwrap();
+
+        assert_eq!(ephemeris.names.len(), 9);
+        assert_eq!(ephemeris.index_of("Sun"), Some(0));
+        // G times a solar mass is 4 pi^2 in astronomical units
+        let four_pi_sq = 4.0 * float_consts::PI * float_consts::PI;
+        assert!((ephemeris.mass_vectors[0] / four_pi_sq - 1.0).abs() < 1e-3);
+        // Earth is about 1 au from the Sun, moving at about 2 pi au/yr
+        let earth = ephemeris.index_of("Earth").unwrap();
+        let (x, y, z) = ephemeris.position_vectors.at(earth);
+        let (vx, vy, vz) = ephemeris.velocity_vectors.at(earth);
+        assert!(((x * x + y * y + z * z).sqrt() - 0.983).abs() < 1e-3);
+        let speed = (vx * vx + vy * vy + vz * vz).sqrt();
+        assert!((speed / (2.0 * float_consts::PI) - 1.017).abs() < 1e-2);
+    }
+
+    #[test]
+    fn test_earth_orbital_period() {
+        // Integrates the Sun and planets with a symplectic integrator, timing Earth's first
+        // full turn around the Sun, which should take a sidereal year of 1.00002 Julian years
+        let ephemeris = read_ephemeris(solar_system_path(), &UnitSystem::ASTRONOMICAL).unwrap();
+        let sun = ephemeris.index_of("Sun").unwrap();
+        let earth = ephemeris.index_of("Earth").unwrap();
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            solar_system(solar_system_path(), &UnitSystem::ASTRONOMICAL).unwrap();
+        let mut simulation = Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            "direct",
+            0.0,
+            "yoshida",
+            Softening::None,
+        );
+        simulation.h = 0.001;
+
+        let angle = |simulation: &Simulation| {
+            let (x_sun, y_sun, _) = simulation.position_vectors.at(sun);
+            let (x, y, _) = simulation.position_vectors.at(earth);
+            (y - y_sun).atan2(x - x_sun)
+        };
+        // Angle swept since the start, unwrapped across the branch cut of atan2
+        let mut swept = 0.0;
+        let mut previous = angle(&simulation);
+        let mut period = None;
+        while simulation.t < 1.2 {
+            simulation.update();
+            let current = angle(&simulation);
+            let mut step = current - previous;
+            if step < -float_consts::PI {
+                step += 2.0 * float_consts::PI;
+            }
+            previous = current;
+
+            if swept + step >= 2.0 * float_consts::PI {
+                // Interpolates within the step for the time the turn completed
+                let fraction = (2.0 * float_consts::PI - swept) / step;
+                period = Some(simulation.t - simulation.h + fraction * simulation.h);
+                break;
+            }
+            swept += step;
+        }
+
+        let period = period.expect("Earth did not complete an orbit");
+        assert!((period - 1.00002).abs() < 1e-4, "period {period}");
+    }
+}
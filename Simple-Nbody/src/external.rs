@@ -0,0 +1,491 @@
+use std::ops::Add;
+
+use crate::units::UnitSystem;
+use crate::{Float, VectorArray};
+
+// Fixed background potentials, whose accelerations are added to the bodies' own gravity and
+// whose potential energies are added to the diagnostics. Centred on the origin, with masses
+// in simulation units, so with G multiplied in as for mass_vectors
+//
+// Kepler: a point mass, phi = -M / r
+// Logarithmic: a halo with a flat rotation curve of speed v0 beyond the core radius Rc,
+//        phi = v0^2 / 2 ln(Rc^2 + x^2 + y^2 + z^2 / q^2), flattened in z by q
+//        (Binney & Tremaine 2008, eq. 2.71)
+// Nfw: the dark matter halo of Navarro, Frenk & White (1996) with scale radius rs,
+//        phi = -M ln(1 + r / rs) / r, where M = 4 pi rho0 rs^3
+// MiyamotoNagai: a disk of scale length a and scale height b,
+//        phi = -M / sqrt(x^2 + y^2 + (a + sqrt(z^2 + b^2))^2) (Miyamoto & Nagai 1975)
+// Bar: a quadrupole rotating about z with a pattern speed, its long axis along x at t = 0,
+//        phi = -A Rb^3 (x'^2 - y'^2) / (Rb^2 + r^2)^(5/2) in the bar's frame, harmonic
+//        inside the bar radius Rb and falling off as a quadrupole outside it. Energy is not
+//        conserved in a rotating potential, but the Jacobi integral E - pattern_speed L_z is
+// Sum: the total of several potentials
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExternalPotential {
+    Kepler {
+        mass: Float,
+    },
+    Logarithmic {
+        velocity: Float,
+        core_radius: Float,
+        flattening: Float,
+    },
+    Nfw {
+        mass: Float,
+        scale_radius: Float,
+    },
+    MiyamotoNagai {
+        mass: Float,
+        scale_length: Float,
+        scale_height: Float,
+    },
+    Bar {
+        strength: Float,
+        radius: Float,
+        pattern_speed: Float,
+    },
+    Sum(Vec<ExternalPotential>),
+}
+
+impl ExternalPotential {
+    pub fn from_spec(spec: &str, units: &UnitSystem) -> Result<ExternalPotential, String> {
+        // Potentials as given on the command line, name:parameter,parameter,... joined by +
+        // Masses are converted from the given units, and other parameters are in them as is
+        //   kepler:mass
+        //   logarithmic:velocity,core_radius,flattening
+        //   nfw:mass,scale_radius
+        //   miyamoto_nagai:mass,scale_length,scale_height
+        //   bar:strength,radius,pattern_speed
+        let mut terms = Vec::new();
+        for term in spec.split('+') {
+            let (name, parameters) = term.split_once(':').unwrap_or((term, ""));
+            let values = parameters
+                .split(',')
+                .filter(|value| !value.is_empty())
+                .map(|value| {
+                    value
+                        .trim()
+                        .parse::<Float>()
+                        .map_err(|_| format!("Invalid parameter {value} for {name}"))
+                })
+                .collect::<Result<Vec<Float>, String>>()?;
+            let expect = |count: usize| {
+                if values.len() == count {
+                    Ok(())
+                } else {
+                    Err(format!(
+                        "Potential {name} takes {count} parameters, not {}",
+                        values.len()
+                    ))
+                }
+            };
+
+            let potential = match name.trim() {
+                "kepler" => {
+                    expect(1)?;
+                    ExternalPotential::Kepler {
+                        mass: units.simulation_mass(values[0]),
+                    }
+                }
+                "logarithmic" => {
+                    expect(3)?;
+                    ExternalPotential::Logarithmic {
+                        velocity: values[0],
+                        core_radius: values[1],
+                        flattening: values[2],
+                    }
+                }
+                "nfw" => {
+                    expect(2)?;
+                    ExternalPotential::Nfw {
+                        mass: units.simulation_mass(values[0]),
+                        scale_radius: values[1],
+                    }
+                }
+                "miyamoto_nagai" => {
+                    expect(3)?;
+                    ExternalPotential::MiyamotoNagai {
+                        mass: units.simulation_mass(values[0]),
+                        scale_length: values[1],
+                        scale_height: values[2],
+                    }
+                }
+                "bar" => {
+                    expect(3)?;
+                    ExternalPotential::Bar {
+                        strength: values[0],
+                        radius: values[1],
+                        pattern_speed: values[2],
+                    }
+                }
+                other => return Err(format!("Invalid external potential {other}")),
+            };
+            terms.push(potential);
+        }
+
+        if terms.len() == 1 {
+            Ok(terms.remove(0))
+        } else {
+            Ok(ExternalPotential::Sum(terms))
+        }
+    }
+
+    pub fn spec(&self) -> String {
+        // The inverse of from_spec() in nbody units, with masses as stored, for checkpoints
+        match self {
+            ExternalPotential::Kepler { mass } => format!("kepler:{mass}"),
+            ExternalPotential::Logarithmic {
+                velocity,
+                core_radius,
+                flattening,
+            } => format!("logarithmic:{velocity},{core_radius},{flattening}"),
+            ExternalPotential::Nfw { mass, scale_radius } => format!("nfw:{mass},{scale_radius}"),
+            ExternalPotential::MiyamotoNagai {
+                mass,
+                scale_length,
+                scale_height,
+            } => format!("miyamoto_nagai:{mass},{scale_length},{scale_height}"),
+            ExternalPotential::Bar {
+                strength,
+                radius,
+                pattern_speed,
+            } => format!("bar:{strength},{radius},{pattern_speed}"),
+            ExternalPotential::Sum(terms) => terms
+                .iter()
+                .map(|term| term.spec())
+                .collect::<Vec<String>>()
+                .join("+"),
+        }
+    }
+
+    pub fn potential_at(&self, position: (Float, Float, Float), t: Float) -> Float {
+        let (x, y, z) = position;
+        let r_sq = x * x + y * y + z * z;
+        match *self {
+            ExternalPotential::Kepler { mass } => -mass / r_sq.sqrt(),
+            ExternalPotential::Logarithmic {
+                velocity,
+                core_radius,
+                flattening,
+            } => {
+                let d = core_radius.powi(2) + x * x + y * y + (z / flattening).powi(2);
+                0.5 * velocity * velocity * d.ln()
+            }
+            ExternalPotential::Nfw { mass, scale_radius } => {
+                let r = r_sq.sqrt();
+                if r == 0.0 {
+                    -mass / scale_radius
+                } else {
+                    -mass * (r / scale_radius).ln_1p() / r
+                }
+            }
+            ExternalPotential::MiyamotoNagai {
+                mass,
+                scale_length,
+                scale_height,
+            } => {
+                let b = (z * z + scale_height * scale_height).sqrt();
+                -mass / (x * x + y * y + (scale_length + b).powi(2)).sqrt()
+            }
+            ExternalPotential::Bar {
+                strength,
+                radius,
+                pattern_speed,
+            } => {
+                let (x_bar, y_bar) = rotate((x, y), -pattern_speed * t);
+                let s = radius * radius + r_sq;
+                -strength * radius.powi(3) * (x_bar * x_bar - y_bar * y_bar) / s.powf(2.5)
+            }
+            ExternalPotential::Sum(ref terms) => terms
+                .iter()
+                .map(|term| term.potential_at(position, t))
+                .sum(),
+        }
+    }
+
+    pub fn acc_at(&self, position: (Float, Float, Float), t: Float) -> (Float, Float, Float) {
+        // Acceleration, the negative gradient of potential_at()
+        let (x, y, z) = position;
+        let r_sq = x * x + y * y + z * z;
+        match *self {
+            ExternalPotential::Kepler { mass } => {
+                let a = -mass / r_sq.powf(1.5);
+                (a * x, a * y, a * z)
+            }
+            ExternalPotential::Logarithmic {
+                velocity,
+                core_radius,
+                flattening,
+            } => {
+                let q_sq = flattening * flattening;
+                let d = core_radius.powi(2) + x * x + y * y + z * z / q_sq;
+                let a = -velocity * velocity / d;
+                (a * x, a * y, a * z / q_sq)
+            }
+            ExternalPotential::Nfw { mass, scale_radius } => {
+                // Mass enclosed within r, over r^3
+                let r = r_sq.sqrt();
+                if r == 0.0 {
+                    return (0.0, 0.0, 0.0);
+                }
+                let u = r / scale_radius;
+                let a = -mass * (u.ln_1p() - u / (1.0 + u)) / (r * r_sq);
+                (a * x, a * y, a * z)
+            }
+            ExternalPotential::MiyamotoNagai {
+                mass,
+                scale_length,
+                scale_height,
+            } => {
+                let b = (z * z + scale_height * scale_height).sqrt();
+                let d_cubed = (x * x + y * y + (scale_length + b).powi(2)).powf(1.5);
+                let a = -mass / d_cubed;
+                (a * x, a * y, a * z * (scale_length + b) / b)
+            }
+            ExternalPotential::Bar {
+                strength,
+                radius,
+                pattern_speed,
+            } => {
+                // Gradient in the bar's frame, rotated back
+                let angle = pattern_speed * t;
+                let (x_bar, y_bar) = rotate((x, y), -angle);
+                let s = radius * radius + r_sq;
+                let q = x_bar * x_bar - y_bar * y_bar;
+                let k = strength * radius.powi(3) / s.powf(2.5);
+                let radial = 5.0 * q / s;
+                let ax_bar = k * (2.0 * x_bar - radial * x_bar);
+                let ay_bar = k * (-2.0 * y_bar - radial * y_bar);
+                let (ax, ay) = rotate((ax_bar, ay_bar), angle);
+                (ax, ay, -k * radial * z)
+            }
+            ExternalPotential::Sum(ref terms) => terms.iter().fold((0.0, 0.0, 0.0), |acc, term| {
+                let term_acc = term.acc_at(position, t);
+                (acc.0 + term_acc.0, acc.1 + term_acc.1, acc.2 + term_acc.2)
+            }),
+        }
+    }
+
+    pub fn add_acc_vectors(
+        &self,
+        acc_vectors: &mut VectorArray,
+        position_vectors: &VectorArray,
+        t: Float,
+    ) {
+        for i in 0..position_vectors.x.len() {
+            let (ax, ay, az) = self.acc_at(position_vectors.at(i), t);
+            acc_vectors.x[i] += ax;
+            acc_vectors.y[i] += ay;
+            acc_vectors.z[i] += az;
+        }
+    }
+
+    pub fn energy(
+        &self,
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        t: Float,
+    ) -> Float {
+        // Potential energy of the bodies in the background, multiplied by G as their masses are
+        (0..mass_vectors.len())
+            .map(|i| mass_vectors[i] * self.potential_at(position_vectors.at(i), t))
+            .sum()
+    }
+}
+
+impl Add for ExternalPotential {
+    type Output = ExternalPotential;
+
+    fn add(self, other: ExternalPotential) -> ExternalPotential {
+        // Sums are kept flat, so a sum of sums lists every term once
+        let mut terms = Vec::new();
+        for potential in [self, other] {
+            match potential {
+                ExternalPotential::Sum(inner) => terms.extend(inner),
+                potential => terms.push(potential),
+            }
+        }
+
+        ExternalPotential::Sum(terms)
+    }
+}
+
+fn rotate(vector: (Float, Float), angle: Float) -> (Float, Float) {
+    // Rotates anticlockwise about z by angle
+    let (sin, cos) = angle.sin_cos();
+    (
+        vector.0 * cos - vector.1 * sin,
+        vector.0 * sin + vector.1 * cos,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::angular_momentum;
+    use crate::external::*;
+    use crate::softening::Softening;
+    use crate::Simulation;
+
+    fn all_potentials() -> Vec<ExternalPotential> {
+        vec![
+            ExternalPotential::Kepler { mass: 1.0e6 },
+            ExternalPotential::Logarithmic {
+                velocity: 200.0,
+                core_radius: 10.0,
+                flattening: 0.8,
+            },
+            ExternalPotential::Nfw {
+                mass: 5.0e7,
+                scale_radius: 50.0,
+            },
+            ExternalPotential::MiyamotoNagai {
+                mass: 2.0e7,
+                scale_length: 30.0,
+                scale_height: 3.0,
+            },
+            ExternalPotential::Bar {
+                strength: 3000.0,
+                radius: 20.0,
+                pattern_speed: 0.5,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_acceleration_is_potential_gradient() {
+        let mut potentials = all_potentials();
+        potentials.push(all_potentials().into_iter().reduce(|a, b| a + b).unwrap());
+        let t = 1.3;
+        // Large enough for single precision round-off in the potential to stay small
+        let step = 0.05;
+
+        for potential in &potentials {
+            for position in [(15.0, -8.0, 4.0), (-40.0, 25.0, -12.0), (3.0, 60.0, 0.5)] {
+                let acc = potential.acc_at(position, t);
+                let (x, y, z) = position;
+                let derivative = |plus: (Float, Float, Float), minus: (Float, Float, Float)| {
+                    (potential.potential_at(plus, t) - potential.potential_at(minus, t))
+                        / (2.0 * step)
+                };
+                let expected = (
+                    -derivative((x + step, y, z), (x - step, y, z)),
+                    -derivative((x, y + step, z), (x, y - step, z)),
+                    -derivative((x, y, z + step), (x, y, z - step)),
+                );
+                let size = (acc.0.powi(2) + acc.1.powi(2) + acc.2.powi(2)).sqrt();
+                for (value, expected) in [
+                    (acc.0, expected.0),
+                    (acc.1, expected.1),
+                    (acc.2, expected.2),
+                ] {
+                    assert!(
+                        (value - expected).abs() < 2.0e-3 * size,
+                        "{potential:?} at {position:?}: {acc:?} against {expected:?}"
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_spec_round_trip() {
+        let potential = all_potentials().into_iter().reduce(|a, b| a + b).unwrap();
+        let parsed = ExternalPotential::from_spec(&potential.spec(), &UnitSystem::NBODY).unwrap();
+        assert_eq!(parsed, potential);
+
+        let parsed =
+            ExternalPotential::from_spec("kepler:1+logarithmic:1,2,3", &UnitSystem::ASTRONOMICAL)
+                .unwrap();
+        let ExternalPotential::Sum(terms) = parsed else {
+            panic!("Expected a sum, got {parsed:?}");
+        };
+        // G may be folded differently at each call site, so converted masses are only close
+        let ExternalPotential::Kepler { mass } = terms[0] else {
+            panic!("Expected a Kepler potential, got {:?}", terms[0]);
+        };
+        let expected = UnitSystem::ASTRONOMICAL.simulation_mass(1.0);
+        assert!((mass / expected - 1.0).abs() < 1e-6);
+        assert!(ExternalPotential::from_spec("kepler", &UnitSystem::NBODY).is_err());
+        assert!(ExternalPotential::from_spec("kepler:1,2", &UnitSystem::NBODY).is_err());
+        assert!(ExternalPotential::from_spec("plummer:1", &UnitSystem::NBODY).is_err());
+    }
+
+    fn test_particle(external: ExternalPotential, integrator: &str) -> Simulation {
+        // A single body, feeling nothing but the background, on a slightly eccentric orbit
+        let mut simulation = Simulation::new(
+            vec![1.0],
+            VectorArray {
+                x: vec![40.0],
+                y: vec![0.0],
+                z: vec![5.0],
+            },
+            VectorArray {
+                x: vec![0.0],
+                y: vec![150.0],
+                z: vec![10.0],
+            },
+            "direct",
+            0.0,
+            integrator,
+            Softening::None,
+        );
+        simulation.h = 0.002;
+        simulation.external = Some(external);
+        simulation.acc_vectors = simulation.compute_acc_vectors();
+
+        simulation
+    }
+
+    #[test]
+    fn test_energy_conserved_in_static_potential() {
+        let external = ExternalPotential::Logarithmic {
+            velocity: 150.0,
+            core_radius: 5.0,
+            flattening: 0.9,
+        } + ExternalPotential::MiyamotoNagai {
+            mass: 1.0e6,
+            scale_length: 20.0,
+            scale_height: 2.0,
+        };
+        let mut simulation = test_particle(external, "yoshida");
+        let initial = simulation.diagnostics();
+        assert!(initial.potential_energy != 0.0);
+        for _i in 0..2000 {
+            simulation.update();
+        }
+
+        assert!(simulation.diagnostics().energy_drift(&initial).abs() < 1e-4);
+    }
+
+    #[test]
+    fn test_jacobi_integral_conserved_in_rotating_bar() {
+        // Energy changes as the bar turns, while E - pattern_speed L_z stays constant
+        let pattern_speed = 2.0;
+        let external = ExternalPotential::Kepler { mass: 1.0e6 }
+            + ExternalPotential::Bar {
+                strength: 20000.0,
+                radius: 30.0,
+                pattern_speed,
+            };
+        let mut simulation = test_particle(external, "rk4");
+        let jacobi = |simulation: &Simulation| {
+            let l_z = angular_momentum(
+                &simulation.position_vectors,
+                &simulation.velocity_vectors,
+                &simulation.mass_vectors,
+            )
+            .2;
+            simulation.diagnostics().total_energy - pattern_speed * l_z
+        };
+        let initial_energy = simulation.diagnostics().total_energy;
+        let initial_jacobi = jacobi(&simulation);
+        for _i in 0..2000 {
+            simulation.update();
+        }
+
+        let energy_change = simulation.diagnostics().total_energy - initial_energy;
+        let jacobi_change = jacobi(&simulation) - initial_jacobi;
+        assert!(energy_change.abs() > 1e-3 * initial_energy.abs());
+        assert!(jacobi_change.abs() < 1e-5 * initial_jacobi.abs());
+    }
+}
@@ -0,0 +1,156 @@
+// Fast Fourier transforms of complex data, as (real, imaginary) pairs in double precision
+// Lengths must be powers of two. The forward transform is unnormalised, and the inverse
+// divides by the length, so the inverse undoes the forward transform
+// Source: Cooley & Tukey (1965), iterative radix-2 decimation in time
+
+pub type Complex = (f64, f64);
+
+pub fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    assert!(n.is_power_of_two(), "FFT length {n} is not a power of two");
+
+    // Bit reversal permutation, so the butterflies below can work in place
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut length = 2;
+    while length <= n {
+        let angle = sign * 2.0 * std::f64::consts::PI / length as f64;
+        let (sin, cos) = angle.sin_cos();
+        for start in (0..n).step_by(length) {
+            let mut twiddle: Complex = (1.0, 0.0);
+            for k in 0..length / 2 {
+                let a = data[start + k];
+                let b = multiply(data[start + k + length / 2], twiddle);
+                data[start + k] = (a.0 + b.0, a.1 + b.1);
+                data[start + k + length / 2] = (a.0 - b.0, a.1 - b.1);
+                twiddle = multiply(twiddle, (cos, sin));
+            }
+        }
+        length <<= 1;
+    }
+
+    if inverse {
+        for value in data.iter_mut() {
+            *value = (value.0 / n as f64, value.1 / n as f64);
+        }
+    }
+}
+
+pub fn fft_grid(data: &mut [Complex], n: usize, axes: u32, inverse: bool) {
+    // Transform of an n^axes grid stored with the last index fastest, one axis at a time
+    let points = n.pow(axes);
+    assert_eq!(data.len(), points);
+    let mut line = vec![(0.0, 0.0); n];
+    for stride in (0..axes).map(|axis| n.pow(axis)) {
+        for start in 0..points {
+            // Each line along this axis starts where the index along it is 0
+            if (start / stride) % n != 0 {
+                continue;
+            }
+            for (k, value) in line.iter_mut().enumerate() {
+                *value = data[start + k * stride];
+            }
+            fft(&mut line, inverse);
+            for (k, value) in line.iter().enumerate() {
+                data[start + k * stride] = *value;
+            }
+        }
+    }
+}
+
+pub fn multiply(a: Complex, b: Complex) -> Complex {
+    (a.0 * b.0 - a.1 * b.1, a.0 * b.1 + a.1 * b.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fft::*;
+
+    fn dft(data: &[Complex]) -> Vec<Complex> {
+        // Transform straight from the definition, in O(n^2)
+        let n = data.len();
+        (0..n)
+            .map(|k| {
+                let mut sum = (0.0, 0.0);
+                for (j, value) in data.iter().enumerate() {
+                    let angle = -2.0 * std::f64::consts::PI * (j * k) as f64 / n as f64;
+                    let term = multiply(*value, (angle.cos(), angle.sin()));
+                    sum = (sum.0 + term.0, sum.1 + term.1);
+                }
+                sum
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_fft_matches_dft() {
+        for n in [1, 2, 8, 64] {
+            let data: Vec<Complex> = (0..n)
+                .map(|i| ((i as f64 * 0.7).sin(), (i as f64 * 1.3).cos() - 0.5))
+                .collect();
+            let expected = dft(&data);
+            let mut transformed = data.clone();
+            fft(&mut transformed, false);
+            for k in 0..n {
+                assert!((transformed[k].0 - expected[k].0).abs() < 1e-9);
+                assert!((transformed[k].1 - expected[k].1).abs() < 1e-9);
+            }
+
+            fft(&mut transformed, true);
+            for k in 0..n {
+                assert!((transformed[k].0 - data[k].0).abs() < 1e-12);
+                assert!((transformed[k].1 - data[k].1).abs() < 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fft_grid_of_plane_wave() {
+        // A plane wave with wave vector (1, 2, 3) transforms to a single spike
+        let n = 8;
+        let mut data = vec![(0.0, 0.0); n * n * n];
+        for i in 0..n {
+            for j in 0..n {
+                for k in 0..n {
+                    let angle = 2.0 * std::f64::consts::PI * (i + 2 * j + 3 * k) as f64 / n as f64;
+                    data[(i * n + j) * n + k] = (angle.cos(), angle.sin());
+                }
+            }
+        }
+
+        fft_grid(&mut data, n, 3, false);
+        for (index, value) in data.iter().enumerate() {
+            let expected = if index == (n + 2) * n + 3 {
+                (n * n * n) as f64
+            } else {
+                0.0
+            };
+            assert!((value.0 - expected).abs() < 1e-9 && value.1.abs() < 1e-9);
+        }
+
+        // Likewise in two dimensions, for a wave vector of (1, 2)
+        let mut plane = vec![(0.0, 0.0); n * n];
+        for (index, value) in plane.iter_mut().enumerate() {
+            let angle =
+                2.0 * std::f64::consts::PI * (index / n + 2 * (index % n)) as f64 / n as f64;
+            *value = (angle.cos(), angle.sin());
+        }
+        fft_grid(&mut plane, n, 2, false);
+        for (index, value) in plane.iter().enumerate() {
+            let expected = if index == n + 2 { (n * n) as f64 } else { 0.0 };
+            assert!((value.0 - expected).abs() < 1e-9 && value.1.abs() < 1e-9);
+        }
+    }
+}
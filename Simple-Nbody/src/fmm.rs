@@ -0,0 +1,479 @@
+use std::ops::Range;
+
+use crate::fft::{multiply, Complex};
+use crate::parallel::map_bodies;
+use crate::quadtree::QUADTREE_MAX_DEPTH;
+use crate::softening::Softening;
+use crate::{compute_m_by_r_sq, Float, VectorArray};
+
+// Fast multipole method (FMM) for bodies in the x-y plane, O(n) instead of the O(n log n)
+// of Barnes-Hut. Cells of a quadtree carry a multipole expansion of the field of the bodies
+// inside them, and a local expansion of the field from distant cells. Each pair of well
+// separated cells interacts once, multipole to local, local expansions are passed down the
+// tree, and each body evaluates the local expansion of its leaf, plus the direct sum over
+// nearby leaves
+//
+// With z = x + iy, the potential of a mass at w is -m / |z - w|, and
+// 1 / |z - w| = (z - w)^(-1/2) conj(z - w)^(-1/2), the product of a function of z and one
+// of conj(z). Both factors expand as series in complex powers, so expansions are double
+// series in powers of z and conj(z), of total degree up to the order. Errors fall off
+// roughly as (cell sizes / distance)^order, so each step up in order gains a constant factor
+// in accuracy, at a cost growing as order^4 for each pair of cells
+// Source: Greengard & Rokhlin (1987), with the dual tree walk of Dehnen (2002)
+//
+// Expansions only see x and y, so the far field ignores z, while nearby bodies are summed
+// in three dimensions, softened as usual. Distant cells are never softened, which matches
+// the spline kernel beyond its support, but not the long tail of Plummer softening
+// Expansions are always computed in double precision
+
+// Default order of the expansions
+pub const FMM_ORDER: usize = 8;
+// Highest order, well past where double precision round-off stops errors falling
+pub const FMM_MAX_ORDER: usize = 30;
+// Cells interact through their expansions when the sum of their radii is below
+// FMM_THETA times the distance between their centres
+const FMM_THETA: f64 = 0.5;
+// Most bodies in a leaf, where the direct sum is cheaper than the expansions
+const FMM_LEAF_SIZE: usize = 16;
+
+pub fn valid_order(order: usize) -> bool {
+    (1..=FMM_MAX_ORDER).contains(&order)
+}
+
+pub fn generate_acc_vectors_fmm(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    order: usize,
+    softening: Softening,
+    threads: usize,
+) -> VectorArray {
+    // The tree, expansions and interaction lists are built serially, and the local
+    // expansions and nearby bodies then summed for each body in parallel
+    assert!(
+        valid_order(order),
+        "FMM order of {order} is not from 1 to {FMM_MAX_ORDER}"
+    );
+    let n = mass_vectors.len();
+    if n == 0 {
+        return VectorArray::zeros(0);
+    }
+    let tree = Tree::build(position_vectors, mass_vectors, order);
+
+    let accs = map_bodies(n, threads, |i| {
+        let leaf = &tree.cells[tree.leaf_of[i]];
+        let (mut acc_x, mut acc_y) = tree.coefficients.evaluate(
+            &tree.locals[tree.leaf_of[i]],
+            complex_at(position_vectors, i),
+            leaf.center,
+        );
+        let mut acc_z = 0.0;
+        for &near in &leaf.near {
+            for &j in &tree.order[tree.cells[near].bodies.clone()] {
+                if j != i && mass_vectors[j] != 0.0 {
+                    let acc = compute_m_by_r_sq(
+                        mass_vectors[j],
+                        position_vectors.at(i),
+                        position_vectors.at(j),
+                        softening,
+                    );
+                    acc_x += acc.0 as f64;
+                    acc_y += acc.1 as f64;
+                    acc_z += acc.2;
+                }
+            }
+        }
+        (acc_x as Float, acc_y as Float, acc_z)
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+struct Cell {
+    // Centre of the cell's square, and distance of its furthest body from it
+    center: Complex,
+    radius: f64,
+    // Range of Tree::order holding the cell's bodies
+    bodies: Range<usize>,
+    children: Vec<usize>,
+    // Leaves whose bodies are summed directly, only populated for leaves
+    near: Vec<usize>,
+}
+
+struct Tree {
+    // Parents come before their children
+    cells: Vec<Cell>,
+    // Body indices, grouped so that each cell's bodies are contiguous
+    order: Vec<usize>,
+    leaf_of: Vec<usize>,
+    coefficients: Coefficients,
+    multipoles: Vec<Vec<Complex>>,
+    locals: Vec<Vec<Complex>>,
+}
+
+impl Tree {
+    fn build(position_vectors: &VectorArray, mass_vectors: &[Float], order: usize) -> Tree {
+        let n = mass_vectors.len();
+        let positions: Vec<Complex> = (0..n).map(|i| complex_at(position_vectors, i)).collect();
+        let mut x_range = (f64::INFINITY, f64::NEG_INFINITY);
+        let mut y_range = (f64::INFINITY, f64::NEG_INFINITY);
+        for &(x, y) in &positions {
+            x_range = (x_range.0.min(x), x_range.1.max(x));
+            y_range = (y_range.0.min(y), y_range.1.max(y));
+        }
+        let center = (0.5 * (x_range.0 + x_range.1), 0.5 * (y_range.0 + y_range.1));
+        let half_width = 0.5 * (x_range.1 - x_range.0).max(y_range.1 - y_range.0);
+
+        let mut tree = Tree {
+            cells: Vec::new(),
+            order: (0..n).collect(),
+            leaf_of: vec![0; n],
+            coefficients: Coefficients::new(order),
+            multipoles: Vec::new(),
+            locals: Vec::new(),
+        };
+        tree.add_cell(&positions, 0..n, center, half_width, 0);
+
+        // Multipoles of leaves from their bodies, and of other cells from their children,
+        // which come later in the list
+        let size = tree.coefficients.size();
+        tree.multipoles = vec![vec![(0.0, 0.0); size]; tree.cells.len()];
+        for index in (0..tree.cells.len()).rev() {
+            let cell = &tree.cells[index];
+            let mut multipole = vec![(0.0, 0.0); size];
+            if cell.children.is_empty() {
+                for &i in &tree.order[cell.bodies.clone()] {
+                    let offset = subtract(positions[i], cell.center);
+                    tree.coefficients
+                        .add_body(&mut multipole, offset, mass_vectors[i] as f64);
+                }
+            } else {
+                for &child in &cell.children {
+                    let shift = subtract(tree.cells[child].center, cell.center);
+                    tree.coefficients.shift_multipole(
+                        &mut multipole,
+                        &tree.multipoles[child],
+                        shift,
+                    );
+                }
+            }
+            tree.multipoles[index] = multipole;
+        }
+
+        // Interactions between every pair of cells, then local expansions passed down
+        let mut far = Vec::new();
+        tree.walk(0, 0, &mut far);
+        tree.locals = vec![vec![(0.0, 0.0); size]; tree.cells.len()];
+        for (target, source) in far {
+            let separation = subtract(tree.cells[target].center, tree.cells[source].center);
+            tree.coefficients.multipole_to_local(
+                &mut tree.locals[target],
+                &tree.multipoles[source],
+                separation,
+            );
+        }
+        for index in 0..tree.cells.len() {
+            for k in 0..tree.cells[index].children.len() {
+                let child = tree.cells[index].children[k];
+                let shift = subtract(tree.cells[child].center, tree.cells[index].center);
+                let parent = tree.locals[index].clone();
+                tree.coefficients
+                    .shift_local(&mut tree.locals[child], &parent, shift);
+            }
+        }
+
+        tree
+    }
+
+    fn add_cell(
+        &mut self,
+        positions: &[Complex],
+        bodies: Range<usize>,
+        center: Complex,
+        half_width: f64,
+        depth: u32,
+    ) -> usize {
+        // Adds the cell holding order[bodies], dividing it into quadrants until leaves hold at
+        // most FMM_LEAF_SIZE bodies or reach the maximum depth
+        let index = self.cells.len();
+        let radius = self.order[bodies.clone()]
+            .iter()
+            .map(|&i| abs(subtract(positions[i], center)))
+            .fold(0.0, f64::max);
+        self.cells.push(Cell {
+            center,
+            radius,
+            bodies: bodies.clone(),
+            children: Vec::new(),
+            near: Vec::new(),
+        });
+
+        if bodies.len() <= FMM_LEAF_SIZE || depth >= QUADTREE_MAX_DEPTH {
+            for &i in &self.order[bodies] {
+                self.leaf_of[i] = index;
+            }
+            return index;
+        }
+
+        // Quadrants as in Quadtree::find_quadrant()
+        let quadrant = |i: usize| {
+            let (x, y) = positions[i];
+            2 * (x <= center.0) as usize + (y <= center.1) as usize
+        };
+        self.order[bodies.clone()].sort_by_key(|&i| quadrant(i));
+        let mut start = bodies.start;
+        for q in 0..4 {
+            let end = start
+                + self.order[start..bodies.end]
+                    .iter()
+                    .take_while(|&&i| quadrant(i) == q)
+                    .count();
+            if end > start {
+                let half = 0.5 * half_width;
+                let offset = (
+                    if q < 2 { half } else { -half },
+                    if q % 2 == 0 { half } else { -half },
+                );
+                let child =
+                    self.add_cell(positions, start..end, add(center, offset), half, depth + 1);
+                self.cells[index].children.push(child);
+            }
+            start = end;
+        }
+
+        index
+    }
+
+    fn walk(&mut self, target: usize, source: usize, far: &mut Vec<(usize, usize)>) {
+        // Dual tree walk: well separated pairs interact through their expansions, pairs of
+        // leaves directly, and otherwise the larger cell is opened
+        let (a, b) = (&self.cells[target], &self.cells[source]);
+        let distance = abs(subtract(a.center, b.center));
+        if a.radius + b.radius < FMM_THETA * distance {
+            far.push((target, source));
+        } else if a.children.is_empty() && b.children.is_empty() {
+            self.cells[target].near.push(source);
+        } else if b.children.is_empty() || (!a.children.is_empty() && a.radius >= b.radius) {
+            for child in a.children.clone() {
+                self.walk(child, source, far);
+            }
+        } else {
+            for child in b.children.clone() {
+                self.walk(target, child, far);
+            }
+        }
+    }
+}
+
+struct Coefficients {
+    // Expansions hold coefficients c[k][l] of w^k conj(w)^l for k + l <= order, stored at
+    // k * (order + 1) + l
+    order: usize,
+    // binomial[k][i] = k choose i
+    binomial: Vec<f64>,
+    // series[k][n] = (-1/2 - k) choose n, the coefficients of (1 + x)^(-1/2 - k)
+    series: Vec<f64>,
+}
+
+impl Coefficients {
+    fn new(order: usize) -> Coefficients {
+        let size = (order + 1) * (order + 1);
+        let mut binomial = vec![0.0; size];
+        let mut series = vec![0.0; size];
+        for k in 0..=order {
+            binomial[k * (order + 1)] = 1.0;
+            series[k * (order + 1)] = 1.0;
+            for i in 1..=order {
+                binomial[k * (order + 1) + i] =
+                    binomial[k * (order + 1) + i - 1] * (k as f64 - i as f64 + 1.0) / i as f64;
+                series[k * (order + 1) + i] =
+                    series[k * (order + 1) + i - 1] * (-0.5 - k as f64 - i as f64 + 1.0) / i as f64;
+            }
+        }
+
+        Coefficients {
+            order,
+            binomial,
+            series,
+        }
+    }
+
+    fn size(&self) -> usize {
+        (self.order + 1) * (self.order + 1)
+    }
+
+    fn index(&self, k: usize, l: usize) -> usize {
+        k * (self.order + 1) + l
+    }
+
+    fn terms(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+        (0..=self.order).flat_map(move |k| (0..=self.order - k).map(move |l| (k, l)))
+    }
+
+    fn add_body(&self, multipole: &mut [Complex], offset: Complex, mass: f64) {
+        // Multipole moments about the cell centre are m w^k conj(w)^l, summed over bodies,
+        // with w the body's offset from the centre
+        let powers = powers(offset, self.order);
+        for (k, l) in self.terms() {
+            let term = multiply(powers[k], conjugate(powers[l]));
+            let index = self.index(k, l);
+            multipole[index] = add(multipole[index], scale(term, mass));
+        }
+    }
+
+    fn shift_multipole(&self, multipole: &mut [Complex], child: &[Complex], shift: Complex) {
+        // Adds a child's moments about a centre shift from the child's centre, expanding
+        // (w + shift)^k conj(w + shift)^l binomially
+        let powers = powers(shift, self.order);
+        for (k, l) in self.terms() {
+            let mut sum = (0.0, 0.0);
+            for i in 0..=k {
+                for j in 0..=l {
+                    let factor = self.binomial[self.index(k, i)] * self.binomial[self.index(l, j)];
+                    let term = multiply(powers[k - i], conjugate(powers[l - j]));
+                    sum = add(sum, scale(multiply(term, child[self.index(i, j)]), factor));
+                }
+            }
+            let index = self.index(k, l);
+            multipole[index] = add(multipole[index], sum);
+        }
+    }
+
+    fn multipole_to_local(
+        &self,
+        local: &mut [Complex],
+        multipole: &[Complex],
+        separation: Complex,
+    ) {
+        // Adds the field of a multipole at a separation from the local expansion's centre
+        // The potential -sum m / |z - w| expands, with z = t + separation about the multipole's
+        // centre, as -sum a_k a_l M_kl (t + separation)^(-1/2 - k) conj(...)^(-1/2 - l), with
+        // a_k = (-1)^k series[0][k], and each factor expands in powers of t by series[k]
+        let order = self.order;
+        let inverse = {
+            let r_sq = separation.0 * separation.0 + separation.1 * separation.1;
+            (separation.0 / r_sq, -separation.1 / r_sq)
+        };
+        let inverse_powers = powers(inverse, 2 * order);
+        let a = |k: usize| (-1.0_f64).powi(k as i32) * self.series[k];
+        let norm = -1.0 / abs(separation);
+
+        for (n, q) in self.terms() {
+            let mut sum = (0.0, 0.0);
+            for (k, l) in self.terms() {
+                let factor =
+                    a(k) * a(l) * self.series[self.index(k, n)] * self.series[self.index(l, q)];
+                let term = multiply(inverse_powers[k + n], conjugate(inverse_powers[l + q]));
+                sum = add(
+                    sum,
+                    scale(multiply(term, multipole[self.index(k, l)]), factor),
+                );
+            }
+            let index = self.index(n, q);
+            local[index] = add(local[index], scale(sum, norm));
+        }
+    }
+
+    fn shift_local(&self, local: &mut [Complex], parent: &[Complex], shift: Complex) {
+        // Adds a parent's local expansion, re-expanded about a centre shift from the parent's
+        let powers = powers(shift, self.order);
+        for (i, j) in self.terms() {
+            let mut sum = (0.0, 0.0);
+            for n in i..=self.order {
+                for q in j..=self.order - n {
+                    let factor = self.binomial[self.index(n, i)] * self.binomial[self.index(q, j)];
+                    let term = multiply(powers[n - i], conjugate(powers[q - j]));
+                    sum = add(sum, scale(multiply(term, parent[self.index(n, q)]), factor));
+                }
+            }
+            let index = self.index(i, j);
+            local[index] = add(local[index], sum);
+        }
+    }
+
+    fn evaluate(&self, local: &[Complex], position: Complex, center: Complex) -> (f64, f64) {
+        // Acceleration from a local expansion. With the potential phi a real function of t and
+        // conj(t), d phi / dx = 2 Re(d phi / dt) and d phi / dy = -2 Im(d phi / dt)
+        let powers = powers(subtract(position, center), self.order);
+        let mut derivative = (0.0, 0.0);
+        for (n, q) in self.terms() {
+            if n > 0 {
+                let term = multiply(powers[n - 1], conjugate(powers[q]));
+                let coefficient = scale(local[self.index(n, q)], n as f64);
+                derivative = add(derivative, multiply(term, coefficient));
+            }
+        }
+
+        (-2.0 * derivative.0, 2.0 * derivative.1)
+    }
+}
+
+fn complex_at(position_vectors: &VectorArray, i: usize) -> Complex {
+    (position_vectors.x[i] as f64, position_vectors.y[i] as f64)
+}
+
+fn powers(z: Complex, order: usize) -> Vec<Complex> {
+    // z^0 to z^order
+    let mut powers = vec![(1.0, 0.0); order + 1];
+    for k in 1..=order {
+        powers[k] = multiply(powers[k - 1], z);
+    }
+
+    powers
+}
+
+fn add(a: Complex, b: Complex) -> Complex {
+    (a.0 + b.0, a.1 + b.1)
+}
+
+fn subtract(a: Complex, b: Complex) -> Complex {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn scale(a: Complex, k: f64) -> Complex {
+    (k * a.0, k * a.1)
+}
+
+fn conjugate(a: Complex) -> Complex {
+    (a.0, -a.1)
+}
+
+fn abs(a: Complex) -> f64 {
+    a.0.hypot(a.1)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fmm::*;
+    use crate::generate_acc_vectors;
+    use crate::test_helpers::{random_particles, relative_error};
+
+    #[test]
+    fn test_fmm_accuracy_against_order() {
+        // Compared with the direct sum of compute_m_by_r_sq() over every pair, errors fall
+        // by about a factor of 3 with each order, until they reach single precision round-off
+        let (position_vectors, mass_vectors) = random_particles(2000, 0.0);
+        let direct = generate_acc_vectors(&position_vectors, &mass_vectors, Softening::None, 1);
+
+        let mut previous_error = Float::INFINITY;
+        for order in [1, 2, 4, 6, 8] {
+            let fmm = generate_acc_vectors_fmm(
+                &position_vectors,
+                &mass_vectors,
+                order,
+                Softening::None,
+                1,
+            );
+            let error = relative_error(&fmm, &direct);
+            println!("order = {order}: relative error = {error}");
+
+            assert!(error < previous_error);
+            previous_error = error;
+        }
+        assert!(previous_error < 1e-5);
+    }
+}
@@ -0,0 +1,374 @@
+use ::rand::prelude::*;
+
+use crate::{Float, VectorArray, G};
+
+// Initial conditions are generated in simulation units, centred on the origin
+// Rendering maps these onto the screen, so they do not depend on the window size
+// All randomness comes from an rng passed in, so a run is reproduced by reusing its seed
+// Generators work in three dimensions where it is natural, and flatten() turns their
+// output into initial conditions for a two dimensional run
+
+pub fn new_rng(seed: u64) -> StdRng {
+    StdRng::seed_from_u64(seed)
+}
+
+pub fn generate_uniform_box(
+    n: u32,
+    width: Float,
+    height: Float,
+    depth: Float,
+    seed: u64,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    // Masses, positions and velocities for bodies spread uniformly in a box
+    // A depth of zero gives a two dimensional box, with bodies moving in the plane
+    // Generators are always called in the same order, so a seed always gives the same bodies
+    let dimensions = if depth > 0.0 { 3 } else { 2 };
+    let mut rng = new_rng(seed);
+    let mass_vectors = generate_mass_vectors(n, &mut rng);
+    let velocity_vectors = generate_velocity_vectors(n, dimensions, &mut rng);
+    let position_vectors = generate_position_vectors(n, width, height, depth, &mut rng);
+
+    (mass_vectors, position_vectors, velocity_vectors)
+}
+
+pub fn generate_mass_vectors(n: u32, rng: &mut StdRng) -> Vec<Float> {
+    let mut mass_vectors: Vec<Float> = Vec::new();
+
+    for _i in 0..n {
+        mass_vectors.push(rng.gen());
+    }
+
+    for mass in mass_vectors.iter_mut() {
+        *mass = G * *mass * Float::powi(10.0, 15);
+    }
+
+    mass_vectors
+}
+
+pub fn generate_velocity_vectors(n: u32, dimensions: u32, rng: &mut StdRng) -> VectorArray {
+    // z velocities are only drawn in three dimensions, so two dimensional runs draw
+    // the same numbers from a seed as before z existed
+    let mut velocity_vectors = VectorArray::zeros(0);
+
+    for _i in 0..n {
+        let vel_x: Float = (rng.gen::<Float>() - 0.5) * 5.0;
+        let vel_y: Float = (rng.gen::<Float>() - 0.5) * 5.0;
+        let mut vel_z: Float = 0.0;
+        if dimensions == 3 {
+            vel_z = (rng.gen::<Float>() - 0.5) * 5.0;
+        }
+        velocity_vectors.push((vel_x, vel_y, vel_z));
+    }
+
+    velocity_vectors
+}
+
+pub fn generate_position_vectors(
+    n: u32,
+    width: Float,
+    height: Float,
+    depth: Float,
+    rng: &mut StdRng,
+) -> VectorArray {
+    // Uniformly distributed in a width by height by depth box, flat when depth is zero
+    let mut position_vectors = VectorArray::zeros(0);
+
+    for _i in 0..n {
+        let x = (rng.gen::<Float>() - 0.5) * width;
+        let y = (rng.gen::<Float>() - 0.5) * height;
+        let mut z = 0.0;
+        if depth > 0.0 {
+            z = (rng.gen::<Float>() - 0.5) * depth;
+        }
+        position_vectors.push((x, y, z));
+    }
+
+    position_vectors
+}
+
+pub fn flatten(position_vectors: &mut VectorArray, velocity_vectors: &mut VectorArray) {
+    // Projects bodies onto the x-y plane for a two dimensional run
+    // Three dimensional equilibria, like the spherical models below, are not in equilibrium
+    // once projected, though they keep their radial profile on screen
+    position_vectors.z.iter_mut().for_each(|z| *z = 0.0);
+    velocity_vectors.z.iter_mut().for_each(|vz| *vz = 0.0);
+}
+
+// Spherical models are sampled in three dimensions from their distribution functions
+
+// Radii are drawn below this fraction of the total mass, as both profiles extend to infinity
+// Without a cut, a handful of bodies would be placed thousands of scale radii away
+const MAX_MASS_FRACTION: f64 = 0.99;
+
+pub fn generate_plummer(
+    n: u32,
+    total_mass: Float,
+    scale_radius: Float,
+    seed: u64,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    sample_plummer(n, total_mass, scale_radius, &mut new_rng(seed))
+}
+
+pub fn generate_hernquist(
+    n: u32,
+    total_mass: Float,
+    scale_radius: Float,
+    seed: u64,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    sample_hernquist(n, total_mass, scale_radius, &mut new_rng(seed))
+}
+
+pub fn sample_plummer(
+    n: u32,
+    total_mass: Float,
+    scale_radius: Float,
+    rng: &mut StdRng,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    // Plummer sphere, with potential -GM / sqrt(r^2 + a^2)
+    // total_mass is G times the mass, matching mass_vectors, so G = 1 in these units
+    // Enclosed mass M(r) = M r^3 / (r^2 + a^2)^(3/2) is inverted for the radius,
+    // and the distribution function is f(E) proportional to E^(7/2)
+    sample_spherical_model(
+        n,
+        total_mass,
+        scale_radius,
+        rng,
+        |fraction| (fraction.powf(-2.0 / 3.0) - 1.0).powf(-0.5),
+        |r| 1.0 / (r * r + 1.0).sqrt(),
+        |energy| energy.powf(3.5),
+    )
+}
+
+pub fn sample_hernquist(
+    n: u32,
+    total_mass: Float,
+    scale_radius: Float,
+    rng: &mut StdRng,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    // Hernquist (1990) profile, with potential -GM / (r + a)
+    // Enclosed mass M(r) = M r^2 / (r + a)^2 is inverted for the radius, and the
+    // distribution function is the isotropic one from equation 17 of the paper
+    sample_spherical_model(
+        n,
+        total_mass,
+        scale_radius,
+        rng,
+        |fraction| fraction.sqrt() / (1.0 - fraction.sqrt()),
+        |r| 1.0 / (r + 1.0),
+        hernquist_distribution_function,
+    )
+}
+
+fn hernquist_distribution_function(energy: f64) -> f64 {
+    // Unnormalised, for binding energy in units of GM / a
+    let q = energy.sqrt();
+    let q_sq = energy;
+    let bracket = 3.0 * q.asin()
+        + q * (1.0 - q_sq).sqrt() * (1.0 - 2.0 * q_sq) * (8.0 * q_sq * q_sq - 8.0 * q_sq - 3.0);
+
+    (bracket / (1.0 - q_sq).powf(2.5)).max(0.0)
+}
+
+fn sample_spherical_model(
+    n: u32,
+    total_mass: Float,
+    scale_radius: Float,
+    rng: &mut StdRng,
+    radius_at_mass_fraction: impl Fn(f64) -> f64,
+    relative_potential: impl Fn(f64) -> f64,
+    distribution_function: impl Fn(f64) -> f64,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    // Sampled in units of G = M = a = 1, then scaled
+    // Radii come from inverting the enclosed mass, speeds from the distribution function
+    // at that radius, and both directions are isotropic
+    let mut position = [Vec::new(), Vec::new(), Vec::new()];
+    let mut velocity = [Vec::new(), Vec::new(), Vec::new()];
+
+    for _i in 0..n {
+        let fraction = rng.gen::<f64>() * MAX_MASS_FRACTION;
+        let r = radius_at_mass_fraction(fraction);
+        let speed = sample_speed(relative_potential(r), &distribution_function, rng);
+
+        let direction = random_direction(rng);
+        let velocity_direction = random_direction(rng);
+        for k in 0..3 {
+            position[k].push(r * direction[k]);
+            velocity[k].push(speed * velocity_direction[k]);
+        }
+    }
+
+    // Moved to the centre of mass frame, as sampling leaves a small net offset and drift
+    for k in 0..3 {
+        let mean_position = position[k].iter().sum::<f64>() / n as f64;
+        let mean_velocity = velocity[k].iter().sum::<f64>() / n as f64;
+        position[k].iter_mut().for_each(|x| *x -= mean_position);
+        velocity[k].iter_mut().for_each(|v| *v -= mean_velocity);
+    }
+
+    let velocity_scale = (total_mass as f64 / scale_radius as f64).sqrt();
+    let scaled = |values: &[f64], scale: f64| -> Vec<Float> {
+        values
+            .iter()
+            .map(|value| (value * scale) as Float)
+            .collect()
+    };
+    let [position_x, position_y, position_z] = position;
+    let [velocity_x, velocity_y, velocity_z] = velocity;
+    let position_vectors = VectorArray {
+        x: scaled(&position_x, scale_radius as f64),
+        y: scaled(&position_y, scale_radius as f64),
+        z: scaled(&position_z, scale_radius as f64),
+    };
+    let velocity_vectors = VectorArray {
+        x: scaled(&velocity_x, velocity_scale),
+        y: scaled(&velocity_y, velocity_scale),
+        z: scaled(&velocity_z, velocity_scale),
+    };
+
+    (
+        vec![total_mass / n as Float; n as usize],
+        position_vectors,
+        velocity_vectors,
+    )
+}
+
+fn sample_speed(
+    potential: f64,
+    distribution_function: impl Fn(f64) -> f64,
+    rng: &mut StdRng,
+) -> f64 {
+    // Rejection sampling from p(v) proportional to v^2 f(potential - v^2 / 2),
+    // for speeds below escape. The peak is found on a grid, then refined, as it sits
+    // close to zero speed near the centre of cuspy models like Hernquist
+    let escape_speed = (2.0 * potential).sqrt();
+    let density = |v: f64| v * v * distribution_function(potential - 0.5 * v * v);
+
+    let grid_points = 256;
+    let spacing = escape_speed / grid_points as f64;
+    let peak_index = (1..grid_points)
+        .max_by(|&a, &b| density(a as f64 * spacing).total_cmp(&density(b as f64 * spacing)))
+        .unwrap();
+    let (mut low, mut high) = (
+        (peak_index - 1) as f64 * spacing,
+        (peak_index + 1) as f64 * spacing,
+    );
+    for _i in 0..40 {
+        let third = (high - low) / 3.0;
+        if density(low + third) < density(high - third) {
+            low += third;
+        } else {
+            high -= third;
+        }
+    }
+    // Margin above the peak, so rounding in the search cannot cut off the top of the distribution
+    let envelope = 1.05 * density(0.5 * (low + high));
+
+    loop {
+        let v = rng.gen::<f64>() * escape_speed;
+        if rng.gen::<f64>() * envelope < density(v) {
+            return v;
+        }
+    }
+}
+
+fn random_direction(rng: &mut StdRng) -> [f64; 3] {
+    // Uniform on the unit sphere
+    let cos_theta = 2.0 * rng.gen::<f64>() - 1.0;
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * rng.gen::<f64>();
+
+    [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta]
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initial_conditions::*;
+
+    #[test]
+    fn test_generate_position_vectors() {
+        let position_vectors = generate_position_vectors(1000, 640.0, 360.0, 0.0, &mut new_rng(0));
+
+        assert_eq!(position_vectors.x.len(), 1000);
+        assert!(position_vectors.x.iter().all(|x| x.abs() <= 320.0));
+        assert!(position_vectors.y.iter().all(|y| y.abs() <= 180.0));
+        assert!(position_vectors.z.iter().all(|&z| z == 0.0));
+
+        let position_vectors =
+            generate_position_vectors(1000, 640.0, 360.0, 200.0, &mut new_rng(0));
+        assert!(position_vectors.z.iter().all(|z| z.abs() <= 100.0));
+        assert!(position_vectors.z.iter().any(|z| z.abs() > 50.0));
+    }
+
+    #[test]
+    fn test_seeded_generation() {
+        let (mass_a, position_a, velocity_a) = generate_uniform_box(100, 640.0, 360.0, 0.0, 42);
+        let (mass_b, position_b, velocity_b) = generate_uniform_box(100, 640.0, 360.0, 0.0, 42);
+        let (mass_c, _, _) = generate_uniform_box(100, 640.0, 360.0, 0.0, 43);
+
+        assert_eq!(mass_a, mass_b);
+        assert_eq!(position_a.x, position_b.x);
+        assert_eq!(position_a.y, position_b.y);
+        assert_eq!(velocity_a.x, velocity_b.x);
+        assert_eq!(velocity_a.y, velocity_b.y);
+        assert_ne!(mass_a, mass_c);
+    }
+
+    fn virial_ratio(model: &(Vec<Float>, VectorArray, VectorArray)) -> f64 {
+        // 2K / |W| in three dimensions, without softening, to match the sampled model
+        let (mass_vectors, position_vectors, velocity_vectors) = model;
+        let n = mass_vectors.len();
+        let mut kinetic_energy = 0.0;
+        let mut potential_energy = 0.0;
+        for i in 0..n {
+            let m = mass_vectors[i] as f64;
+            let (vx, vy, vz) = velocity_vectors.at(i);
+            kinetic_energy += 0.5 * m * (vx as f64).powi(2)
+                + 0.5 * m * (vy as f64).powi(2)
+                + 0.5 * m * (vz as f64).powi(2);
+            for j in (i + 1)..n {
+                let (a, b) = (position_vectors.at(i), position_vectors.at(j));
+                let r = ((a.0 as f64 - b.0 as f64).powi(2)
+                    + (a.1 as f64 - b.1 as f64).powi(2)
+                    + (a.2 as f64 - b.2 as f64).powi(2))
+                .sqrt();
+                potential_energy -= m * mass_vectors[j] as f64 / r;
+            }
+        }
+
+        2.0 * kinetic_energy / potential_energy.abs()
+    }
+
+    #[test]
+    fn test_spherical_models_are_virialised() {
+        // Sampling noise in the virial ratio is a few percent for a couple of thousand bodies
+        for seed in [1, 2, 3] {
+            let plummer = sample_plummer(2000, 1.0e7, 100.0, &mut new_rng(seed));
+            let hernquist = sample_hernquist(2000, 1.0e7, 100.0, &mut new_rng(seed));
+
+            assert!((virial_ratio(&plummer) - 1.0).abs() < 0.1);
+            assert!((virial_ratio(&hernquist) - 1.0).abs() < 0.1);
+        }
+    }
+
+    #[test]
+    fn test_spherical_model_scaling() {
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            generate_plummer(500, 1.0e7, 100.0, 5);
+
+        assert_eq!(mass_vectors.len(), 500);
+        assert!((mass_vectors.iter().sum::<Float>() - 1.0e7).abs() < 1.0);
+        let mean_x = position_vectors.x.iter().sum::<Float>() / 500.0;
+        let mean_vx = velocity_vectors.x.iter().sum::<Float>() / 500.0;
+        assert!(mean_x.abs() < 1e-3);
+        assert!(mean_vx.abs() < 1e-3);
+        // Half of the mass of a Plummer sphere lies within 1.3 scale radii
+        let mut radii: Vec<Float> = (0..500)
+            .map(|i| {
+                let (x, y, z) = position_vectors.at(i);
+                (x * x + y * y + z * z).sqrt()
+            })
+            .collect();
+        radii.sort_by(Float::total_cmp);
+        assert!(radii[250] > 100.0 && radii[250] < 160.0);
+    }
+}
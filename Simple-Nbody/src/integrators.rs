@@ -0,0 +1,324 @@
+use crate::{generate_acc_jerk_vectors, update_euler, Float, Simulation};
+
+// Integrators advance a Simulation by a single step of size h
+// All of them leave acc_vectors holding the accelerations at the new positions,
+// so the first force evaluation of the next step can be skipped
+impl Simulation {
+    pub fn update_euler(&mut self) {
+        update_euler(
+            &mut self.position_vectors,
+            &mut self.velocity_vectors,
+            &self.acc_vectors,
+            self.h,
+        );
+        self.acc_vectors = self.compute_acc_vectors_at(
+            &self.position_vectors,
+            &self.velocity_vectors,
+            self.t + self.h,
+        );
+    }
+
+    pub fn update_leap_frog(&mut self) {
+        // Kick-drift-kick leapfrog, equivalent to velocity Verlet
+        // The closing kick's accelerations are reused as the next step's opening kick
+        // Velocity dependent forces are evaluated with the half step velocities, so are only
+        // first order accurate, and rk4 suits post-Newtonian runs better
+        self.leap_frog_step(self.t, self.h);
+    }
+
+    fn leap_frog_step(&mut self, t: Float, h: Float) {
+        // A step of h from time t
+        for i in 0..self.position_vectors.x.len() {
+            self.velocity_vectors.x[i] += 0.5 * h * self.acc_vectors.x[i];
+            self.velocity_vectors.y[i] += 0.5 * h * self.acc_vectors.y[i];
+            self.velocity_vectors.z[i] += 0.5 * h * self.acc_vectors.z[i];
+
+            self.position_vectors.x[i] += h * self.velocity_vectors.x[i];
+            self.position_vectors.y[i] += h * self.velocity_vectors.y[i];
+            self.position_vectors.z[i] += h * self.velocity_vectors.z[i];
+        }
+
+        self.acc_vectors =
+            self.compute_acc_vectors_at(&self.position_vectors, &self.velocity_vectors, t + h);
+
+        for i in 0..self.position_vectors.x.len() {
+            self.velocity_vectors.x[i] += 0.5 * h * self.acc_vectors.x[i];
+            self.velocity_vectors.y[i] += 0.5 * h * self.acc_vectors.y[i];
+            self.velocity_vectors.z[i] += 0.5 * h * self.acc_vectors.z[i];
+        }
+    }
+
+    pub fn update_yoshida(&mut self) {
+        // Fourth order symplectic integrator (Forest-Ruth / Yoshida)
+        // Composes three leapfrog steps, the middle one stepping backwards in time
+        // Source: https://en.wikipedia.org/wiki/Leapfrog_integration#4th_order_Yoshida_integrator
+        let cbrt_2 = Float::cbrt(2.0);
+        let w1 = 1.0 / (2.0 - cbrt_2);
+        let w0 = -cbrt_2 / (2.0 - cbrt_2);
+
+        let t = self.t;
+        self.leap_frog_step(t, w1 * self.h);
+        self.leap_frog_step(t + w1 * self.h, w0 * self.h);
+        self.leap_frog_step(t + (w1 + w0) * self.h, w1 * self.h);
+    }
+
+    pub fn update_rk4(&mut self) {
+        // Classic 4th order Runge-Kutta on the state (position, velocity)
+        // Derivative of position is velocity, derivative of velocity is acceleration
+        let h = self.h;
+        let t = self.t;
+        let x0 = &self.position_vectors;
+        let v0 = &self.velocity_vectors;
+
+        let k1_x = v0.clone();
+        let k1_v = self.acc_vectors.clone();
+
+        let k2_x = v0.add(&k1_v.scale(0.5 * h));
+        let k2_v = self.compute_acc_vectors_at(&x0.add(&k1_x.scale(0.5 * h)), &k2_x, t + 0.5 * h);
+
+        let k3_x = v0.add(&k2_v.scale(0.5 * h));
+        let k3_v = self.compute_acc_vectors_at(&x0.add(&k2_x.scale(0.5 * h)), &k3_x, t + 0.5 * h);
+
+        let k4_x = v0.add(&k3_v.scale(h));
+        let k4_v = self.compute_acc_vectors_at(&x0.add(&k3_x.scale(h)), &k4_x, t + h);
+
+        let dx = k1_x.add(&k2_x.scale(2.0)).add(&k3_x.scale(2.0)).add(&k4_x);
+        let dv = k1_v.add(&k2_v.scale(2.0)).add(&k3_v.scale(2.0)).add(&k4_v);
+
+        self.position_vectors = x0.add(&dx.scale(h / 6.0));
+        self.velocity_vectors = v0.add(&dv.scale(h / 6.0));
+        self.acc_vectors =
+            self.compute_acc_vectors_at(&self.position_vectors, &self.velocity_vectors, t + h);
+    }
+
+    pub fn update_hermite(&mut self) {
+        // 4th order Hermite predictor-corrector
+        // Always uses the direct sum, as the tree solver does not provide jerk
+        // Source: Makino & Aarseth (1992), PASJ 44, 141
+        let h = self.h;
+        let n = self.position_vectors.x.len();
+        if self.jerk_vectors.x.len() != n {
+            (self.acc_vectors, self.jerk_vectors) = generate_acc_jerk_vectors(
+                &self.position_vectors,
+                &self.velocity_vectors,
+                &self.mass_vectors,
+                self.softening,
+                self.threads,
+            );
+        }
+
+        // Predict positions and velocities with a Taylor series to third order
+        let mut predicted_positions = self.position_vectors.clone();
+        let mut predicted_velocities = self.velocity_vectors.clone();
+        for i in 0..n {
+            predicted_positions.x[i] += h * self.velocity_vectors.x[i]
+                + h.powi(2) / 2.0 * self.acc_vectors.x[i]
+                + h.powi(3) / 6.0 * self.jerk_vectors.x[i];
+            predicted_positions.y[i] += h * self.velocity_vectors.y[i]
+                + h.powi(2) / 2.0 * self.acc_vectors.y[i]
+                + h.powi(3) / 6.0 * self.jerk_vectors.y[i];
+            predicted_positions.z[i] += h * self.velocity_vectors.z[i]
+                + h.powi(2) / 2.0 * self.acc_vectors.z[i]
+                + h.powi(3) / 6.0 * self.jerk_vectors.z[i];
+
+            predicted_velocities.x[i] +=
+                h * self.acc_vectors.x[i] + h.powi(2) / 2.0 * self.jerk_vectors.x[i];
+            predicted_velocities.y[i] +=
+                h * self.acc_vectors.y[i] + h.powi(2) / 2.0 * self.jerk_vectors.y[i];
+            predicted_velocities.z[i] +=
+                h * self.acc_vectors.z[i] + h.powi(2) / 2.0 * self.jerk_vectors.z[i];
+        }
+
+        let (acc1, jerk1) = generate_acc_jerk_vectors(
+            &predicted_positions,
+            &predicted_velocities,
+            &self.mass_vectors,
+            self.softening,
+            self.threads,
+        );
+
+        // Correct using accelerations and jerks at both ends of the step
+        for i in 0..n {
+            let vx0 = self.velocity_vectors.x[i];
+            let vy0 = self.velocity_vectors.y[i];
+            let vz0 = self.velocity_vectors.z[i];
+
+            self.velocity_vectors.x[i] += h / 2.0 * (self.acc_vectors.x[i] + acc1.x[i])
+                + h.powi(2) / 12.0 * (self.jerk_vectors.x[i] - jerk1.x[i]);
+            self.velocity_vectors.y[i] += h / 2.0 * (self.acc_vectors.y[i] + acc1.y[i])
+                + h.powi(2) / 12.0 * (self.jerk_vectors.y[i] - jerk1.y[i]);
+            self.velocity_vectors.z[i] += h / 2.0 * (self.acc_vectors.z[i] + acc1.z[i])
+                + h.powi(2) / 12.0 * (self.jerk_vectors.z[i] - jerk1.z[i]);
+
+            self.position_vectors.x[i] += h / 2.0 * (vx0 + self.velocity_vectors.x[i])
+                + h.powi(2) / 12.0 * (self.acc_vectors.x[i] - acc1.x[i]);
+            self.position_vectors.y[i] += h / 2.0 * (vy0 + self.velocity_vectors.y[i])
+                + h.powi(2) / 12.0 * (self.acc_vectors.y[i] - acc1.y[i]);
+            self.position_vectors.z[i] += h / 2.0 * (vz0 + self.velocity_vectors.z[i])
+                + h.powi(2) / 12.0 * (self.acc_vectors.z[i] - acc1.z[i]);
+        }
+
+        // Accelerations at the predicted state stand in for those at the corrected state
+        self.acc_vectors = acc1;
+        self.jerk_vectors = jerk1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::softening::Softening;
+    use crate::{Float, Simulation, VectorArray};
+
+    fn eccentric_orbit(integrator: &str, h: Float) -> Simulation {
+        // Light body on an eccentric orbit around a heavy one, well outside the softening length
+        let mass_vectors = vec![10_000.0, 1.0];
+        let position_vectors = VectorArray {
+            x: vec![0.0, 100.0],
+            y: vec![0.0, 0.0],
+            z: vec![0.0, 0.0],
+        };
+        let velocity_vectors = VectorArray {
+            x: vec![0.0, 0.0],
+            y: vec![0.0, 8.0],
+            z: vec![0.0, 0.0],
+        };
+        let mut simulation = Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            "direct",
+            0.0,
+            integrator,
+            Softening::default(),
+        );
+        simulation.h = h;
+
+        simulation
+    }
+
+    fn total_energy(simulation: &Simulation) -> Float {
+        simulation.diagnostics().total_energy
+    }
+
+    fn max_energy_error(integrator: &str, h: Float, steps: u32) -> Float {
+        let mut simulation = eccentric_orbit(integrator, h);
+        let energy0 = total_energy(&simulation);
+        let mut max_error: Float = 0.0;
+        for _i in 0..steps {
+            simulation.update();
+            let error = ((total_energy(&simulation) - energy0) / energy0).abs();
+            max_error = max_error.max(error);
+        }
+        println!("{integrator} maximum relative energy error: {max_error}");
+
+        max_error
+    }
+
+    fn final_position_error(integrator: &str, h: Float, t: Float) -> Float {
+        // Distance of the orbiting body from a fine step rk4 reference after time t
+        let mut reference = eccentric_orbit("rk4", 0.01);
+        for _i in 0..(t / 0.01).round() as u32 {
+            reference.update();
+        }
+        let mut simulation = eccentric_orbit(integrator, h);
+        for _i in 0..(t / h).round() as u32 {
+            simulation.update();
+        }
+
+        ((simulation.position_vectors.x[1] - reference.position_vectors.x[1]).powi(2)
+            + (simulation.position_vectors.y[1] - reference.position_vectors.y[1]).powi(2))
+        .sqrt()
+    }
+
+    #[test]
+    fn test_leap_frog_energy_bounded() {
+        // Roughly 15 orbits, leapfrog error should oscillate rather than grow
+        let mut simulation = eccentric_orbit("leap_frog", 0.1);
+        let energy0 = total_energy(&simulation);
+        let mut max_error: Float = 0.0;
+        for _i in 0..10_000 {
+            simulation.update();
+            let error = ((total_energy(&simulation) - energy0) / energy0).abs();
+            max_error = max_error.max(error);
+        }
+        println!("leap_frog maximum relative energy error: {max_error}");
+
+        assert_eq!(simulation.iteration, 10_000);
+        assert!(max_error < 0.001);
+    }
+
+    #[test]
+    fn test_leap_frog_beats_euler() {
+        let mut leap_frog = eccentric_orbit("leap_frog", 0.1);
+        let mut euler = eccentric_orbit("euler", 0.1);
+        let energy0 = total_energy(&leap_frog);
+        for _i in 0..10_000 {
+            leap_frog.update();
+            euler.update();
+        }
+        let leap_frog_error = ((total_energy(&leap_frog) - energy0) / energy0).abs();
+        let euler_error = ((total_energy(&euler) - energy0) / energy0).abs();
+        println!("leap_frog: {leap_frog_error}, euler: {euler_error}");
+
+        assert!(leap_frog_error < euler_error);
+    }
+
+    #[test]
+    fn test_fourth_order_energy() {
+        // Over two orbits at a coarse step, every 4th order scheme should do far better than leapfrog
+        // rk4 and hermite are not symplectic, so unlike yoshida their error drifts over longer runs
+        let leap_frog_error = max_energy_error("leap_frog", 1.0, 120);
+        for integrator in ["rk4", "yoshida", "hermite"] {
+            let error = max_energy_error(integrator, 1.0, 120);
+
+            assert!(error < 0.25 * leap_frog_error);
+        }
+    }
+
+    #[test]
+    fn test_fourth_order_convergence() {
+        // Halving the step of a 4th order scheme should cut the error by about 2^4 = 16
+        for integrator in ["rk4", "yoshida", "hermite"] {
+            let error_coarse = final_position_error(integrator, 2.0, 60.0);
+            let error_fine = final_position_error(integrator, 1.0, 60.0);
+            println!("{integrator}: {error_coarse} -> {error_fine}");
+
+            assert!(error_coarse / error_fine > 10.0);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "Invalid integrator option")]
+    fn test_invalid_integrator() {
+        eccentric_orbit("verlet", 0.1).update();
+    }
+
+    #[test]
+    fn test_inclined_orbit() {
+        // The same orbit tilted out of the plane, about the x axis, should give the same
+        // trajectory tilted by the same angle
+        let mut planar = eccentric_orbit("yoshida", 0.5);
+        let mut inclined = eccentric_orbit("yoshida", 0.5);
+        let (sin, cos) = Float::sin_cos(0.6);
+        for vectors in [
+            &mut inclined.position_vectors,
+            &mut inclined.velocity_vectors,
+        ] {
+            for i in 0..2 {
+                let y = vectors.y[i];
+                (vectors.y[i], vectors.z[i]) = (cos * y, sin * y);
+            }
+        }
+        inclined.dimensions = 3;
+        for _i in 0..200 {
+            planar.update();
+            inclined.update();
+        }
+
+        let (x, y, z) = inclined.position_vectors.at(1);
+        assert!((x - planar.position_vectors.x[1]).abs() < 1e-2);
+        assert!((y - cos * planar.position_vectors.y[1]).abs() < 1e-2);
+        assert!((z - sin * planar.position_vectors.y[1]).abs() < 1e-2);
+        assert!(planar.position_vectors.z.iter().all(|&z| z == 0.0));
+    }
+}
@@ -0,0 +1,862 @@
+// Particle data is stored as parallel arrays, indexed together in most loops
+#![allow(clippy::needless_range_loop)]
+// Conversions between Float and a fixed precision are needed in one build and redundant in
+// the other
+#![allow(clippy::unnecessary_cast)]
+
+pub mod block_timesteps;
+pub mod camera;
+pub mod checkpoint;
+pub mod collisions;
+pub mod config;
+pub mod diagnostics;
+pub mod ephemeris;
+pub mod external;
+pub mod fft;
+pub mod fmm;
+pub mod initial_conditions;
+pub mod integrators;
+pub mod octree;
+pub mod pairwise;
+pub mod parallel;
+pub mod particle_mesh;
+pub mod periodic;
+pub mod post_newtonian;
+pub mod quadtree;
+pub mod scenario;
+pub mod snapshot;
+pub mod softening;
+#[cfg(test)]
+mod test_helpers;
+pub mod test_particles;
+pub mod units;
+
+use block_timesteps::ETA;
+use collisions::Collisions;
+use external::ExternalPotential;
+use fmm::FMM_ORDER;
+use particle_mesh::{GreenCache, PM_GRID};
+use post_newtonian::PostNewtonian;
+use softening::Softening;
+use units::UnitSystem;
+
+// Floating point type of the simulation, f32 unless built with the f64 feature
+// Rendering converts to f32 when drawing, whichever is chosen
+#[cfg(not(feature = "f64"))]
+pub type Float = f32;
+#[cfg(feature = "f64")]
+pub type Float = f64;
+#[cfg(not(feature = "f64"))]
+pub use std::f32::consts as float_consts;
+#[cfg(feature = "f64")]
+pub use std::f64::consts as float_consts;
+
+pub const G: Float = 6.67430 / 100_000_000_000.0;
+pub const H: Float = 0.01;
+// Default Plummer softening length, see softening.rs
+pub const EPSILON: Float = 5.0;
+// Default density of bodies when collisions are on, with G multiplied in as for mass_vectors,
+// which gives bodies of the default box and cluster masses a radius of about 1
+pub const DENSITY: Float = 1.0e4;
+
+// Settings of the approximate force solvers, each of which ignores the others
+// Passed to compute_acc_vectors() alongside the method name
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ForceParameters {
+    // Opening angle of the tree methods
+    pub theta: Float,
+    // Cells along each side of the particle mesh grid
+    pub pm_grid: usize,
+    // Order of the FMM's multipole and local expansions
+    pub fmm_order: usize,
+}
+
+impl Default for ForceParameters {
+    fn default() -> ForceParameters {
+        ForceParameters {
+            theta: 0.5,
+            pm_grid: PM_GRID,
+            fmm_order: FMM_ORDER,
+        }
+    }
+}
+
+// Vectors are always three dimensional. Two dimensional runs keep z and its velocity at zero,
+// so forces never leave the plane
+#[derive(Clone, Default)]
+pub struct VectorArray {
+    pub x: Vec<Float>,
+    pub y: Vec<Float>,
+    pub z: Vec<Float>,
+}
+
+impl VectorArray {
+    pub fn zeros(n: usize) -> VectorArray {
+        VectorArray {
+            x: vec![0.0; n],
+            y: vec![0.0; n],
+            z: vec![0.0; n],
+        }
+    }
+
+    pub fn at(&self, i: usize) -> (Float, Float, Float) {
+        (self.x[i], self.y[i], self.z[i])
+    }
+
+    pub fn push(&mut self, vector: (Float, Float, Float)) {
+        self.x.push(vector.0);
+        self.y.push(vector.1);
+        self.z.push(vector.2);
+    }
+
+    pub fn scale(&self, k: Float) -> VectorArray {
+        VectorArray {
+            x: self.x.iter().map(|x| k * x).collect(),
+            y: self.y.iter().map(|y| k * y).collect(),
+            z: self.z.iter().map(|z| k * z).collect(),
+        }
+    }
+
+    pub fn add(&self, v: &VectorArray) -> VectorArray {
+        VectorArray {
+            x: self.x.iter().zip(&v.x).map(|(a, b)| a + b).collect(),
+            y: self.y.iter().zip(&v.y).map(|(a, b)| a + b).collect(),
+            z: self.z.iter().zip(&v.z).map(|(a, b)| a + b).collect(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Simulation {
+    // Bodies with zero mass are test particles, feeling the others without pulling on them
+    // Kept after the massive bodies, so force sums only need to run over the first
+    // active_count() bodies, see test_particles.rs
+    pub mass_vectors: Vec<Float>,
+    pub position_vectors: VectorArray,
+    pub velocity_vectors: VectorArray,
+    // Accelerations at the current positions
+    // Kept between steps so each step only needs one force evaluation
+    pub acc_vectors: VectorArray,
+    // Time derivative of acceleration, only kept by the Hermite integrator
+    pub jerk_vectors: VectorArray,
+    // "direct", "pairwise", "barnes_hut", "octree" or "octree_quadrupole",
+    // see compute_acc_vectors(), "pm", see particle_mesh.rs, or "fmm", see fmm.rs
+    pub force_method: String,
+    pub theta: Float,
+    // Cells along each side of the particle mesh grid
+    pub pm_grid: usize,
+    // Transformed Green's function of the particle mesh grid, kept between force evaluations
+    pub pm_green: GreenCache,
+    // Order of the FMM's multipole and local expansions
+    pub fmm_order: usize,
+    // Kernel and length used to soften forces between close bodies
+    pub softening: Softening,
+    // Relativistic corrections to the pair force, see post_newtonian.rs
+    pub post_newtonian: PostNewtonian,
+    // Whether overlapping bodies merge, bounce or pass through each other, see collisions.rs
+    pub collisions: Collisions,
+    // Density bodies' radii are derived from, with G multiplied in as for mass_vectors
+    pub density: Float,
+    // "euler", "leap_frog", "rk4", "yoshida" or "hermite", see integrators.rs,
+    // or "block_hermite", see block_timesteps.rs
+    pub integrator: String,
+    // Step size for integration over time, the longest step for block_hermite
+    pub h: Float,
+    // Accuracy parameter of block_hermite's timestep criterion
+    pub eta: Float,
+    // Timestep level of each body during the last step of block_hermite, empty otherwise
+    // Not part of the simulation state, as levels are chosen afresh every step
+    pub block_levels: Vec<u32>,
+    pub iteration: u64,
+    // Simulation time
+    pub t: Float,
+    // Seed initial conditions were generated from, recorded in output files
+    pub seed: u64,
+    // 2 or 3. Two dimensional runs have all z components at zero and are drawn from above
+    pub dimensions: u32,
+    // Side of the periodic box centred on the origin, or None for open boundaries,
+    // see periodic.rs
+    pub box_size: Option<Float>,
+    // Background potential the bodies move in as well as their own gravity, see external.rs
+    pub external: Option<ExternalPotential>,
+    // Units positions, velocities and times are in, and masses are in before G is multiplied in
+    pub units: UnitSystem,
+    // Threads forces are computed on, 0 for one per core. Not part of the simulation state,
+    // as results do not depend on it
+    pub threads: usize,
+}
+
+impl Simulation {
+    pub fn new(
+        mass_vectors: Vec<Float>,
+        position_vectors: VectorArray,
+        velocity_vectors: VectorArray,
+        force_method: &str,
+        theta: Float,
+        integrator: &str,
+        softening: Softening,
+    ) -> Simulation {
+        let mut simulation = Simulation {
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            acc_vectors: VectorArray::zeros(0),
+            jerk_vectors: VectorArray::zeros(0),
+            force_method: force_method.to_owned(),
+            theta,
+            pm_grid: PM_GRID,
+            pm_green: GreenCache::default(),
+            fmm_order: FMM_ORDER,
+            softening,
+            post_newtonian: PostNewtonian::None,
+            collisions: Collisions::None,
+            density: DENSITY,
+            integrator: integrator.to_owned(),
+            h: H,
+            eta: ETA,
+            block_levels: Vec::new(),
+            iteration: 0,
+            t: 0.0,
+            seed: 0,
+            dimensions: 2,
+            box_size: None,
+            external: None,
+            units: UnitSystem::NBODY,
+            threads: 1,
+        };
+        simulation.acc_vectors = simulation.compute_acc_vectors();
+
+        simulation
+    }
+
+    pub fn compute_acc_vectors(&self) -> VectorArray {
+        // Accelerations at the current state and time with the configured force solver
+        self.compute_acc_vectors_at(&self.position_vectors, &self.velocity_vectors, self.t)
+    }
+
+    pub fn compute_acc_vectors_at(
+        &self,
+        position_vectors: &VectorArray,
+        velocity_vectors: &VectorArray,
+        t: Float,
+    ) -> VectorArray {
+        // Accelerations at arbitrary positions, velocities and time, used by integrators with
+        // intermediate stages. Velocities only matter to post-Newtonian corrections, and time
+        // to time dependent external potentials
+        let mut acc_vectors = self.compute_gravity_at(position_vectors, velocity_vectors);
+        if let Some(external) = &self.external {
+            external.add_acc_vectors(&mut acc_vectors, position_vectors, t);
+        }
+
+        acc_vectors
+    }
+
+    fn compute_gravity_at(
+        &self,
+        position_vectors: &VectorArray,
+        velocity_vectors: &VectorArray,
+    ) -> VectorArray {
+        // Accelerations from the bodies themselves
+        // Particle mesh solves on the periodic box itself, and otherwise periodic boundaries
+        // use the Ewald sum for the direct method. Post-Newtonian corrections use the direct
+        // sum, as they depend on the velocities of each pair. validate() rejects other methods
+        // Particle mesh is dispatched here rather than by compute_acc_vectors(), to keep its
+        // Green's function between steps and use a square grid for two dimensional runs
+        if self.post_newtonian != PostNewtonian::None {
+            return post_newtonian::generate_acc_vectors_pn(
+                position_vectors,
+                velocity_vectors,
+                &self.mass_vectors,
+                self.softening,
+                self.post_newtonian,
+                self.threads,
+            );
+        }
+        if self.force_method == "pm" {
+            return particle_mesh::generate_acc_vectors_pm(
+                position_vectors,
+                &self.mass_vectors,
+                self.pm_grid,
+                self.box_size,
+                self.dimensions,
+                &self.pm_green,
+            );
+        }
+        if let Some(box_size) = self.box_size {
+            return periodic::generate_acc_vectors_ewald(
+                position_vectors,
+                &self.mass_vectors,
+                box_size,
+                self.softening,
+                self.threads,
+            );
+        }
+        compute_acc_vectors(
+            position_vectors,
+            &self.mass_vectors,
+            &self.force_method,
+            ForceParameters {
+                theta: self.theta,
+                pm_grid: self.pm_grid,
+                fmm_order: self.fmm_order,
+            },
+            self.softening,
+            self.threads,
+        )
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        // Checks the force method, integrator and boundaries work together, once the
+        // simulation is set up, as some combinations would otherwise silently drop an option
+        // The Hermite integrators use the open boundary Newtonian direct sum and its jerk
+        let hermite = self.integrator.ends_with("hermite");
+        if hermite && self.force_method != "direct" {
+            return Err("The Hermite integrators only support the direct force method".to_owned());
+        }
+        if self.box_size.is_some() {
+            if hermite {
+                return Err(
+                    "Periodic boundaries are not supported by the Hermite integrators".to_owned(),
+                );
+            }
+            if self.force_method != "direct" && self.force_method != "pm" {
+                return Err(
+                    "Periodic boundaries only support the direct and pm force methods".to_owned(),
+                );
+            }
+        }
+        if self.force_method == "fmm" && self.dimensions == 3 {
+            return Err("The fmm force method only supports two dimensional runs".to_owned());
+        }
+        if self.external.is_some() && (self.box_size.is_some() || hermite) {
+            return Err(
+                "External potentials need open boundaries and an integrator other than Hermite"
+                    .to_owned(),
+            );
+        }
+        if self.post_newtonian != PostNewtonian::None {
+            if self.box_size.is_some() || hermite {
+                return Err("Post-Newtonian corrections need open boundaries and an integrator other than Hermite".to_owned());
+            }
+            if self.force_method != "direct" {
+                return Err(
+                    "Post-Newtonian corrections only support the direct force method".to_owned(),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn update(&mut self) {
+        // Advances the simulation by a single step of size h
+        if self.integrator == "euler" {
+            self.update_euler();
+        } else if self.integrator == "leap_frog" {
+            self.update_leap_frog();
+        } else if self.integrator == "rk4" {
+            self.update_rk4();
+        } else if self.integrator == "yoshida" {
+            self.update_yoshida();
+        } else if self.integrator == "hermite" {
+            self.update_hermite();
+        } else if self.integrator == "block_hermite" {
+            self.update_block_hermite();
+        } else {
+            panic!("Invalid integrator option");
+        }
+        self.iteration += 1;
+        self.t += self.h;
+        self.resolve_collisions();
+        self.wrap_positions();
+    }
+}
+
+pub fn compute_acc_vectors(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    method: &str,
+    parameters: ForceParameters,
+    softening: Softening,
+    threads: usize,
+) -> VectorArray {
+    // Dispatches to the requested force solver, with open boundaries
+    // barnes_hut builds a quadtree, suited to two dimensions, and the octree methods suit three
+    // pm solves on a three dimensional grid, see particle_mesh.rs, and fmm, see fmm.rs, only
+    // expands the field in the x-y plane, so is only meant for bodies with z = 0
+    // Bodies are split between threads, see parallel.rs, except by the pairwise direct sum
+    // and particle mesh
+    let theta = parameters.theta;
+    if method == "direct" {
+        generate_acc_vectors(position_vectors, mass_vectors, softening, threads)
+    } else if method == "pairwise" {
+        pairwise::generate_acc_vectors_pairwise(position_vectors, mass_vectors, softening)
+    } else if method == "barnes_hut" {
+        quadtree::generate_acc_vectors_barnes_hut(
+            position_vectors,
+            mass_vectors,
+            theta,
+            softening,
+            threads,
+        )
+    } else if method == "octree" {
+        octree::generate_acc_vectors_octree(
+            position_vectors,
+            mass_vectors,
+            theta,
+            softening,
+            false,
+            threads,
+        )
+    } else if method == "octree_quadrupole" {
+        octree::generate_acc_vectors_octree(
+            position_vectors,
+            mass_vectors,
+            theta,
+            softening,
+            true,
+            threads,
+        )
+    } else if method == "pm" {
+        particle_mesh::generate_acc_vectors_pm(
+            position_vectors,
+            mass_vectors,
+            parameters.pm_grid,
+            None,
+            3,
+            &GreenCache::default(),
+        )
+    } else if method == "fmm" {
+        fmm::generate_acc_vectors_fmm(
+            position_vectors,
+            mass_vectors,
+            parameters.fmm_order,
+            softening,
+            threads,
+        )
+    } else {
+        panic!("Invalid force method option");
+    }
+}
+
+pub fn active_count(mass_vectors: &[Float]) -> usize {
+    // Bodies up to the last with non zero mass, which are the only ones exerting forces
+    // Test particles after them are skipped as sources, so direct sums cost
+    // O(active_count * n) rather than O(n^2)
+    mass_vectors
+        .iter()
+        .rposition(|&mass| mass != 0.0)
+        .map_or(0, |last| last + 1)
+}
+
+pub fn generate_acc_vectors(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+    threads: usize,
+) -> VectorArray {
+    let n = mass_vectors.len();
+    let active = active_count(mass_vectors);
+    let accs = parallel::map_bodies(n, threads, |i| {
+        let mut acc_x = 0.0;
+        let mut acc_y = 0.0;
+        let mut acc_z = 0.0;
+
+        for j in 0..active {
+            if j != i {
+                let acc = compute_m_by_r_sq(
+                    mass_vectors[j],
+                    position_vectors.at(i),
+                    position_vectors.at(j),
+                    softening,
+                );
+                acc_x += acc.0;
+                acc_y += acc.1;
+                acc_z += acc.2;
+            }
+        }
+        (acc_x, acc_y, acc_z)
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+pub fn compute_m_by_r_sq(
+    m: Float,
+    position1: (Float, Float, Float),
+    position2: (Float, Float, Float),
+    softening: Softening,
+) -> (Float, Float, Float) {
+    // Acceleration on body 1 from a mass m at body 2
+    let rx = position2.0 - position1.0;
+    let ry = position2.1 - position1.1;
+    let rz = position2.2 - position1.2;
+    let rmag_sq = rx.powi(2) + ry.powi(2) + rz.powi(2);
+    let a = m * softening.force_factor(rmag_sq);
+
+    (a * rx, a * ry, a * rz)
+}
+
+pub fn generate_acc_jerk_vectors(
+    position_vectors: &VectorArray,
+    velocity_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+    threads: usize,
+) -> (VectorArray, VectorArray) {
+    // Direct sum of accelerations along with their time derivatives (jerk)
+    let bodies: Vec<usize> = (0..mass_vectors.len()).collect();
+    generate_acc_jerk_vectors_of(
+        &bodies,
+        position_vectors,
+        velocity_vectors,
+        mass_vectors,
+        softening,
+        threads,
+    )
+}
+
+pub fn generate_acc_jerk_vectors_of(
+    bodies: &[usize],
+    position_vectors: &VectorArray,
+    velocity_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+    threads: usize,
+) -> (VectorArray, VectorArray) {
+    // As generate_acc_jerk_vectors(), for only the listed bodies, in the order listed
+    // Forces still come from every massive body
+    let active = active_count(mass_vectors);
+    let acc_jerks = parallel::map_bodies(bodies.len(), threads, |k| {
+        let i = bodies[k];
+        let mut acc = (0.0, 0.0, 0.0);
+        let mut jerk = (0.0, 0.0, 0.0);
+
+        for j in 0..active {
+            if j != i {
+                let (acc_j, jerk_j) = compute_m_by_r_sq_jerk(
+                    mass_vectors[j],
+                    position_vectors.at(i),
+                    velocity_vectors.at(i),
+                    position_vectors.at(j),
+                    velocity_vectors.at(j),
+                    softening,
+                );
+                acc = (acc.0 + acc_j.0, acc.1 + acc_j.1, acc.2 + acc_j.2);
+                jerk = (jerk.0 + jerk_j.0, jerk.1 + jerk_j.1, jerk.2 + jerk_j.2);
+            }
+        }
+        (acc, jerk)
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    let mut jerk_vectors = VectorArray::zeros(0);
+    for (acc, jerk) in acc_jerks {
+        acc_vectors.push(acc);
+        jerk_vectors.push(jerk);
+    }
+
+    (acc_vectors, jerk_vectors)
+}
+
+pub fn compute_m_by_r_sq_jerk(
+    m: Float,
+    position1: (Float, Float, Float),
+    velocity1: (Float, Float, Float),
+    position2: (Float, Float, Float),
+    velocity2: (Float, Float, Float),
+    softening: Softening,
+) -> ((Float, Float, Float), (Float, Float, Float)) {
+    // Acceleration on body 1 from body 2 as in compute_m_by_r_sq(), and its time derivative
+    // j = m * (g v + (dg/dr / r) (r.v) r), with g the force factor of the softening kernel
+    let rx = position2.0 - position1.0;
+    let ry = position2.1 - position1.1;
+    let rz = position2.2 - position1.2;
+    let vx = velocity2.0 - velocity1.0;
+    let vy = velocity2.1 - velocity1.1;
+    let vz = velocity2.2 - velocity1.2;
+    let rmag_sq = rx.powi(2) + ry.powi(2) + rz.powi(2);
+    let a = m * softening.force_factor(rmag_sq);
+    let b = m * softening.jerk_factor(rmag_sq);
+    let r_dot_v = rx * vx + ry * vy + rz * vz;
+
+    let jerk_x = a * vx + b * r_dot_v * rx;
+    let jerk_y = a * vy + b * r_dot_v * ry;
+    let jerk_z = a * vz + b * r_dot_v * rz;
+
+    ((a * rx, a * ry, a * rz), (jerk_x, jerk_y, jerk_z))
+}
+
+pub fn update_euler<'a>(
+    position_vectors: &'a mut VectorArray,
+    velocity_vectors: &'a mut VectorArray,
+    acc_vectors: &'a VectorArray,
+    h: Float,
+) {
+    for i in 0..position_vectors.x.len() {
+        velocity_vectors.x[i] += h * acc_vectors.x[i];
+        velocity_vectors.y[i] += h * acc_vectors.y[i];
+        velocity_vectors.z[i] += h * acc_vectors.z[i];
+
+        position_vectors.x[i] += h * velocity_vectors.x[i];
+        position_vectors.y[i] += h * velocity_vectors.y[i];
+        position_vectors.z[i] += h * velocity_vectors.z[i];
+    }
+}
+
+pub fn print_vector_array(vector_array: &VectorArray, name: &str) {
+    println!("{name}");
+    for i in 0..vector_array.x.len() {
+        let (x, y, z) = vector_array.at(i);
+        println!("{i}: x = {x}, y = {y}, z = {z}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::external::ExternalPotential;
+    use crate::initial_conditions::{generate_plummer, generate_uniform_box};
+    use crate::post_newtonian::PostNewtonian;
+    use crate::softening::Softening;
+    use crate::{
+        compute_acc_vectors, compute_m_by_r_sq, compute_m_by_r_sq_jerk, generate_acc_jerk_vectors,
+        Float, ForceParameters, Simulation,
+    };
+
+    const FLOAT_TOLERANCE: Float = 0.000_1;
+
+    #[test]
+    fn test_every_method_dispatched() {
+        // Every force method named on the command line goes through compute_acc_vectors(),
+        // and agrees with the direct sum to within its accuracy. FMM is planar, and the
+        // particle mesh is too coarse for a planar box, so it is checked in a cube
+        for (depth, methods) in [
+            (
+                0.0,
+                vec![
+                    "pairwise",
+                    "barnes_hut",
+                    "octree",
+                    "octree_quadrupole",
+                    "fmm",
+                ],
+            ),
+            (640.0, vec!["octree", "pm"]),
+        ] {
+            let (mass_vectors, position_vectors, _) =
+                generate_uniform_box(300, 640.0, 640.0, depth, 5);
+            let forces = |method| {
+                compute_acc_vectors(
+                    &position_vectors,
+                    &mass_vectors,
+                    method,
+                    ForceParameters::default(),
+                    Softening::default(),
+                    1,
+                )
+            };
+            let direct = forces("direct");
+            let size = |(x, y, z): (Float, Float, Float)| (x * x + y * y + z * z).sqrt();
+            let total: Float = (0..300).map(|i| size(direct.at(i))).sum();
+
+            for method in methods {
+                let acc_vectors = forces(method);
+                let error: Float = (0..300)
+                    .map(|i| {
+                        let (ax, ay, az) = acc_vectors.at(i);
+                        let (dx, dy, dz) = direct.at(i);
+                        size((ax - dx, ay - dy, az - dz))
+                    })
+                    .sum();
+                assert!(error < 0.05 * total, "{method}: {}", error / total);
+            }
+        }
+    }
+
+    #[test]
+    fn test_threads_match_serial() {
+        // Each body's sum runs in the same order on whichever thread it is given to,
+        // so results are identical rather than merely close
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            generate_plummer(1000, 1.0e7, 100.0, 3);
+
+        for method in ["direct", "barnes_hut", "octree", "octree_quadrupole", "fmm"] {
+            let serial = compute_acc_vectors(
+                &position_vectors,
+                &mass_vectors,
+                method,
+                ForceParameters::default(),
+                Softening::default(),
+                1,
+            );
+            for threads in [2, 3, 0] {
+                let parallel = compute_acc_vectors(
+                    &position_vectors,
+                    &mass_vectors,
+                    method,
+                    ForceParameters::default(),
+                    Softening::default(),
+                    threads,
+                );
+                assert_eq!(parallel.x, serial.x);
+                assert_eq!(parallel.y, serial.y);
+                assert_eq!(parallel.z, serial.z);
+            }
+        }
+
+        let serial = generate_acc_jerk_vectors(
+            &position_vectors,
+            &velocity_vectors,
+            &mass_vectors,
+            Softening::default(),
+            1,
+        );
+        let parallel = generate_acc_jerk_vectors(
+            &position_vectors,
+            &velocity_vectors,
+            &mass_vectors,
+            Softening::default(),
+            4,
+        );
+        assert_eq!(parallel.0.x, serial.0.x);
+        assert_eq!(parallel.1.z, serial.1.z);
+    }
+
+    #[test]
+    fn test_compute_m_by_r_sq() {
+        let earth_mass = 3.986_004_4 * Float::powi(10.0, 14);
+        let earth_radius = 6371.0 * Float::powi(10.0, 3);
+        let earth_surface_acc = 9.82025;
+        let origin = (0.0, 0.0, 0.0);
+
+        let (acc_x, acc_y, acc_z) = compute_m_by_r_sq(
+            earth_mass,
+            (earth_radius, 0.0, 0.0),
+            origin,
+            Softening::default(),
+        );
+        print!("{acc_x}, {acc_y}");
+
+        assert!((acc_x + earth_surface_acc).abs() < FLOAT_TOLERANCE);
+        assert!((acc_y - 0.0).abs() < FLOAT_TOLERANCE);
+        assert!((acc_z - 0.0).abs() < FLOAT_TOLERANCE);
+
+        let (acc_x, acc_y, _) = compute_m_by_r_sq(
+            earth_mass,
+            (0.0, earth_radius, 0.0),
+            origin,
+            Softening::default(),
+        );
+        assert!((acc_x - 0.0).abs() < FLOAT_TOLERANCE);
+        assert!((acc_y + earth_surface_acc).abs() < FLOAT_TOLERANCE);
+
+        let (acc_x, _, acc_z) = compute_m_by_r_sq(
+            earth_mass,
+            (0.0, 0.0, earth_radius),
+            origin,
+            Softening::default(),
+        );
+        assert!((acc_x - 0.0).abs() < FLOAT_TOLERANCE);
+        assert!((acc_z + earth_surface_acc).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_compute_m_by_r_sq_jerk() {
+        // Jerk should match a finite difference of the acceleration along the relative motion
+        // The separation of about 20 lies inside the spline kernel's support, and outside it
+        for softening in [
+            Softening::default(),
+            Softening::Spline(30.0),
+            Softening::Spline(8.0),
+        ] {
+            let m = 1000.0;
+            let position1 = (3.0, -4.0, 2.0);
+            let velocity1 = (0.5, 1.0, -0.5);
+            let position2 = (20.0, 7.0, -1.0);
+            let velocity2 = (-1.5, 2.0, 1.0);
+            let dt = 0.01;
+            let moved =
+                |position: (Float, Float, Float), velocity: (Float, Float, Float), dt: Float| {
+                    (
+                        position.0 + velocity.0 * dt,
+                        position.1 + velocity.1 * dt,
+                        position.2 + velocity.2 * dt,
+                    )
+                };
+
+            let (acc, jerk) =
+                compute_m_by_r_sq_jerk(m, position1, velocity1, position2, velocity2, softening);
+            let acc_later = compute_m_by_r_sq(
+                m,
+                moved(position1, velocity1, dt),
+                moved(position2, velocity2, dt),
+                softening,
+            );
+            let acc_earlier = compute_m_by_r_sq(
+                m,
+                moved(position1, velocity1, -dt),
+                moved(position2, velocity2, -dt),
+                softening,
+            );
+
+            assert_eq!(acc, compute_m_by_r_sq(m, position1, position2, softening));
+            for (jerk, later, earlier) in [
+                (jerk.0, acc_later.0, acc_earlier.0),
+                (jerk.1, acc_later.1, acc_earlier.1),
+                (jerk.2, acc_later.2, acc_earlier.2),
+            ] {
+                assert!((jerk - (later - earlier) / (2.0 * dt)).abs() < 0.01 * jerk.abs());
+            }
+        }
+    }
+
+    #[test]
+    fn test_validate() {
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            generate_uniform_box(10, 100.0, 100.0, 0.0, 2);
+        let simulation = |force_method, integrator| {
+            Simulation::new(
+                mass_vectors.clone(),
+                position_vectors.clone(),
+                velocity_vectors.clone(),
+                force_method,
+                0.5,
+                integrator,
+                Softening::default(),
+            )
+        };
+        assert!(simulation("direct", "hermite").validate().is_ok());
+        assert!(simulation("barnes_hut", "leap_frog").validate().is_ok());
+        assert!(simulation("barnes_hut", "block_hermite")
+            .validate()
+            .is_err());
+
+        let mut periodic = simulation("pm", "leap_frog");
+        periodic.box_size = Some(200.0);
+        assert!(periodic.validate().is_ok());
+        periodic.force_method = "octree".to_owned();
+        assert!(periodic.validate().is_err());
+        periodic.force_method = "direct".to_owned();
+        periodic.integrator = "hermite".to_owned();
+        assert!(periodic.validate().is_err());
+
+        let mut fmm = simulation("fmm", "leap_frog");
+        fmm.dimensions = 3;
+        assert!(fmm.validate().is_err());
+
+        let mut external = simulation("direct", "hermite");
+        external.external = Some(ExternalPotential::Kepler { mass: 1.0 });
+        assert!(external.validate().is_err());
+
+        let mut post_newtonian = simulation("pairwise", "rk4");
+        post_newtonian.post_newtonian = PostNewtonian::FirstOrder(100.0);
+        assert!(post_newtonian.validate().is_err());
+        post_newtonian.force_method = "direct".to_owned();
+        assert!(post_newtonian.validate().is_ok());
+    }
+}
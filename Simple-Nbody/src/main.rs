@@ -1,135 +1,289 @@
-use ::rand::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
 use macroquad::prelude::*;
+use simple_nbody::camera::Camera;
+use simple_nbody::checkpoint::read_checkpoint;
+use simple_nbody::collisions::Collisions;
+use simple_nbody::config::{Config, USAGE};
+use simple_nbody::diagnostics::Diagnostics;
+use simple_nbody::external::ExternalPotential;
+use simple_nbody::initial_conditions::*;
+use simple_nbody::post_newtonian::PostNewtonian;
+use simple_nbody::scenario::{solar_system, toomre_encounter};
+use simple_nbody::snapshot::run_headless;
+use simple_nbody::softening::Softening;
+use simple_nbody::units::UnitSystem;
+use simple_nbody::*;
 
-pub const G: f32 = 6.67430 / 100_000_000_000.0;
-pub const H: f32 = 0.01;
 pub const SAVE_SCREENSHOT: bool = false;
-pub struct VectorArray {
-    pub x: Vec<f32>,
-    pub y: Vec<f32>,
+pub const LOG_DIAGNOSTICS: bool = false;
+// Camera rotation per frame in radians, and zoom factor per frame, while a key is held
+pub const CAMERA_ROTATE_SPEED: f32 = 0.02;
+pub const CAMERA_ZOOM_SPEED: f32 = 1.02;
+// Test particles are drawn faded, to tell them from the massive bodies
+pub const TEST_PARTICLE_COLOR: Color = Color::new(0.5, 0.7, 1.0, 0.6);
+
+fn new_simulation(config: &Config) -> Simulation {
+    // The thread count is not stored in checkpoints, so is set for restarted runs too
+    let mut simulation = load_simulation(config);
+    simulation.threads = config.threads;
+
+    simulation
 }
 
-fn generate_mass_vectors(n: u32) -> Vec<f32> {
-    let mut mass_vectors: Vec<f32> = Vec::new();
-    let mut rng = thread_rng();
-
-    for _i in 0..n {
-        mass_vectors.push(rng.gen());
-    }
-
-    for i in 0..mass_vectors.len() {
-        mass_vectors[i] = G * mass_vectors[i] * 10_f32.powi(15);
+fn load_simulation(config: &Config) -> Simulation {
+    if let Some(path) = &config.restart {
+        match read_checkpoint(Path::new(path)) {
+            Ok(simulation) => return simulation,
+            Err(error) => {
+                eprintln!("Failed reading checkpoint {path}: {error}");
+                std::process::exit(1);
+            }
+        }
     }
 
-    return mass_vectors;
-}
-
-fn generate_velocity_vectors(n: u32) -> VectorArray {
-    let mut velocity_vectors = VectorArray {
-        x: Vec::new(),
-        y: Vec::new(),
+    let softening = match Softening::from_name(&config.softening, config.epsilon) {
+        Ok(softening) => softening,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
     };
-    let mut rng = thread_rng();
-
-    for _i in 0..n {
-        let vel_x: f32 = (rng.gen::<f32>() - 0.5) * 5.0;
-        let vel_y: f32 = (rng.gen::<f32>() - 0.5) * 5.0;
-        velocity_vectors.x.push(vel_x);
-        velocity_vectors.y.push(vel_y);
-    }
-
-    return velocity_vectors;
-}
-
-fn generate_position_vectors(n: u32) -> VectorArray {
-    let mut position_vectors = VectorArray {
-        x: Vec::new(),
-        y: Vec::new(),
+    let collisions = match Collisions::from_name(&config.collisions, config.restitution) {
+        Ok(collisions) => collisions,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
     };
-    let mut rng = thread_rng();
-
-    for _i in 0..n {
-        position_vectors
-            .x
-            .push((rng.gen::<f32>() - 0.5) * screen_width() * 0.5 + screen_width() * 0.5);
-        position_vectors
-            .y
-            .push((rng.gen::<f32>() - 0.5) * screen_height() * 0.5 + screen_height() * 0.5);
+    let units = match UnitSystem::from_name(&config.units) {
+        Ok(units) => units,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
+    };
+    let total_mass = units.simulation_mass(config.total_mass);
+    let mut speed_of_light = config.speed_of_light;
+    if speed_of_light <= 0.0 {
+        speed_of_light = units.speed_of_light() as Float;
     }
-
-    return position_vectors;
-}
-
-fn generate_acc_vectors(position_vectors: &VectorArray, mass_vectors: &Vec<f32>) -> VectorArray {
-    let mut acc_vectors = VectorArray {
-        x: Vec::new(),
-        y: Vec::new(),
+    let post_newtonian = match PostNewtonian::from_name(&config.post_newtonian, speed_of_light) {
+        Ok(post_newtonian) => post_newtonian,
+        Err(error) => {
+            eprintln!("{error}");
+            std::process::exit(1);
+        }
     };
-    let n = mass_vectors.len();
-
-    for i in 0..n {
-        let mut acc_x = 0.0;
-        let mut acc_y = 0.0;
-
-        for j in 0..n {
-            if j != i {
-                let acc = compute_m_by_r_sq(
-                    mass_vectors[j],
-                    position_vectors.x[i],
-                    position_vectors.y[i],
-                    position_vectors.x[j],
-                    position_vectors.y[j],
-                );
-                acc_x = acc_x + acc.0;
-                acc_y = acc_y + acc.1;
+    let mut external = None;
+    if !config.external.is_empty() {
+        match ExternalPotential::from_spec(&config.external, &units) {
+            Ok(potential) => external = Some(potential),
+            Err(error) => {
+                eprintln!("{error}");
+                std::process::exit(1);
             }
         }
-        acc_vectors.x.push(acc_x);
-        acc_vectors.y.push(acc_y);
+    }
+    // The box and toomre masses are G times masses in metres and seconds, which only match
+    // the positions and velocities of units in metres and seconds
+    let generated_in_metres =
+        config.initial_conditions == "box" || config.initial_conditions == "toomre";
+    if generated_in_metres && (units.length != 1.0 || units.time != 1.0) {
+        eprintln!(
+            "The {} initial conditions need nbody or si units",
+            config.initial_conditions
+        );
+        std::process::exit(1);
+    }
+    if config.initial_conditions == "toomre" && config.n < 4 {
+        eprintln!("The toomre initial conditions need --n of at least 4, two bodies per galaxy");
+        std::process::exit(1);
+    }
+    let seed = config.seed.unwrap_or_else(::rand::random);
+    println!("seed: {seed}");
+    // Bodies are generated in three dimensions, and flattened onto the plane for 2D runs
+    // In a periodic box, the uniform box fills it
+    let (mut box_width, mut box_height, mut box_depth) =
+        (config.box_width, config.box_height, config.box_depth);
+    if config.periodic > 0.0 {
+        (box_width, box_height, box_depth) = (config.periodic, config.periodic, config.periodic);
+    }
+    if config.dimensions == 2 {
+        box_depth = 0.0;
+    }
+    let (mass_vectors, mut position_vectors, mut velocity_vectors) =
+        match config.initial_conditions.as_str() {
+            "box" => generate_uniform_box(config.n, box_width, box_height, box_depth, seed),
+            "plummer" => generate_plummer(config.n, total_mass, config.scale_radius, seed),
+            "hernquist" => generate_hernquist(config.n, total_mass, config.scale_radius, seed),
+            "toomre" => toomre_encounter(config.n, softening, seed),
+            "solar_system" => match solar_system(Path::new(&config.ephemeris), &units) {
+                Ok(bodies) => bodies,
+                Err(error) => {
+                    eprintln!("Failed reading ephemeris {}: {error}", config.ephemeris);
+                    std::process::exit(1);
+                }
+            },
+            other => {
+                eprintln!("Invalid initial conditions option {other}");
+                std::process::exit(1);
+            }
+        };
+    if config.dimensions == 2 {
+        flatten(&mut position_vectors, &mut velocity_vectors);
+    }
+    let mut simulation = Simulation::new(
+        mass_vectors,
+        position_vectors,
+        velocity_vectors,
+        &config.force_method,
+        config.theta,
+        &config.integrator,
+        softening,
+    );
+    simulation.h = config.h;
+    simulation.eta = config.eta;
+    simulation.seed = seed;
+    simulation.dimensions = config.dimensions;
+    simulation.units = units;
+    simulation.collisions = collisions;
+    simulation.density = units.simulation_mass(config.density);
+    simulation.pm_grid = config.pm_grid;
+    simulation.fmm_order = config.fmm_order;
+    simulation.external = external;
+    simulation.post_newtonian = post_newtonian;
+    if config.force_method == "pm"
+        || config.force_method == "fmm"
+        || simulation.external.is_some()
+        || post_newtonian != PostNewtonian::None
+    {
+        simulation.acc_vectors = simulation.compute_acc_vectors();
+    }
+    if config.test_particles > 0 {
+        simulation.add_test_particles(config.test_particles, config.test_radius, seed);
+    }
+    if config.periodic > 0.0 {
+        simulation.make_periodic(config.periodic);
+    }
+    if let Err(error) = simulation.validate() {
+        eprintln!("{error}");
+        std::process::exit(1);
     }
 
-    return acc_vectors;
+    simulation
 }
 
-fn compute_m_by_r_sq(m: f32, x1: f32, y1: f32, x2: f32, y2: f32) -> (f32, f32) {
-    let epsilon: f32 = 5.0;
-    let rx = x2 - x1;
-    let ry = y2 - y1;
-    let rmag_sq = rx.powi(2) + ry.powi(2);
-    let a = m / (rmag_sq + epsilon.powi(2)).powf(3.0 / 2.0);
-
-    return (a * rx, a * ry);
+#[allow(clippy::unnecessary_cast)]
+fn to_f32(value: Float) -> f32 {
+    // The simulation may run in double precision, but is drawn in single precision
+    value as f32
 }
 
-fn update_euler<'a>(
-    position_vectors: &'a mut VectorArray,
-    velocity_vectors: &'a mut VectorArray,
-    acc_vectors: &'a VectorArray,
-) {
-    for i in 0..position_vectors.x.len() {
-        // position_vectors.x[i] = 100.0;
-        velocity_vectors.x[i] = velocity_vectors.x[i] + H * acc_vectors.x[i];
-        velocity_vectors.y[i] = velocity_vectors.y[i] + H * acc_vectors.y[i];
-
-        position_vectors.x[i] = position_vectors.x[i] + H * velocity_vectors.x[i];
-        position_vectors.y[i] = position_vectors.y[i] + H * velocity_vectors.y[i];
+fn particle_color(i: usize, active: usize) -> Color {
+    // Bodies from active onwards are test particles
+    if i < active {
+        WHITE
+    } else {
+        TEST_PARTICLE_COLOR
     }
-
-    // return (position_vectors, velocity_vectors);
 }
 
-fn draw_particles(position_vectors: &VectorArray) {
+fn draw_particles(
+    position_vectors: &VectorArray,
+    count: usize,
+    active: usize,
+    x_centre: f32,
+    y_centre: f32,
+) {
+    // Simulation origin is drawn at the centre of the screen, with y upwards
+    // Only the first count bodies are drawn
     let circle_size = 1.0;
-    for i in 0..position_vectors.x.len() {
+    for i in 0..count {
         draw_circle(
-            position_vectors.x[i],
-            position_vectors.y[i],
+            x_centre + to_f32(position_vectors.x[i]),
+            y_centre - to_f32(position_vectors.y[i]),
             circle_size,
-            WHITE,
+            particle_color(i, active),
         );
     }
 }
 
+fn draw_particles_projected(
+    position_vectors: &VectorArray,
+    count: usize,
+    active: usize,
+    camera: &Camera,
+    x_centre: f32,
+    y_centre: f32,
+) {
+    // Nearer bodies are drawn larger, and bodies behind the camera are skipped
+    let circle_size = 1.0;
+    for i in 0..count {
+        let (x, y, z) = position_vectors.at(i);
+        if let Some((x, y, scale)) = camera.project((to_f32(x), to_f32(y), to_f32(z))) {
+            draw_circle(
+                x_centre + x,
+                y_centre - y,
+                (circle_size * scale).max(0.5),
+                particle_color(i, active),
+            );
+        }
+    }
+}
+
+fn update_camera(camera: &mut Camera) {
+    // Arrow keys orbit the camera around the origin, and + and - zoom in and out
+    if is_key_down(KeyCode::Left) {
+        camera.rotate(-CAMERA_ROTATE_SPEED, 0.0);
+    }
+    if is_key_down(KeyCode::Right) {
+        camera.rotate(CAMERA_ROTATE_SPEED, 0.0);
+    }
+    if is_key_down(KeyCode::Up) {
+        camera.rotate(0.0, CAMERA_ROTATE_SPEED);
+    }
+    if is_key_down(KeyCode::Down) {
+        camera.rotate(0.0, -CAMERA_ROTATE_SPEED);
+    }
+    if is_key_down(KeyCode::Equal) || is_key_down(KeyCode::KpAdd) {
+        camera.zoom(1.0 / CAMERA_ZOOM_SPEED);
+    }
+    if is_key_down(KeyCode::Minus) || is_key_down(KeyCode::KpSubtract) {
+        camera.zoom(CAMERA_ZOOM_SPEED);
+    }
+}
+
+fn draw_diagnostics(diagnostics: &Diagnostics, initial: &Diagnostics, t: Float) {
+    let lines = [
+        format!("t: {:.2}", t),
+        format!("Total Energy: {:.5e}", diagnostics.total_energy),
+        format!("Virial Ratio: {:.3}", diagnostics.virial_ratio()),
+        format!("Energy Drift: {:.3e}", diagnostics.energy_drift(initial)),
+        format!(
+            "Momentum Drift: {:.3e}",
+            diagnostics.momentum_drift(initial)
+        ),
+        format!(
+            "Angular Momentum Drift: {:.3e}",
+            diagnostics.angular_momentum_drift(initial)
+        ),
+        format!(
+            "Centre of Mass: ({:.1}, {:.1}, {:.1})",
+            diagnostics.centre_of_mass.0,
+            diagnostics.centre_of_mass.1,
+            diagnostics.centre_of_mass.2
+        ),
+        format!("FPS: {}", get_fps()),
+    ];
+
+    for (i, line) in lines.iter().enumerate() {
+        draw_text(line, 10.0, 20.0 * (i + 1) as f32, 20.0, GRAY);
+    }
+}
+
 fn window_conf() -> Conf {
     Conf {
         window_title: "Simple NBody".to_owned(),
@@ -139,31 +293,105 @@ fn window_conf() -> Conf {
     }
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
-    let mut iter = 0;
-    let n = 500;
-    let mass_vectors = generate_mass_vectors(n);
-    let mut velocity_vectors = generate_velocity_vectors(n);
-    let mut position_vectors = generate_position_vectors(n);
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let config = match Config::from_args(&args) {
+        Ok(config) => config,
+        Err(message) => {
+            if message == USAGE {
+                println!("{message}");
+                std::process::exit(0);
+            }
+            eprintln!("{message}");
+            std::process::exit(1);
+        }
+    };
 
-    // print_vector_array(&position_vectors, &"Position Vectors".to_owned());
-    // print_vector_array(&velocity_vectors, &"Velocity Vectors".to_owned());
+    if config.headless {
+        let mut simulation = new_simulation(&config);
+        let output_dir = Path::new(&config.output_dir);
+        if let Err(error) = run_headless(
+            &mut simulation,
+            config.steps,
+            config.snapshot_every,
+            config.checkpoint_every,
+            output_dir,
+            config.show_test_particles,
+            |simulation| println!("iteration {}, t = {}", simulation.iteration, simulation.t),
+        ) {
+            eprintln!("Failed writing snapshots to {}: {error}", config.output_dir);
+            std::process::exit(1);
+        }
+    } else {
+        macroquad::Window::from_config(window_conf(), run_window(config));
+    }
+}
 
-    loop {
-        let acc_vectors = generate_acc_vectors(&position_vectors, &mass_vectors);
-        // let (&position_vectors, &velocity_vectors) =  -> (&'a VectorArray, &'a VectorArray)
+async fn run_window(config: Config) {
+    let mut iter = 0;
+    let mut simulation = new_simulation(&config);
+
+    let initial_diagnostics = simulation.diagnostics();
+    let mut show_ui = true;
+    let mut camera = Camera::new(2.0 * screen_height());
+
+    // Per step drift of conserved quantities, written as csv
+    let mut diagnostics_log = None;
+    if LOG_DIAGNOSTICS {
+        let mut log = BufWriter::new(File::create("./diagnostics.csv").unwrap());
+        writeln!(log, "# seed {}", simulation.seed).unwrap();
+        writeln!(log, "{}", Diagnostics::log_header()).unwrap();
+        diagnostics_log = Some(log);
+    }
 
-        // if is_key_pressed(KeyCode::Space) {
-        update_euler(&mut position_vectors, &mut velocity_vectors, &acc_vectors);
-        // print_vector_array(&position_vectors, &"Position Vectors".to_owned());
-        // print_vector_array(&velocity_vectors, &"Velocity Vectors".to_owned());
-        // }
+    loop {
+        simulation.update();
 
         clear_background(BLACK);
-        draw_particles(&position_vectors);
+        let x_centre = screen_width() / 2.0;
+        let y_centre = screen_height() / 2.0;
+        let active = active_count(&simulation.mass_vectors);
+        let mut count = simulation.mass_vectors.len();
+        if !config.show_test_particles {
+            count = active;
+        }
+        if simulation.dimensions == 3 {
+            update_camera(&mut camera);
+            draw_particles_projected(
+                &simulation.position_vectors,
+                count,
+                active,
+                &camera,
+                x_centre,
+                y_centre,
+            );
+        } else {
+            draw_particles(
+                &simulation.position_vectors,
+                count,
+                active,
+                x_centre,
+                y_centre,
+            );
+        }
 
-        if SAVE_SCREENSHOT == true {
+        // Potential energy is O(n^2), so diagnostics are only computed when needed
+        if show_ui || diagnostics_log.is_some() {
+            let diagnostics = simulation.diagnostics();
+            if show_ui {
+                draw_diagnostics(&diagnostics, &initial_diagnostics, simulation.t);
+            }
+            if let Some(log) = diagnostics_log.as_mut() {
+                let line =
+                    diagnostics.log_line(simulation.iteration, simulation.t, &initial_diagnostics);
+                writeln!(log, "{}", line).unwrap();
+            }
+        }
+        if is_key_pressed(KeyCode::F2) {
+            show_ui = !show_ui;
+        }
+
+        if SAVE_SCREENSHOT {
             let path = format!("./screenshots/{}.png", iter);
             let screenshot = get_screen_data();
             screenshot.export_png(&path);
@@ -172,36 +400,3 @@ async fn main() {
         next_frame().await
     }
 }
-
-fn print_vector_array(vector_array: &VectorArray, name: &String) {
-    print!("{name}\n");
-    for i in 0..vector_array.x.len() {
-        let x = vector_array.x[i];
-        let y = vector_array.y[i];
-        print!("{i}: x = {x}, y = {y}\n");
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use crate::{compute_m_by_r_sq, G};
-
-    const FLOAT_TOLERANCE: f32 = 0.000_1;
-
-    #[test]
-    fn test_compute_m_by_r_sq() {
-        let earth_mass = 3.986004418 * 10_f32.powi(14);
-        let earth_radius = 6371.0 * 10_f32.powi(3);
-        let earth_surface_acc = 9.82025;
-
-        let (acc_x, acc_y) = compute_m_by_r_sq(earth_mass, earth_radius, 0.0, 0.0, 0.0);
-        print!("{acc_x}, {acc_y}");
-
-        assert!((acc_x + earth_surface_acc).abs() < FLOAT_TOLERANCE);
-        assert!((acc_y - 0.0).abs() < FLOAT_TOLERANCE);
-
-        let (acc_x, acc_y) = compute_m_by_r_sq(earth_mass, 0.0, earth_radius, 0.0, 0.0);
-        assert!((acc_x - 0.0).abs() < FLOAT_TOLERANCE);
-        assert!((acc_y + earth_surface_acc).abs() < FLOAT_TOLERANCE);
-    }
-}
@@ -0,0 +1,535 @@
+use crate::parallel::map_bodies;
+use crate::softening::Softening;
+use crate::{compute_m_by_r_sq, Float, VectorArray};
+
+// As for the quadtree, deep enough to separate distinct positions while stopping coincident
+// particles from partitioning forever
+pub const OCTREE_MAX_DEPTH: u32 = 32;
+
+// Three dimensional counterpart of the quadtree, dividing space into eight cubic octants
+pub struct Octree {
+    // Coordinates of center of octree
+    pub center: (Float, Float, Float),
+    // Side length of the cube
+    pub width: Float,
+    pub current_depth: u32,
+    pub max_depth: u32,
+    // Index of particles contained within the tree, only populated for leaves
+    pub contents: Vec<usize>,
+    // Mass of tree
+    pub mass: Float,
+    // Location of center of mass
+    pub com: (Float, Float, Float),
+    // Traceless quadrupole moment about the center of mass, sum of m (3 d_i d_j - d^2 delta_ij)
+    // Stored as xx, xy, xz, yy, yz, zz
+    pub quadrupole: [Float; 6],
+    // Empty for leaves, otherwise eight octants indexed as in find_octant()
+    pub children: Vec<Octree>,
+}
+
+impl Octree {
+    pub fn new(center: (Float, Float, Float), width: Float, max_depth: u32) -> Octree {
+        // Creates new root Octree
+        Octree {
+            center,
+            width,
+            current_depth: 0,
+            max_depth,
+            contents: Vec::new(),
+            mass: 0.0,
+            com: center,
+            quadrupole: [0.0; 6],
+            children: Vec::new(),
+        }
+    }
+
+    pub fn from_positions(
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        max_depth: u32,
+    ) -> Octree {
+        // Builds a cubic tree enclosing all particles, with mass moments computed
+        let mut min = (Float::INFINITY, Float::INFINITY, Float::INFINITY);
+        let mut max = (
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+            Float::NEG_INFINITY,
+        );
+        for i in 0..position_vectors.x.len() {
+            let (x, y, z) = position_vectors.at(i);
+            min = (min.0.min(x), min.1.min(y), min.2.min(z));
+            max = (max.0.max(x), max.1.max(y), max.2.max(z));
+        }
+        if position_vectors.x.is_empty() {
+            (min, max) = ((0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+        }
+
+        // Padded slightly so particles on the bounding box edge are still inside the root
+        let width = (max.0 - min.0).max(max.1 - min.1).max(max.2 - min.2) * 1.01 + Float::EPSILON;
+        let center = (
+            0.5 * (min.0 + max.0),
+            0.5 * (min.1 + max.1),
+            0.5 * (min.2 + max.2),
+        );
+
+        let mut octree = Octree::new(center, width, max_depth);
+        for i in 0..position_vectors.x.len() {
+            octree.insert(i, position_vectors);
+        }
+        octree.compute_mass_distribution(position_vectors, mass_vectors);
+
+        octree
+    }
+
+    pub fn find_octant(&self, x: Float, y: Float, z: Float) -> usize {
+        // Octants are indexed by the sign of the offset from center
+        // Right handed axes, x right, y upwards, z towards the viewer
+        // 0: +++ : right, top, front
+        // 1: ++- : right, top, rear
+        // 2: +-+ : right, bottom, front
+        // 3: +-- : right, bottom, rear
+        // 4: -++ : left, top, front
+        // 5: -+- : left, top, rear
+        // 6: --+ : left, bottom, front
+        // 7: --- : left, bottom, rear
+        let mut index = 0;
+        if x <= self.center.0 {
+            index += 4;
+        }
+        if y <= self.center.1 {
+            index += 2;
+        }
+        if z <= self.center.2 {
+            index += 1;
+        }
+
+        index
+    }
+
+    pub fn contains_point(&self, x: Float, y: Float, z: Float) -> bool {
+        let half_width = 0.5 * self.width;
+        (x - self.center.0).abs() <= half_width
+            && (y - self.center.1).abs() <= half_width
+            && (z - self.center.2).abs() <= half_width
+    }
+
+    pub fn insert(&mut self, index: usize, position_vectors: &VectorArray) {
+        // Inserts particle index into the tree, partitioning leaves holding more than one particle
+        if self.children.is_empty() {
+            self.contents.push(index);
+            if self.contents.len() > 1 && self.current_depth < self.max_depth {
+                self.partition(position_vectors);
+            }
+        } else {
+            let (x, y, z) = position_vectors.at(index);
+            let octant = self.find_octant(x, y, z);
+            self.children[octant].insert(index, position_vectors);
+        }
+    }
+
+    fn partition(&mut self, position_vectors: &VectorArray) {
+        // Splits a leaf into eight octants and moves its particles into them
+        let width = 0.5 * self.width;
+        let offset = 0.5 * width;
+
+        for octant in 0..8 {
+            let x_offset = if octant < 4 { offset } else { -offset };
+            let y_offset = if octant % 4 < 2 { offset } else { -offset };
+            let z_offset = if octant % 2 == 0 { offset } else { -offset };
+            let center = (
+                self.center.0 + x_offset,
+                self.center.1 + y_offset,
+                self.center.2 + z_offset,
+            );
+
+            self.children.push(Octree {
+                current_depth: self.current_depth + 1,
+                ..Octree::new(center, width, self.max_depth)
+            });
+        }
+
+        let contents = std::mem::take(&mut self.contents);
+        for index in contents {
+            self.insert(index, position_vectors);
+        }
+    }
+
+    pub fn compute_mass_distribution(
+        &mut self,
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+    ) {
+        // Computes mass, center of mass and quadrupole moment for entire tree
+        let mut mass = 0.0;
+        let mut com_x = 0.0;
+        let mut com_y = 0.0;
+        let mut com_z = 0.0;
+
+        if self.children.is_empty() {
+            for &j in &self.contents {
+                mass += mass_vectors[j];
+                com_x += mass_vectors[j] * position_vectors.x[j];
+                com_y += mass_vectors[j] * position_vectors.y[j];
+                com_z += mass_vectors[j] * position_vectors.z[j];
+            }
+        } else {
+            for child in &mut self.children {
+                child.compute_mass_distribution(position_vectors, mass_vectors);
+                mass += child.mass;
+                com_x += child.mass * child.com.0;
+                com_y += child.mass * child.com.1;
+                com_z += child.mass * child.com.2;
+            }
+        }
+
+        self.mass = mass;
+        if mass == 0.0 {
+            self.com = self.center;
+            return;
+        }
+        self.com = (com_x / mass, com_y / mass, com_z / mass);
+
+        // Second pass once the center of mass is known, shifting children's moments onto it
+        let mut quadrupole = [0.0; 6];
+        if self.children.is_empty() {
+            for &j in &self.contents {
+                let term = quadrupole_term(mass_vectors[j], position_vectors.at(j), self.com);
+                for k in 0..6 {
+                    quadrupole[k] += term[k];
+                }
+            }
+        } else {
+            for child in &self.children {
+                let term = quadrupole_term(child.mass, child.com, self.com);
+                for k in 0..6 {
+                    quadrupole[k] += child.quadrupole[k] + term[k];
+                }
+            }
+        }
+        self.quadrupole = quadrupole;
+    }
+
+    pub fn acc_at(
+        &self,
+        i: usize,
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        theta: Float,
+        softening: Softening,
+        use_quadrupole: bool,
+    ) -> (Float, Float, Float) {
+        // Walks the tree, summing the acceleration on particle i
+        // A node is approximated by its multipole expansion about its center of mass when its
+        // width over its distance from the particle is below theta, and the particle does not
+        // lie inside it. The expansion is the monopole, plus the quadrupole if use_quadrupole
+        if self.mass == 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+
+        let position = position_vectors.at(i);
+        let (x, y, z) = position;
+        let mut acc_x = 0.0;
+        let mut acc_y = 0.0;
+        let mut acc_z = 0.0;
+
+        if self.children.is_empty() {
+            for &j in &self.contents {
+                if j != i {
+                    let acc = compute_m_by_r_sq(
+                        mass_vectors[j],
+                        position,
+                        position_vectors.at(j),
+                        softening,
+                    );
+                    acc_x += acc.0;
+                    acc_y += acc.1;
+                    acc_z += acc.2;
+                }
+            }
+            return (acc_x, acc_y, acc_z);
+        }
+
+        let r =
+            ((self.com.0 - x).powi(2) + (self.com.1 - y).powi(2) + (self.com.2 - z).powi(2)).sqrt();
+        if !self.contains_point(x, y, z) && self.width < theta * r {
+            let acc = compute_m_by_r_sq(self.mass, position, self.com, softening);
+            if !use_quadrupole {
+                return acc;
+            }
+            let correction = self.quadrupole_acc(position);
+            return (
+                acc.0 + correction.0,
+                acc.1 + correction.1,
+                acc.2 + correction.2,
+            );
+        }
+
+        for child in &self.children {
+            let acc = child.acc_at(
+                i,
+                position_vectors,
+                mass_vectors,
+                theta,
+                softening,
+                use_quadrupole,
+            );
+            acc_x += acc.0;
+            acc_y += acc.1;
+            acc_z += acc.2;
+        }
+
+        (acc_x, acc_y, acc_z)
+    }
+
+    pub fn quadrupole_acc(&self, position: (Float, Float, Float)) -> (Float, Float, Float) {
+        // Acceleration at position from the quadrupole term of the potential,
+        // phi = -(r.Q.r) / (2 r^5) with r the offset from the center of mass, so
+        // a = Q.r / r^5 - 5 (r.Q.r) r / (2 r^7)
+        // Only used for distant nodes, so unsoftened
+        let rx = position.0 - self.com.0;
+        let ry = position.1 - self.com.1;
+        let rz = position.2 - self.com.2;
+        let [q_xx, q_xy, q_xz, q_yy, q_yz, q_zz] = self.quadrupole;
+
+        let q_rx = q_xx * rx + q_xy * ry + q_xz * rz;
+        let q_ry = q_xy * rx + q_yy * ry + q_yz * rz;
+        let q_rz = q_xz * rx + q_yz * ry + q_zz * rz;
+        let r_q_r = rx * q_rx + ry * q_ry + rz * q_rz;
+
+        let r_sq = rx.powi(2) + ry.powi(2) + rz.powi(2);
+        let inv_r5 = 1.0 / (r_sq * r_sq * r_sq.sqrt());
+        let radial = 2.5 * r_q_r * inv_r5 / r_sq;
+
+        (
+            q_rx * inv_r5 - radial * rx,
+            q_ry * inv_r5 - radial * ry,
+            q_rz * inv_r5 - radial * rz,
+        )
+    }
+}
+
+fn quadrupole_term(
+    mass: Float,
+    position: (Float, Float, Float),
+    origin: (Float, Float, Float),
+) -> [Float; 6] {
+    // Contribution m (3 d_i d_j - d^2 delta_ij) of a point mass to the quadrupole about origin
+    let dx = position.0 - origin.0;
+    let dy = position.1 - origin.1;
+    let dz = position.2 - origin.2;
+    let d_sq = dx.powi(2) + dy.powi(2) + dz.powi(2);
+
+    [
+        mass * (3.0 * dx * dx - d_sq),
+        mass * 3.0 * dx * dy,
+        mass * 3.0 * dx * dz,
+        mass * (3.0 * dy * dy - d_sq),
+        mass * 3.0 * dy * dz,
+        mass * (3.0 * dz * dz - d_sq),
+    ]
+}
+
+pub fn generate_acc_vectors_octree(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    theta: Float,
+    softening: Softening,
+    use_quadrupole: bool,
+    threads: usize,
+) -> VectorArray {
+    // Barnes-Hut approximation of generate_acc_vectors() in three dimensions
+    // The tree is built serially, and then walked for each body in parallel
+    let octree = Octree::from_positions(position_vectors, mass_vectors, OCTREE_MAX_DEPTH);
+    let accs = map_bodies(mass_vectors.len(), threads, |i| {
+        octree.acc_at(
+            i,
+            position_vectors,
+            mass_vectors,
+            theta,
+            softening,
+            use_quadrupole,
+        )
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::octree::{generate_acc_vectors_octree, Octree, OCTREE_MAX_DEPTH};
+    use crate::softening::Softening;
+    use crate::test_helpers::{random_particles, relative_error};
+    use crate::{generate_acc_vectors, Float, VectorArray};
+
+    const FLOAT_TOLERANCE: Float = 0.000_1;
+
+    #[test]
+    fn test_find_octant() {
+        let octree = Octree::new((0.0, 0.0, 0.0), 10.0, 5);
+
+        assert_eq!(octree.find_octant(1.0, 1.0, 1.0), 0);
+        assert_eq!(octree.find_octant(1.0, 1.0, -1.0), 1);
+        assert_eq!(octree.find_octant(1.0, -1.0, 1.0), 2);
+        assert_eq!(octree.find_octant(1.0, -1.0, -1.0), 3);
+        assert_eq!(octree.find_octant(-1.0, 1.0, 1.0), 4);
+        assert_eq!(octree.find_octant(-1.0, -1.0, -1.0), 7);
+    }
+
+    #[test]
+    fn test_partition_centers() {
+        // Each child's center lies in the octant find_octant() assigns it
+        let position_vectors = VectorArray {
+            x: vec![1.0, -1.0],
+            y: vec![1.0, -1.0],
+            z: vec![1.0, -1.0],
+        };
+        let octree = Octree::from_positions(&position_vectors, &[1.0, 1.0], OCTREE_MAX_DEPTH);
+
+        assert_eq!(octree.children.len(), 8);
+        for (octant, child) in octree.children.iter().enumerate() {
+            let (x, y, z) = child.center;
+            assert_eq!(octree.find_octant(x, y, z), octant);
+            assert_eq!(child.width, 0.5 * octree.width);
+        }
+    }
+
+    #[test]
+    fn test_compute_mass_distribution() {
+        let (position_vectors, mass_vectors) = random_particles(200, 1000.0);
+        let octree = Octree::from_positions(&position_vectors, &mass_vectors, OCTREE_MAX_DEPTH);
+
+        let mut mass = 0.0;
+        let mut com = (0.0, 0.0, 0.0);
+        for i in 0..mass_vectors.len() {
+            mass += mass_vectors[i];
+            com.0 += mass_vectors[i] * position_vectors.x[i];
+            com.1 += mass_vectors[i] * position_vectors.y[i];
+            com.2 += mass_vectors[i] * position_vectors.z[i];
+        }
+        com = (com.0 / mass, com.1 / mass, com.2 / mass);
+
+        // Quadrupole summed directly over particles, compared with the one built up the tree
+        let mut q_xy = 0.0;
+        let mut q_zz = 0.0;
+        for i in 0..mass_vectors.len() {
+            let dx = position_vectors.x[i] - com.0;
+            let dy = position_vectors.y[i] - com.1;
+            let dz = position_vectors.z[i] - com.2;
+            q_xy += mass_vectors[i] * 3.0 * dx * dy;
+            q_zz += mass_vectors[i] * (2.0 * dz * dz - dx * dx - dy * dy);
+        }
+        let q_scale = mass * Float::powi(500.0, 2);
+
+        assert!((octree.mass - mass).abs() / mass < FLOAT_TOLERANCE);
+        assert!((octree.com.0 - com.0).abs() / com.0 < FLOAT_TOLERANCE);
+        assert!((octree.com.1 - com.1).abs() / com.1 < FLOAT_TOLERANCE);
+        assert!((octree.com.2 - com.2).abs() / com.2 < FLOAT_TOLERANCE);
+        assert!((octree.quadrupole[1] - q_xy).abs() / q_scale < FLOAT_TOLERANCE);
+        assert!((octree.quadrupole[5] - q_zz).abs() / q_scale < FLOAT_TOLERANCE);
+        let trace = octree.quadrupole[0] + octree.quadrupole[3] + octree.quadrupole[5];
+        assert!(trace.abs() / q_scale < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_coincident_particles() {
+        let position_vectors = VectorArray {
+            x: vec![5.0, 5.0, 5.0],
+            y: vec![5.0, 5.0, 5.0],
+            z: vec![5.0, 5.0, 5.0],
+        };
+        let mass_vectors = vec![1.0, 1.0, 1.0];
+        let octree = Octree::from_positions(&position_vectors, &mass_vectors, 8);
+
+        assert!((octree.mass - 3.0).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_theta_zero_matches_direct() {
+        let (position_vectors, mass_vectors) = random_particles(300, 1000.0);
+        let direct =
+            generate_acc_vectors(&position_vectors, &mass_vectors, Softening::default(), 1);
+
+        for use_quadrupole in [false, true] {
+            let octree = generate_acc_vectors_octree(
+                &position_vectors,
+                &mass_vectors,
+                0.0,
+                Softening::default(),
+                use_quadrupole,
+                1,
+            );
+
+            assert!(relative_error(&octree, &direct) < FLOAT_TOLERANCE);
+        }
+    }
+
+    #[test]
+    fn test_octree_against_direct() {
+        // Monopole truncation error scales roughly as theta^2, and adding the quadrupole
+        // leaves an error scaling roughly as theta^3
+        let (position_vectors, mass_vectors) = random_particles(1000, 1000.0);
+        let direct =
+            generate_acc_vectors(&position_vectors, &mass_vectors, Softening::default(), 1);
+
+        for theta in [0.3, 0.5, 0.7, 1.0] {
+            let monopole = generate_acc_vectors_octree(
+                &position_vectors,
+                &mass_vectors,
+                theta,
+                Softening::default(),
+                false,
+                1,
+            );
+            let quadrupole = generate_acc_vectors_octree(
+                &position_vectors,
+                &mass_vectors,
+                theta,
+                Softening::default(),
+                true,
+                1,
+            );
+            let monopole_error = relative_error(&monopole, &direct);
+            let quadrupole_error = relative_error(&quadrupole, &direct);
+            println!("theta = {theta}: monopole {monopole_error}, quadrupole {quadrupole_error}");
+
+            assert!(monopole_error < 0.1 * theta * theta);
+            assert!(quadrupole_error < 0.05 * theta * theta * theta);
+            assert!(quadrupole_error < monopole_error);
+        }
+    }
+
+    #[test]
+    fn test_quadrupole_of_two_bodies() {
+        // Far from a pair of equal masses, the quadrupole corrects the monopole towards the
+        // exact acceleration
+        let position_vectors = VectorArray {
+            x: vec![-1.0, 1.0, 40.0],
+            y: vec![0.0, 0.0, 30.0],
+            z: vec![0.0, 0.0, 0.0],
+        };
+        let mass_vectors = vec![1.0, 1.0, 0.0];
+        let exact = generate_acc_vectors(&position_vectors, &mass_vectors, Softening::None, 1);
+        let mut pair = Octree::new((0.0, 0.0, 0.0), 4.0, OCTREE_MAX_DEPTH);
+        pair.insert(0, &position_vectors);
+        pair.insert(1, &position_vectors);
+        pair.compute_mass_distribution(&position_vectors, &mass_vectors);
+
+        let monopole =
+            crate::compute_m_by_r_sq(pair.mass, position_vectors.at(2), pair.com, Softening::None);
+        let correction = pair.quadrupole_acc(position_vectors.at(2));
+        let quadrupole = (
+            monopole.0 + correction.0,
+            monopole.1 + correction.1,
+            monopole.2 + correction.2,
+        );
+
+        let monopole_error = (monopole.0 - exact.x[2]).hypot(monopole.1 - exact.y[2]);
+        let quadrupole_error = (quadrupole.0 - exact.x[2]).hypot(quadrupole.1 - exact.y[2]);
+        assert!(quadrupole_error < 0.05 * monopole_error);
+    }
+}
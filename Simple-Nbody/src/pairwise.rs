@@ -0,0 +1,131 @@
+use crate::softening::Softening;
+use crate::{active_count, Float, VectorArray};
+
+// Direct sum visiting each pair of bodies once, applying equal and opposite accelerations
+// to both, so does half the work of generate_acc_vectors()
+// The inner loop runs over contiguous slices of the structure of arrays, with the softening
+// kernel chosen outside it, so the compiler can vectorise it
+// Results match generate_acc_vectors() up to summation order. As every pair writes to two
+// bodies, the sum runs on a single thread
+// Rows start only from massive bodies, as pairs of test particles exert no forces
+
+pub fn generate_acc_vectors_pairwise(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+) -> VectorArray {
+    match softening {
+        Softening::None => pairwise_sum(position_vectors, mass_vectors, |r_sq| {
+            1.0 / (r_sq * r_sq.sqrt())
+        }),
+        Softening::Plummer(epsilon) => {
+            let epsilon_sq = epsilon * epsilon;
+            pairwise_sum(position_vectors, mass_vectors, |r_sq| {
+                let s = r_sq + epsilon_sq;
+                1.0 / (s * s.sqrt())
+            })
+        }
+        Softening::Spline(_) => pairwise_sum(position_vectors, mass_vectors, |r_sq| {
+            softening.force_factor(r_sq)
+        }),
+    }
+}
+
+fn pairwise_sum<F: Fn(Float) -> Float>(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    force_factor: F,
+) -> VectorArray {
+    // force_factor is g(r) as in Softening::force_factor()
+    let n = mass_vectors.len();
+    let mut acc_vectors = VectorArray::zeros(n);
+
+    for i in 0..active_count(mass_vectors) {
+        let (x_i, y_i, z_i) = position_vectors.at(i);
+        let m_i = mass_vectors[i];
+        let mut acc_i = (0.0, 0.0, 0.0);
+
+        // Bodies after i, each of which has yet to be paired with i
+        let x = &position_vectors.x[i + 1..];
+        let y = &position_vectors.y[i + 1..];
+        let z = &position_vectors.z[i + 1..];
+        let m = &mass_vectors[i + 1..];
+        let acc_x = &mut acc_vectors.x[i + 1..];
+        let acc_y = &mut acc_vectors.y[i + 1..];
+        let acc_z = &mut acc_vectors.z[i + 1..];
+
+        for j in 0..m.len() {
+            let rx = x[j] - x_i;
+            let ry = y[j] - y_i;
+            let rz = z[j] - z_i;
+            let g = force_factor(rx * rx + ry * ry + rz * rz);
+
+            acc_i.0 += m[j] * g * rx;
+            acc_i.1 += m[j] * g * ry;
+            acc_i.2 += m[j] * g * rz;
+            acc_x[j] -= m_i * g * rx;
+            acc_y[j] -= m_i * g * ry;
+            acc_z[j] -= m_i * g * rz;
+        }
+
+        acc_vectors.x[i] += acc_i.0;
+        acc_vectors.y[i] += acc_i.1;
+        acc_vectors.z[i] += acc_i.2;
+    }
+
+    acc_vectors
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::generate_acc_vectors;
+    use crate::initial_conditions::generate_plummer;
+    use crate::pairwise::generate_acc_vectors_pairwise;
+    use crate::softening::Softening;
+
+    #[test]
+    fn test_pairwise_matches_direct() {
+        let (mass_vectors, position_vectors, _) = generate_plummer(500, 1.0e7, 100.0, 5);
+
+        for softening in [
+            Softening::None,
+            Softening::Plummer(5.0),
+            Softening::Spline(20.0),
+        ] {
+            let direct = generate_acc_vectors(&position_vectors, &mass_vectors, softening, 1);
+            let pairwise =
+                generate_acc_vectors_pairwise(&position_vectors, &mass_vectors, softening);
+
+            for i in 0..mass_vectors.len() {
+                let (ax, ay, az) = direct.at(i);
+                let magnitude = (ax * ax + ay * ay + az * az).sqrt();
+                let error = ((pairwise.x[i] - ax).powi(2)
+                    + (pairwise.y[i] - ay).powi(2)
+                    + (pairwise.z[i] - az).powi(2))
+                .sqrt();
+                assert!(error <= 1e-4 * magnitude, "body {i}: error {error}");
+            }
+        }
+    }
+
+    #[test]
+    fn test_pairwise_conserves_momentum() {
+        // Equal and opposite contributions leave the total force at zero up to rounding
+        let (mass_vectors, position_vectors, _) = generate_plummer(300, 1.0e7, 100.0, 6);
+        let acc_vectors =
+            generate_acc_vectors_pairwise(&position_vectors, &mass_vectors, Softening::default());
+
+        let mut force = (0.0, 0.0, 0.0);
+        let mut scale = 0.0;
+        for i in 0..mass_vectors.len() {
+            let (ax, ay, az) = acc_vectors.at(i);
+            force.0 += mass_vectors[i] * ax;
+            force.1 += mass_vectors[i] * ay;
+            force.2 += mass_vectors[i] * az;
+            scale += mass_vectors[i] * (ax * ax + ay * ay + az * az).sqrt();
+        }
+
+        let total = (force.0 * force.0 + force.1 * force.1 + force.2 * force.2).sqrt();
+        assert!(total < 1e-5 * scale);
+    }
+}
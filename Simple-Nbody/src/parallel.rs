@@ -0,0 +1,66 @@
+use std::thread;
+
+// Below this many bodies, spawning threads costs more than it saves
+pub const PARALLEL_MIN_BODIES: usize = 256;
+
+pub fn thread_count(threads: usize) -> usize {
+    // 0 means one thread per available core
+    if threads == 0 {
+        thread::available_parallelism().map_or(1, |count| count.get())
+    } else {
+        threads
+    }
+}
+
+pub fn map_bodies<T, F>(n: usize, threads: usize, f: F) -> Vec<T>
+where
+    T: Send,
+    F: Fn(usize) -> T + Sync,
+{
+    // Evaluates f for every body index, splitting the indices into contiguous chunks, one per
+    // thread. Results are returned in index order, and each is computed exactly as it would be
+    // serially, so the thread count does not change them
+    let threads = thread_count(threads).min(n);
+    if threads <= 1 || n < PARALLEL_MIN_BODIES {
+        return (0..n).map(f).collect();
+    }
+
+    let chunk_size = n.div_ceil(threads);
+    let f = &f;
+    thread::scope(|scope| {
+        let handles: Vec<_> = (0..n)
+            .step_by(chunk_size)
+            .map(|start| {
+                let end = (start + chunk_size).min(n);
+                scope.spawn(move || (start..end).map(f).collect::<Vec<T>>())
+            })
+            .collect();
+
+        let mut results = Vec::with_capacity(n);
+        for handle in handles {
+            results.extend(handle.join().unwrap());
+        }
+        results
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::parallel::*;
+
+    #[test]
+    fn test_map_bodies_keeps_order() {
+        for n in [0, 10, PARALLEL_MIN_BODIES, 1001] {
+            for threads in [1, 3, 8] {
+                let squares = map_bodies(n, threads, |i| i * i);
+                assert_eq!(squares, (0..n).map(|i| i * i).collect::<Vec<usize>>());
+            }
+        }
+    }
+
+    #[test]
+    fn test_thread_count() {
+        assert_eq!(thread_count(3), 3);
+        assert!(thread_count(0) >= 1);
+    }
+}
@@ -0,0 +1,439 @@
+use std::sync::{Arc, Mutex};
+
+use crate::fft::{fft_grid, multiply, Complex};
+use crate::{Float, VectorArray};
+
+// Particle-mesh (PM) gravity: masses are spread onto a cubic grid, the potential is found
+// on the grid by solving Poisson's equation with FFTs, and accelerations are differenced
+// from it and interpolated back to the bodies. Each force evaluation costs O(n + N^3 log N)
+// for a grid of N^3 cells, whatever the number of bodies. Two dimensional runs, with every
+// body in the z = 0 plane, only need the grid's N^2 cells in that plane
+//
+// Mass assignment and interpolation both use cloud-in-cell (CIC) weights, so a body does
+// not pull on itself, and accelerations are central differences over four grid points
+// Forces are smoothed on the scale of a cell or two by the grid, so the softening kernel
+// is not used, and are accurate from a few cells out
+//
+// Isolated boundaries: the grid covers the bodies' bounding cube, and the potential is the
+// convolution of the masses with the Green's function -1 / r, done with FFTs on a grid
+// twice as large in each direction, with the masses zero padded, so that the periodic
+// convolution of the FFT never wraps one side onto the other (Hockney & Eastwood 1988)
+// Periodic boundaries: the grid covers the periodic box, and the potential solves
+// k^2 phi_k = -4 pi rho_k, with the mean density dropped as for the Ewald sum
+// The transformed Green's function only depends on the grid, so is kept between force
+// evaluations, see GreenCache
+//
+// The grid is always computed in double precision
+
+// Default cells along each side of the grid, which must be a power of two
+pub const PM_GRID: usize = 64;
+// Most cells along each side, as isolated runs transform (2 PM_MAX_GRID)^3 points
+pub const PM_MAX_GRID: usize = 512;
+// Empty cells kept around the bodies in isolated runs, so the stencils stay on the grid
+const PM_MARGIN: usize = 3;
+
+pub fn valid_grid(grid: usize) -> bool {
+    // The FFT needs a power of two, and the margins need a few cells inside it
+    grid.is_power_of_two() && (8..=PM_MAX_GRID).contains(&grid)
+}
+
+// Transformed Green's function of the last grid used, for cells of unit width
+// Both Green's functions fall off as 1 / distance, so scale as 1 / cell for other widths,
+// and only change with the number of points, boundaries and dimensions. Not part of the
+// simulation state, and clones of a simulation share it
+#[derive(Clone, Default)]
+pub struct GreenCache {
+    last: Arc<Mutex<Option<CachedGreen>>>,
+}
+
+// Points along each side, axes and whether the grid is periodic, with the transform
+type CachedGreen = ((usize, u32, bool), Arc<Vec<Complex>>);
+
+impl GreenCache {
+    fn get(&self, mesh: &Mesh) -> Arc<Vec<Complex>> {
+        let key = (mesh.size, mesh.axes, mesh.periodic);
+        let mut last = self.last.lock().unwrap();
+        if let Some((cached_key, green)) = last.as_ref() {
+            if *cached_key == key {
+                return Arc::clone(green);
+            }
+        }
+        let green = Arc::new(mesh.green_function());
+        *last = Some((key, Arc::clone(&green)));
+
+        green
+    }
+}
+
+pub fn generate_acc_vectors_pm(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    grid: usize,
+    box_size: Option<Float>,
+    dimensions: u32,
+    green_cache: &GreenCache,
+) -> VectorArray {
+    // Periodic when box_size is given, with positions inside the box as wrapped by
+    // Simulation::wrap_positions(). With 2 dimensions, z is taken to be 0 for every body
+    assert!(
+        valid_grid(grid),
+        "PM grid of {grid} cells is not a power of two from 8 to {PM_MAX_GRID}"
+    );
+    let axes = if dimensions == 2 { 2 } else { 3 };
+    let mesh = match box_size {
+        Some(box_size) => Mesh::periodic(grid, axes, box_size as f64),
+        None => Mesh::isolated(grid, axes, position_vectors),
+    };
+
+    // Mass of each grid point, on the padded grid for isolated boundaries
+    let size = mesh.size;
+    let mut density = vec![(0.0, 0.0); size.pow(axes)];
+    for i in 0..mass_vectors.len() {
+        for (index, weight) in mesh.cic(position_vectors.at(i)) {
+            density[index].0 += weight * mass_vectors[i] as f64;
+        }
+    }
+
+    fft_grid(&mut density, size, axes, false);
+    let green = green_cache.get(&mesh);
+    let scale = 1.0 / mesh.cell;
+    for (value, green) in density.iter_mut().zip(green.iter()) {
+        *value = multiply(*value, (scale * green.0, scale * green.1));
+    }
+    fft_grid(&mut density, size, axes, true);
+    let potential: Vec<f64> = density.iter().map(|value| value.0).collect();
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for i in 0..mass_vectors.len() {
+        let mut acc = [0.0; 3];
+        for (index, weight) in mesh.cic(position_vectors.at(i)) {
+            let node_acc = mesh.gradient(&potential, index);
+            for axis in 0..3 {
+                acc[axis] -= weight * node_acc[axis];
+            }
+        }
+        acc_vectors.push((acc[0] as Float, acc[1] as Float, acc[2] as Float));
+    }
+
+    acc_vectors
+}
+
+struct Mesh {
+    // Cells along each side of the region the bodies are in
+    grid: usize,
+    // Points along each side of the arrays, doubled for isolated boundaries
+    size: usize,
+    // 2 for a square grid in the z = 0 plane, or 3 for a cube
+    axes: u32,
+    cell: f64,
+    // Position of grid point (0, 0, 0)
+    origin: [f64; 3],
+    periodic: bool,
+}
+
+impl Mesh {
+    fn periodic(grid: usize, axes: u32, box_size: f64) -> Mesh {
+        Mesh {
+            grid,
+            size: grid,
+            axes,
+            cell: box_size / grid as f64,
+            origin: [-0.5 * box_size; 3],
+            periodic: true,
+        }
+    }
+
+    fn isolated(grid: usize, axes: u32, position_vectors: &VectorArray) -> Mesh {
+        // Bounding cube, or square, of the bodies, with PM_MARGIN cells on every side
+        let mut min = [0.0; 3];
+        let mut max = [0.0; 3];
+        for (axis, values) in [
+            &position_vectors.x,
+            &position_vectors.y,
+            &position_vectors.z,
+        ]
+        .iter()
+        .take(axes as usize)
+        .enumerate()
+        {
+            min[axis] = f64::MAX;
+            max[axis] = f64::MIN;
+            for &value in values.iter() {
+                min[axis] = min[axis].min(value as f64);
+                max[axis] = max[axis].max(value as f64);
+            }
+        }
+        let side = (0..3)
+            .map(|axis| max[axis] - min[axis])
+            .fold(0.0, f64::max)
+            .max(f64::MIN_POSITIVE);
+        // Bodies span grid points PM_MARGIN to grid - PM_MARGIN - 1, leaving room for CIC
+        // and the differencing stencil
+        let cell = side / (grid - 2 * PM_MARGIN - 1) as f64;
+        let offset = PM_MARGIN as f64 * cell;
+
+        Mesh {
+            grid,
+            size: 2 * grid,
+            axes,
+            cell,
+            origin: [min[0] - offset, min[1] - offset, min[2] - offset],
+            periodic: false,
+        }
+    }
+
+    fn cic(&self, position: (Float, Float, Float)) -> impl Iterator<Item = (usize, f64)> + '_ {
+        // Flat indices and weights of the 8 grid points around a position, or the 4 around
+        // it in the plane
+        let position = [position.0 as f64, position.1 as f64, position.2 as f64];
+        let mut lower = [0; 3];
+        let mut fraction = [0.0; 3];
+        for axis in 0..self.axes as usize {
+            let u = (position[axis] - self.origin[axis]) / self.cell;
+            let floor = u.floor();
+            fraction[axis] = u - floor;
+            lower[axis] = floor as isize;
+        }
+
+        (0..1 << self.axes).map(move |corner| {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for axis in 0..self.axes as usize {
+                let upper = (corner >> axis) & 1;
+                weight *= if upper == 1 {
+                    fraction[axis]
+                } else {
+                    1.0 - fraction[axis]
+                };
+                index[axis] = lower[axis] + upper as isize;
+            }
+            (self.flat(index), weight)
+        })
+    }
+
+    fn flat(&self, index: [isize; 3]) -> usize {
+        // Periodic grids wrap around, while isolated grids have the padding to spare
+        let mut flat = 0;
+        for &i in &index[..self.axes as usize] {
+            let wrapped = if self.periodic {
+                i.rem_euclid(self.grid as isize) as usize
+            } else {
+                i as usize
+            };
+            flat = flat * self.size + wrapped;
+        }
+
+        flat
+    }
+
+    fn unflat(&self, mut index: usize) -> [isize; 3] {
+        // Indices along unused axes are 0
+        let mut point = [0; 3];
+        for axis in (0..self.axes as usize).rev() {
+            point[axis] = (index % self.size) as isize;
+            index /= self.size;
+        }
+
+        point
+    }
+
+    fn gradient(&self, potential: &[f64], index: usize) -> [f64; 3] {
+        // Fourth order central difference of the potential at a grid point, with no z
+        // component in the plane, by symmetry
+        let point = self.unflat(index);
+        let mut gradient = [0.0; 3];
+        for (axis, value) in gradient.iter_mut().enumerate().take(self.axes as usize) {
+            let at = |offset: isize| {
+                let mut neighbour = point;
+                neighbour[axis] += offset;
+                potential[self.flat(neighbour)]
+            };
+            *value = (8.0 * (at(1) - at(-1)) - (at(2) - at(-2))) / (12.0 * self.cell);
+        }
+
+        gradient
+    }
+
+    fn green_function(&self) -> Vec<Complex> {
+        // Fourier transform of the Green's function for cells of unit width, which the
+        // transformed masses are multiplied by to give the transformed potential
+        let size = self.size;
+        let mut green = vec![(0.0, 0.0); size.pow(self.axes)];
+        // Offsets along an axis run from -size / 2 to size / 2 - 1, wrapping around
+        let offset = |i: isize| {
+            if i < size as isize / 2 {
+                i as f64
+            } else {
+                (i - size as isize) as f64
+            }
+        };
+
+        if self.periodic {
+            // -4 pi / k^2 for the density, mass / cell^3, with the mean density dropped
+            // In the plane, this is averaged over k_z, which gives the potential in the
+            // plane of the masses repeated in every box above and below it
+            let k_unit = 2.0 * std::f64::consts::PI / size as f64;
+            let layers: Vec<f64> = (0..size as isize).map(offset).collect();
+            for (index, value) in green.iter_mut().enumerate() {
+                let [i, j, k] = self.unflat(index).map(offset);
+                let k_zs = if self.axes == 3 {
+                    std::slice::from_ref(&k)
+                } else {
+                    &layers[..]
+                };
+                let mut sum = 0.0;
+                for k_z in k_zs {
+                    let k_sq = k_unit * k_unit * (i * i + j * j + k_z * k_z);
+                    if k_sq > 0.0 {
+                        sum += -4.0 * std::f64::consts::PI / k_sq;
+                    }
+                }
+                *value = (sum / k_zs.len() as f64, 0.0);
+            }
+            return green;
+        }
+
+        // -1 / r in real space, transformed. The self term is taken at half a cell
+        for (index, value) in green.iter_mut().enumerate() {
+            let [i, j, k] = self.unflat(index).map(offset);
+            let r = (i * i + j * j + k * k).sqrt();
+            *value = (-1.0 / r.max(0.5), 0.0);
+        }
+        fft_grid(&mut green, size, self.axes, false);
+
+        green
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::particle_mesh::*;
+    use crate::periodic::generate_acc_vectors_ewald;
+    use crate::softening::Softening;
+
+    fn point_mass_with_probes(probes: &[(Float, Float, Float)]) -> (Vec<Float>, VectorArray) {
+        // A unit mass at the origin, with massless probes of its field
+        let mut mass_vectors = vec![1.0];
+        let mut position_vectors = VectorArray::zeros(1);
+        for &probe in probes {
+            mass_vectors.push(0.0);
+            position_vectors.push(probe);
+        }
+
+        (mass_vectors, position_vectors)
+    }
+
+    fn relative_error(acc: (Float, Float, Float), expected: (Float, Float, Float)) -> Float {
+        let error = ((acc.0 - expected.0).powi(2)
+            + (acc.1 - expected.1).powi(2)
+            + (acc.2 - expected.2).powi(2))
+        .sqrt();
+        error / (expected.0.powi(2) + expected.1.powi(2) + expected.2.powi(2)).sqrt()
+    }
+
+    #[test]
+    fn test_isolated_point_mass() {
+        // The probes span 160 units, so with 32 cells the cells are 6.4 units wide, and the
+        // probes are at least 5 cells from the mass
+        let probes = [
+            (100.0, 0.0, 0.0),
+            (-60.0, 45.0, 10.0),
+            (20.0, -30.0, 50.0),
+            (-30.0, 10.0, -16.0),
+            (0.0, 70.0, -100.0),
+        ];
+        let (mass_vectors, position_vectors) = point_mass_with_probes(&probes);
+        let acc_vectors = generate_acc_vectors_pm(
+            &position_vectors,
+            &mass_vectors,
+            32,
+            None,
+            3,
+            &GreenCache::default(),
+        );
+
+        for (i, &(x, y, z)) in probes.iter().enumerate() {
+            let r = (x * x + y * y + z * z).sqrt();
+            let expected = (-x / r.powi(3), -y / r.powi(3), -z / r.powi(3));
+            let error = relative_error(acc_vectors.at(i + 1), expected);
+            assert!(error < 0.01, "probe at {r}: error {error}");
+        }
+        // The mass feels no force from itself
+        let (ax, ay, az) = acc_vectors.at(0);
+        assert!((ax * ax + ay * ay + az * az).sqrt() < 1e-6);
+    }
+
+    #[test]
+    fn test_periodic_matches_ewald() {
+        let box_size = 100.0;
+        let probes = [
+            (20.0, 6.0, 3.0),
+            (-30.0, 25.0, 5.0),
+            (10.0, -15.0, 40.0),
+            (49.0, 49.0, -49.0),
+        ];
+        let (mass_vectors, position_vectors) = point_mass_with_probes(&probes);
+        let acc_vectors = generate_acc_vectors_pm(
+            &position_vectors,
+            &mass_vectors,
+            32,
+            Some(box_size),
+            3,
+            &GreenCache::default(),
+        );
+        let ewald = generate_acc_vectors_ewald(
+            &position_vectors,
+            &mass_vectors,
+            box_size,
+            Softening::None,
+            1,
+        );
+
+        // The field varies a lot over the box and nearly vanishes at its corners, so errors
+        // are measured against the field at a quarter of the box from the mass
+        let scale = 16.0 / (box_size * box_size);
+        for i in 1..=probes.len() {
+            let (ax, ay, az) = acc_vectors.at(i);
+            let (ex, ey, ez) = ewald.at(i);
+            let error = ((ax - ex).powi(2) + (ay - ey).powi(2) + (az - ez).powi(2)).sqrt() / scale;
+            assert!(error < 0.02, "probe {i}: error {error}");
+        }
+    }
+
+    #[test]
+    fn test_plane_matches_cube() {
+        // Bodies in the z = 0 plane feel the same forces from the square grid as from the
+        // cube, and a cache shared between both gives the same results as fresh ones
+        let probes = [
+            (100.0, 0.0, 0.0),
+            (-60.0, 45.0, 0.0),
+            (20.0, -30.0, 0.0),
+            (-30.0, 10.0, 0.0),
+        ];
+        let (mass_vectors, position_vectors) = point_mass_with_probes(&probes);
+        let shared = GreenCache::default();
+        for box_size in [None, Some(400.0)] {
+            let forces = |dimensions, green_cache: &GreenCache| {
+                generate_acc_vectors_pm(
+                    &position_vectors,
+                    &mass_vectors,
+                    16,
+                    box_size,
+                    dimensions,
+                    green_cache,
+                )
+            };
+            let cube = forces(3, &shared);
+            let plane = forces(2, &shared);
+            for i in 0..mass_vectors.len() {
+                let (ax, ay, az) = plane.at(i);
+                let (cx, cy, _) = cube.at(i);
+                let scale = (cx * cx + cy * cy).sqrt().max(1e-6);
+                assert!(((ax - cx).powi(2) + (ay - cy).powi(2)).sqrt() < 1e-4 * scale);
+                assert_eq!(az, 0.0);
+            }
+            assert_eq!(forces(2, &shared).x, forces(2, &GreenCache::default()).x);
+            assert_eq!(forces(3, &shared).y, forces(3, &GreenCache::default()).y);
+        }
+    }
+}
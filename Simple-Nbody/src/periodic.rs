@@ -0,0 +1,438 @@
+use std::sync::OnceLock;
+
+use crate::softening::Softening;
+use crate::{active_count, parallel, Float, Simulation, VectorArray};
+
+// Periodic boundaries, for a cube of side box_size centred on the origin, tiled infinitely
+// Bodies leaving through one face come back in through the opposite one, and each body
+// feels every periodic image of every other body, against a uniform background of negative
+// density cancelling the mean density, as in cosmological simulations where forces come
+// from density perturbations
+//
+// Forces are the softened force from the nearest image of each body (minimum image), plus
+// a correction for all other images and the background. The correction is smooth, so it is
+// tabulated once on a grid over an eighth of the box from Ewald summation and interpolated,
+// as in Gadget-2 (Springel 2005)
+// Ewald sum: Hernquist, Bouchet & Suto (1991), ApJS 75, 231
+
+// Grid cells along each axis of the correction table, which covers 0 to 1/2 box
+pub const EWALD_TABLE_CELLS: usize = 16;
+// Splitting between the real and Fourier space sums, in units of 1 / box_size
+const EWALD_ALPHA: f64 = 2.0;
+// Images and wave vectors with components up to this many box sizes are summed
+const EWALD_IMAGES: i32 = 3;
+
+pub fn wrap(x: Float, box_size: Float) -> Float {
+    // Position moved into [-box_size / 2, box_size / 2)
+    x - box_size * (x / box_size + 0.5).floor()
+}
+
+pub fn minimum_image(dx: Float, box_size: Float) -> Float {
+    // Separation to the nearest image, in [-box_size / 2, box_size / 2]
+    dx - box_size * (dx / box_size).round()
+}
+
+// Correction to the field of a unit mass for a unit box, at grid points (i, j, k) over
+// [0, 1/2]^3: the Ewald acceleration and potential, minus those of the nearest image alone
+struct EwaldTable {
+    acc: Vec<[f64; 3]>,
+    potential: Vec<f64>,
+}
+
+fn ewald_table() -> &'static EwaldTable {
+    // Built on first use, and shared by all simulations, as it scales with box size
+    static TABLE: OnceLock<EwaldTable> = OnceLock::new();
+    TABLE.get_or_init(|| {
+        let points = EWALD_TABLE_CELLS + 1;
+        let mut table = EwaldTable {
+            acc: Vec::with_capacity(points.pow(3)),
+            potential: Vec::with_capacity(points.pow(3)),
+        };
+        for i in 0..points {
+            for j in 0..points {
+                for k in 0..points {
+                    let cell = 0.5 / EWALD_TABLE_CELLS as f64;
+                    let r = [i as f64 * cell, j as f64 * cell, k as f64 * cell];
+                    let (acc, potential) = ewald_correction(r);
+                    table.acc.push(acc);
+                    table.potential.push(potential);
+                }
+            }
+        }
+        table
+    })
+}
+
+fn ewald_correction(r: [f64; 3]) -> ([f64; 3], f64) {
+    // Acceleration and potential at r from a unit mass at the origin and all its images in
+    // a unit box, with the background, minus the Newtonian field of the mass at the origin
+    let alpha = EWALD_ALPHA;
+    let pi = std::f64::consts::PI;
+    let mut acc = [0.0; 3];
+    // Background term, so the mean potential over the box is zero
+    let mut potential = pi / (alpha * alpha);
+
+    for nx in -EWALD_IMAGES..=EWALD_IMAGES {
+        for ny in -EWALD_IMAGES..=EWALD_IMAGES {
+            for nz in -EWALD_IMAGES..=EWALD_IMAGES {
+                // Real space sum over images, with the Newtonian part of the nearest taken out
+                let d = [r[0] - nx as f64, r[1] - ny as f64, r[2] - nz as f64];
+                let distance = (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt();
+                let gaussian =
+                    2.0 * alpha / pi.sqrt() * (-alpha * alpha * distance * distance).exp();
+                let origin = nx == 0 && ny == 0 && nz == 0;
+                if origin && distance == 0.0 {
+                    // Limit of erf(alpha r) / r, while the force vanishes
+                    potential += 2.0 * alpha / pi.sqrt();
+                } else if origin {
+                    let erf = erf(alpha * distance);
+                    potential += erf / distance;
+                    let factor = (erf - distance * gaussian) / distance.powi(3);
+                    for axis in 0..3 {
+                        acc[axis] += factor * d[axis];
+                    }
+                } else {
+                    let erfc = erfc(alpha * distance);
+                    potential -= erfc / distance;
+                    let factor = (erfc + distance * gaussian) / distance.powi(3);
+                    for axis in 0..3 {
+                        acc[axis] -= factor * d[axis];
+                    }
+                }
+
+                // Fourier space sum over wave vectors k = (nx, ny, nz)
+                let k_sq = (nx * nx + ny * ny + nz * nz) as f64;
+                if k_sq == 0.0 {
+                    continue;
+                }
+                let damping = (-pi * pi * k_sq / (alpha * alpha)).exp() / k_sq;
+                let phase = 2.0 * pi * (nx as f64 * r[0] + ny as f64 * r[1] + nz as f64 * r[2]);
+                potential -= damping * phase.cos() / pi;
+                let k = [nx as f64, ny as f64, nz as f64];
+                for axis in 0..3 {
+                    acc[axis] -= 2.0 * damping * phase.sin() * k[axis];
+                }
+            }
+        }
+    }
+
+    (acc, potential)
+}
+
+fn erf(x: f64) -> f64 {
+    // Error function from its Taylor series, for the nearest image, where 1 - erfc() would
+    // lose the small differences the correction is made of. Converges quickly for x below 2
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 0.0;
+    while term.abs() > 1e-17 * sum.abs() {
+        n += 1.0;
+        term *= -x * x / n;
+        sum += term / (2.0 * n + 1.0);
+    }
+
+    2.0 / std::f64::consts::PI.sqrt() * sum
+}
+
+fn erfc(x: f64) -> f64 {
+    // Complementary error function for x >= 0, with fractional error below 1.2e-7
+    // Source: Numerical Recipes in C, 2nd edition, erfcc()
+    let t = 1.0 / (1.0 + 0.5 * x);
+    t * (-x * x - 1.265_512_23
+        + t * (1.000_023_68
+            + t * (0.374_091_96
+                + t * (0.096_784_18
+                    + t * (-0.186_288_06
+                        + t * (0.278_868_07
+                            + t * (-1.135_203_98
+                                + t * (1.488_515_87 + t * (-0.822_152_23 + t * 0.170_872_77)))))))))
+        .exp()
+}
+
+fn interpolate_correction(d: (Float, Float, Float), box_size: Float) -> ([Float; 3], Float) {
+    // Trilinear interpolation of the table at separation d, using the correction's symmetry:
+    // each acceleration component is odd in its own axis and even in the others
+    let table = ewald_table();
+    let cells = EWALD_TABLE_CELLS;
+    let points = cells + 1;
+    let d = [d.0 as f64, d.1 as f64, d.2 as f64];
+    let box_size = box_size as f64;
+
+    let mut index = [0; 3];
+    let mut fraction = [0.0; 3];
+    for axis in 0..3 {
+        let u = (d[axis].abs() / box_size * 2.0 * cells as f64).min(cells as f64);
+        index[axis] = (u as usize).min(cells - 1);
+        fraction[axis] = u - index[axis] as f64;
+    }
+
+    let mut acc = [0.0; 3];
+    let mut potential = 0.0;
+    for corner in 0..8 {
+        let mut weight = 1.0;
+        let mut flat = 0;
+        for axis in 0..3 {
+            let upper = (corner >> axis) & 1;
+            weight *= if upper == 1 {
+                fraction[axis]
+            } else {
+                1.0 - fraction[axis]
+            };
+            flat = flat * points + index[axis] + upper;
+        }
+        for axis in 0..3 {
+            acc[axis] += weight * table.acc[flat][axis];
+        }
+        potential += weight * table.potential[flat];
+    }
+
+    // Scaled from the unit box, as acceleration goes as 1 / length^2 and potential 1 / length
+    let mut scaled = [0.0; 3];
+    for axis in 0..3 {
+        scaled[axis] = (acc[axis] * d[axis].signum() / (box_size * box_size)) as Float;
+    }
+
+    (scaled, (potential / box_size) as Float)
+}
+
+pub fn generate_acc_vectors_ewald(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    box_size: Float,
+    softening: Softening,
+    threads: usize,
+) -> VectorArray {
+    // Direct sum over the nearest image of every other body, plus the Ewald correction
+    // Only massive bodies are sources, so test particles cost O(active_count) each
+    let n = mass_vectors.len();
+    let active = active_count(mass_vectors);
+    let accs = parallel::map_bodies(n, threads, |i| {
+        let (x_i, y_i, z_i) = position_vectors.at(i);
+        let mut acc = (0.0, 0.0, 0.0);
+
+        for j in 0..active {
+            if j != i {
+                let (x_j, y_j, z_j) = position_vectors.at(j);
+                let d = (
+                    minimum_image(x_j - x_i, box_size),
+                    minimum_image(y_j - y_i, box_size),
+                    minimum_image(z_j - z_i, box_size),
+                );
+                let g = softening.force_factor(d.0 * d.0 + d.1 * d.1 + d.2 * d.2);
+                // The correction is for the field around body j, at i's separation of -d
+                let (correction, _) = interpolate_correction(d, box_size);
+                let m = mass_vectors[j];
+                acc.0 += m * (g * d.0 - correction[0]);
+                acc.1 += m * (g * d.1 - correction[1]);
+                acc.2 += m * (g * d.2 - correction[2]);
+            }
+        }
+        acc
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+pub fn energy_potential_ewald(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    box_size: Float,
+    softening: Softening,
+) -> Float {
+    // Sum over each pair once of the nearest image potential and its correction, along with
+    // each body's energy in the field of its own images and the background
+    // Test particles have no potential energy, so only pairs of massive bodies are summed
+    let active = active_count(mass_vectors);
+    let (_, self_potential) = interpolate_correction((0.0, 0.0, 0.0), box_size);
+    let mut potential_energy = 0.0;
+    for i in 0..active {
+        potential_energy += 0.5 * mass_vectors[i] * mass_vectors[i] * self_potential;
+        let (x_i, y_i, z_i) = position_vectors.at(i);
+        for j in (i + 1)..active {
+            let (x_j, y_j, z_j) = position_vectors.at(j);
+            let d = (
+                minimum_image(x_j - x_i, box_size),
+                minimum_image(y_j - y_i, box_size),
+                minimum_image(z_j - z_i, box_size),
+            );
+            let (_, correction) = interpolate_correction(d, box_size);
+            let r_sq = d.0 * d.0 + d.1 * d.1 + d.2 * d.2;
+            potential_energy +=
+                mass_vectors[i] * mass_vectors[j] * (softening.potential(r_sq) + correction);
+        }
+    }
+
+    potential_energy
+}
+
+impl Simulation {
+    pub fn make_periodic(&mut self, box_size: Float) {
+        // Switches to periodic boundaries, wrapping bodies into the box and recomputing the
+        // cached accelerations with periodic forces
+        self.box_size = Some(box_size);
+        self.wrap_positions();
+        self.acc_vectors = self.compute_acc_vectors();
+        self.jerk_vectors = VectorArray::zeros(0);
+    }
+
+    pub fn wrap_positions(&mut self) {
+        if let Some(box_size) = self.box_size {
+            for x in self.position_vectors.x.iter_mut() {
+                *x = wrap(*x, box_size);
+            }
+            for y in self.position_vectors.y.iter_mut() {
+                *y = wrap(*y, box_size);
+            }
+            for z in self.position_vectors.z.iter_mut() {
+                *z = wrap(*z, box_size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::periodic::*;
+
+    fn lattice(per_side: usize, box_size: Float) -> (Vec<Float>, VectorArray) {
+        // Equal masses on a cubic lattice filling the box
+        let spacing = box_size / per_side as Float;
+        let mut position_vectors = VectorArray::zeros(0);
+        for i in 0..per_side {
+            for j in 0..per_side {
+                for k in 0..per_side {
+                    position_vectors.push((
+                        wrap(i as Float * spacing, box_size),
+                        wrap(j as Float * spacing, box_size),
+                        wrap(k as Float * spacing, box_size),
+                    ));
+                }
+            }
+        }
+
+        (vec![1000.0; per_side.pow(3)], position_vectors)
+    }
+
+    #[test]
+    fn test_wrap_and_minimum_image() {
+        assert_eq!(wrap(0.0, 10.0), 0.0);
+        assert_eq!(wrap(6.0, 10.0), -4.0);
+        assert_eq!(wrap(-5.0, 10.0), -5.0);
+        assert_eq!(wrap(5.0, 10.0), -5.0);
+        assert_eq!(wrap(-27.0, 10.0), 3.0);
+        assert_eq!(minimum_image(7.0, 10.0), -3.0);
+        assert_eq!(minimum_image(-7.0, 10.0), 3.0);
+        assert_eq!(minimum_image(2.0, 10.0), 2.0);
+    }
+
+    #[test]
+    fn test_correction_table_interpolation() {
+        // Between grid points, the interpolated correction is close to the exact Ewald sum
+        let box_size = 100.0;
+        for d in [(13.3, -7.1, 2.2), (-40.0, 31.5, -18.7), (49.0, 0.3, -25.1)] {
+            let (acc, potential) = interpolate_correction(d, box_size);
+            let unit = [
+                d.0 as f64 / box_size as f64,
+                d.1 as f64 / box_size as f64,
+                d.2 as f64 / box_size as f64,
+            ];
+            let (exact_acc, exact_potential) = ewald_correction(unit);
+            for axis in 0..3 {
+                let exact = exact_acc[axis] / (box_size * box_size) as f64;
+                assert!((acc[axis] as f64 - exact).abs() < 2e-2 / (box_size * box_size) as f64);
+            }
+            let exact = exact_potential / box_size as f64;
+            assert!((potential as f64 - exact).abs() < 1e-2 / box_size as f64);
+        }
+    }
+
+    #[test]
+    fn test_uniform_lattice_is_force_free() {
+        // By symmetry, every body of an infinite uniform lattice feels no net force. With the
+        // nearest image alone, bodies half a box apart would pull one way only
+        let box_size = 200.0;
+        let (mass_vectors, position_vectors) = lattice(4, box_size);
+        let acc_vectors = generate_acc_vectors_ewald(
+            &position_vectors,
+            &mass_vectors,
+            box_size,
+            Softening::None,
+            1,
+        );
+
+        // Compared with the acceleration from a single neighbour on the lattice
+        let spacing = box_size / 4.0;
+        let scale = 1000.0 / (spacing * spacing);
+        for i in 0..mass_vectors.len() {
+            let (ax, ay, az) = acc_vectors.at(i);
+            assert!((ax * ax + ay * ay + az * az).sqrt() < 1e-4 * scale);
+        }
+    }
+
+    #[test]
+    fn test_tracers_do_not_change_the_lattice() {
+        // Massless bodies are skipped as sources, so they leave the forces on the massive
+        // bodies and the potential energy exactly as they were
+        let box_size = 200.0;
+        let (mut mass_vectors, mut position_vectors) = lattice(3, box_size);
+        let massive = generate_acc_vectors_ewald(
+            &position_vectors,
+            &mass_vectors,
+            box_size,
+            Softening::default(),
+            1,
+        );
+        let energy =
+            energy_potential_ewald(&position_vectors, &mass_vectors, box_size, Softening::None);
+
+        for (x, y, z) in [(11.0, -3.0, 40.0), (-70.0, 25.0, 0.5)] {
+            mass_vectors.push(0.0);
+            position_vectors.push((x, y, z));
+        }
+        let traced = generate_acc_vectors_ewald(
+            &position_vectors,
+            &mass_vectors,
+            box_size,
+            Softening::default(),
+            1,
+        );
+        assert_eq!(massive.x, traced.x[..27]);
+        assert_eq!(massive.y, traced.y[..27]);
+        assert_eq!(massive.z, traced.z[..27]);
+        assert!(traced.x[27] != 0.0);
+        assert_eq!(
+            energy,
+            energy_potential_ewald(&position_vectors, &mass_vectors, box_size, Softening::None)
+        );
+    }
+
+    #[test]
+    fn test_lattice_stays_put() {
+        let box_size = 200.0;
+        let (mass_vectors, position_vectors) = lattice(3, box_size);
+        let mut simulation = Simulation::new(
+            mass_vectors,
+            position_vectors.clone(),
+            VectorArray::zeros(27),
+            "direct",
+            0.0,
+            "leap_frog",
+            Softening::default(),
+        );
+        simulation.make_periodic(box_size);
+        for _i in 0..100 {
+            simulation.update();
+        }
+
+        for i in 0..27 {
+            let (x, y, z) = simulation.position_vectors.at(i);
+            let (x0, y0, z0) = position_vectors.at(i);
+            assert!((x - x0).abs() + (y - y0).abs() + (z - z0).abs() < 1e-3);
+        }
+    }
+}
@@ -0,0 +1,312 @@
+use crate::softening::Softening;
+use crate::{active_count, compute_m_by_r_sq, parallel, Float, VectorArray};
+
+// Post-Newtonian (PN) corrections to the pair force, for compact binaries whose orbits are
+// close enough for general relativity to matter. They are expansions in (v / c)^2, and
+// depend on the bodies' velocities as well as their positions
+//
+// None: Newtonian gravity
+// FirstOrder(c): the 1PN terms of Einstein, Infeld & Hoffmann (1938), which make orbits
+//        precess. Each pair is corrected as if it were an isolated binary, dropping the
+//        terms coupling three bodies, which are small unless a third body is as close
+// RadiationReaction(c): the 1PN terms plus the 2.5PN radiation reaction of a binary in
+//        harmonic coordinates (Iyer & Will 1995), which drains energy and angular momentum
+//        as gravitational waves carry them away, so orbits circularise and shrink
+//
+// c is the speed of light in simulation units, and can be lowered to exaggerate the effects
+// Corrections use the unsoftened separation, so are meant for runs without softening
+// Energy in the diagnostics is the Newtonian energy, which 1PN orbits only conserve on
+// average, so it oscillates around its initial value
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum PostNewtonian {
+    #[default]
+    None,
+    FirstOrder(Float),
+    RadiationReaction(Float),
+}
+
+impl PostNewtonian {
+    pub fn from_name(name: &str, speed_of_light: Float) -> Result<PostNewtonian, String> {
+        // Order names as given on the command line and stored in checkpoints
+        if name == "none" {
+            Ok(PostNewtonian::None)
+        } else if name == "1pn" {
+            Ok(PostNewtonian::FirstOrder(speed_of_light))
+        } else if name == "2.5pn" {
+            Ok(PostNewtonian::RadiationReaction(speed_of_light))
+        } else {
+            Err(format!("Invalid post-Newtonian order {name}"))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostNewtonian::None => "none",
+            PostNewtonian::FirstOrder(_) => "1pn",
+            PostNewtonian::RadiationReaction(_) => "2.5pn",
+        }
+    }
+
+    pub fn speed_of_light(&self) -> Float {
+        match *self {
+            PostNewtonian::None => 0.0,
+            PostNewtonian::FirstOrder(c) | PostNewtonian::RadiationReaction(c) => c,
+        }
+    }
+
+    pub fn pair_correction(
+        &self,
+        m1: Float,
+        m2: Float,
+        position1: (Float, Float, Float),
+        velocity1: (Float, Float, Float),
+        position2: (Float, Float, Float),
+        velocity2: (Float, Float, Float),
+    ) -> (Float, Float, Float) {
+        // Correction to the acceleration on body 1 of mass m1 from body 2 of mass m2
+        match *self {
+            PostNewtonian::None => (0.0, 0.0, 0.0),
+            PostNewtonian::FirstOrder(c) => {
+                compute_1pn_correction(m1, m2, position1, velocity1, position2, velocity2, c)
+            }
+            PostNewtonian::RadiationReaction(c) => {
+                let a =
+                    compute_1pn_correction(m1, m2, position1, velocity1, position2, velocity2, c);
+                let b =
+                    compute_2_5pn_correction(m1, m2, position1, velocity1, position2, velocity2, c);
+                (a.0 + b.0, a.1 + b.1, a.2 + b.2)
+            }
+        }
+    }
+}
+
+pub fn generate_acc_vectors_pn(
+    position_vectors: &VectorArray,
+    velocity_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    softening: Softening,
+    post_newtonian: PostNewtonian,
+    threads: usize,
+) -> VectorArray {
+    // Direct sum as in generate_acc_vectors(), with each pair force corrected
+    let n = mass_vectors.len();
+    let active = active_count(mass_vectors);
+    let accs = parallel::map_bodies(n, threads, |i| {
+        let mut acc = (0.0, 0.0, 0.0);
+
+        for j in 0..active {
+            if j != i {
+                let newtonian = compute_m_by_r_sq(
+                    mass_vectors[j],
+                    position_vectors.at(i),
+                    position_vectors.at(j),
+                    softening,
+                );
+                let correction = post_newtonian.pair_correction(
+                    mass_vectors[i],
+                    mass_vectors[j],
+                    position_vectors.at(i),
+                    velocity_vectors.at(i),
+                    position_vectors.at(j),
+                    velocity_vectors.at(j),
+                );
+                acc.0 += newtonian.0 + correction.0;
+                acc.1 += newtonian.1 + correction.1;
+                acc.2 += newtonian.2 + correction.2;
+            }
+        }
+        acc
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+fn dot(a: (Float, Float, Float), b: (Float, Float, Float)) -> Float {
+    a.0 * b.0 + a.1 * b.1 + a.2 * b.2
+}
+
+pub fn compute_1pn_correction(
+    m1: Float,
+    m2: Float,
+    position1: (Float, Float, Float),
+    velocity1: (Float, Float, Float),
+    position2: (Float, Float, Float),
+    velocity2: (Float, Float, Float),
+    c: Float,
+) -> (Float, Float, Float) {
+    // EIH terms for a binary, with n the unit vector from body 1 to body 2:
+    // a = m2 / (r^2 c^2) [(v1^2 + 2 v2^2 - 4 v1.v2 - 3/2 (n.v2)^2 - 5 m1 / r - 4 m2 / r) n
+    //                     + (n.(4 v1 - 3 v2)) (v2 - v1)]
+    // In the test particle limit this gives the Schwarzschild precession of
+    // 6 pi m2 / (c^2 a (1 - e^2)) per orbit
+    let d = (
+        position2.0 - position1.0,
+        position2.1 - position1.1,
+        position2.2 - position1.2,
+    );
+    let r = dot(d, d).sqrt();
+    let n = (d.0 / r, d.1 / r, d.2 / r);
+    let v = (
+        velocity2.0 - velocity1.0,
+        velocity2.1 - velocity1.1,
+        velocity2.2 - velocity1.2,
+    );
+
+    let n_v2 = dot(n, velocity2);
+    let radial = dot(velocity1, velocity1) + 2.0 * dot(velocity2, velocity2)
+        - 4.0 * dot(velocity1, velocity2)
+        - 1.5 * n_v2 * n_v2
+        - (5.0 * m1 + 4.0 * m2) / r;
+    let along_v = 4.0 * dot(n, velocity1) - 3.0 * n_v2;
+    let a = m2 / (r * r * c * c);
+
+    (
+        a * (radial * n.0 + along_v * v.0),
+        a * (radial * n.1 + along_v * v.1),
+        a * (radial * n.2 + along_v * v.2),
+    )
+}
+
+pub fn compute_2_5pn_correction(
+    m1: Float,
+    m2: Float,
+    position1: (Float, Float, Float),
+    velocity1: (Float, Float, Float),
+    position2: (Float, Float, Float),
+    velocity2: (Float, Float, Float),
+    c: Float,
+) -> (Float, Float, Float) {
+    // Radiation reaction on the relative orbit, x = x1 - x2 and v = v1 - v2, of total mass M
+    // and symmetric mass ratio eta = m1 m2 / M^2:
+    // a_rel = 8/5 eta M^2 / (c^5 r^3) [(3 v^2 + 17/3 M / r) r_dot n - (v^2 + 3 M / r) v]
+    // shared out as a1 = m2 / M a_rel, so the centre of mass is unaffected
+    // Averaged over an orbit, this loses energy at the rate of the quadrupole formula
+    // (Peters & Mathews 1963)
+    let total = m1 + m2;
+    if m1 == 0.0 || m2 == 0.0 {
+        // Test particles neither radiate nor are affected by others radiating
+        return (0.0, 0.0, 0.0);
+    }
+    let x = (
+        position1.0 - position2.0,
+        position1.1 - position2.1,
+        position1.2 - position2.2,
+    );
+    let r = dot(x, x).sqrt();
+    let n = (x.0 / r, x.1 / r, x.2 / r);
+    let v = (
+        velocity1.0 - velocity2.0,
+        velocity1.1 - velocity2.1,
+        velocity1.2 - velocity2.2,
+    );
+
+    let v_sq = dot(v, v);
+    let r_dot = dot(n, v);
+    let along_n = (3.0 * v_sq + 17.0 / 3.0 * total / r) * r_dot;
+    let along_v = v_sq + 3.0 * total / r;
+    let a = 1.6 * m1 * m2 * m2 / (total * c.powi(5) * r.powi(3));
+
+    (
+        a * (along_n * n.0 - along_v * v.0),
+        a * (along_n * n.1 - along_v * v.1),
+        a * (along_n * n.2 - along_v * v.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::post_newtonian::*;
+    use crate::units::UnitSystem;
+    use crate::{float_consts, Simulation};
+
+    #[test]
+    fn test_perihelion_precession() {
+        // Mercury's orbit around the Sun, with the speed of light lowered from 63000 to
+        // 450 au / year, so the orbit precesses by about 0.01 rather than 5e-7 radians
+        let units = UnitSystem::ASTRONOMICAL;
+        let sun = units.simulation_mass(1.0);
+        let mercury = units.simulation_mass(1.66e-7);
+        let (a, e) = (0.387, 0.2056);
+        let total = sun + mercury;
+        let speed = (total * (1.0 + e) / (a * (1.0 - e))).sqrt();
+        let mut position_vectors = VectorArray::zeros(2);
+        let mut velocity_vectors = VectorArray::zeros(2);
+        position_vectors.x[1] = a * (1.0 - e);
+        velocity_vectors.y[0] = -speed * mercury / total;
+        velocity_vectors.y[1] = speed * sun / total;
+
+        let c = 450.0;
+        let mut simulation = Simulation::new(
+            vec![sun, mercury],
+            position_vectors,
+            velocity_vectors,
+            "direct",
+            0.0,
+            "rk4",
+            Softening::None,
+        );
+        simulation.units = units;
+        simulation.post_newtonian = PostNewtonian::FirstOrder(c);
+        simulation.acc_vectors = simulation.compute_acc_vectors();
+        simulation.h = 1.0e-4;
+
+        // Direction of the Laplace-Runge-Lenz vector v x L - M r / |r| of the relative
+        // orbit at each perihelion passage, where r.v turns from negative to positive
+        let mut angles = Vec::new();
+        let mut last_r_dot_v: Float = 0.0;
+        while angles.len() < 6 {
+            simulation.update();
+            let (x, y, _) = simulation.position_vectors.at(1);
+            let (x0, y0, _) = simulation.position_vectors.at(0);
+            let (vx, vy, _) = simulation.velocity_vectors.at(1);
+            let (vx0, vy0, _) = simulation.velocity_vectors.at(0);
+            let (x, y, vx, vy) = (x - x0, y - y0, vx - vx0, vy - vy0);
+            let r_dot_v = x * vx + y * vy;
+            if last_r_dot_v < 0.0 && r_dot_v >= 0.0 {
+                let angular_momentum = x * vy - y * vx;
+                let r = (x * x + y * y).sqrt();
+                let lrl_x = vy * angular_momentum - total * x / r;
+                let lrl_y = -vx * angular_momentum - total * y / r;
+                angles.push(lrl_y.atan2(lrl_x));
+            }
+            last_r_dot_v = r_dot_v;
+        }
+
+        let rate = (angles[5] - angles[0]) / 5.0;
+        let expected = 6.0 * float_consts::PI * total / (c * c * a * (1.0 - e * e));
+        assert!(
+            (rate / expected - 1.0).abs() < 0.01,
+            "precession of {rate} rather than {expected} per orbit"
+        );
+    }
+
+    #[test]
+    fn test_radiation_reaction_power() {
+        // On a circular binary, radiation reaction drains energy at the rate of the
+        // quadrupole formula, dE/dt = -32/5 m1^2 m2^2 M / (c^5 r^5) with G = 1
+        let (m1, m2) = (3.0, 1.0);
+        let total: Float = m1 + m2;
+        let r = 10.0;
+        let c = 3.0;
+        let speed = (total / r).sqrt();
+        let position1 = (-r * m2 / total, 0.0, 0.0);
+        let position2 = (r * m1 / total, 0.0, 0.0);
+        let velocity1 = (0.0, -speed * m2 / total, 0.0);
+        let velocity2 = (0.0, speed * m1 / total, 0.0);
+
+        let a1 = compute_2_5pn_correction(m1, m2, position1, velocity1, position2, velocity2, c);
+        let a2 = compute_2_5pn_correction(m2, m1, position2, velocity2, position1, velocity1, c);
+        let power = m1 * dot(a1, velocity1) + m2 * dot(a2, velocity2);
+        let expected = -6.4 * m1 * m1 * m2 * m2 * total / (c.powi(5) * r.powi(5));
+        assert!((power / expected - 1.0).abs() < 1e-4);
+
+        // and keeps the centre of mass where it is
+        let momentum_change = (m1 * a1.0 + m2 * a2.0, m1 * a1.1 + m2 * a2.1, 0.0);
+        assert!(dot(momentum_change, momentum_change).sqrt() < 1e-6 * m1 * dot(a1, a1).sqrt());
+    }
+}
@@ -0,0 +1,405 @@
+use crate::parallel::map_bodies;
+use crate::softening::Softening;
+use crate::{compute_m_by_r_sq, Float, VectorArray};
+
+// Deep enough to separate any two distinct Float positions on screen scales,
+// while stopping coincident particles from partitioning forever
+pub const QUADTREE_MAX_DEPTH: u32 = 32;
+
+pub struct Quadtree {
+    // Coordinates of center of quadtree
+    pub center: (Float, Float),
+    // Width and height
+    pub dimensions: (Float, Float),
+    pub current_depth: u32,
+    pub max_depth: u32,
+    // Index of particles contained within the tree, only populated for leaves
+    pub contents: Vec<usize>,
+    // Mass of tree
+    pub mass: Float,
+    // Location of center of mass
+    pub com: (Float, Float, Float),
+    // Lowest and highest z of the particles within, as the tree only divides x and y
+    // Zero in two dimensions, and taken into account when deciding whether to open a node
+    pub z_range: (Float, Float),
+    // Empty for leaves, otherwise four quadrants indexed as in find_quadrant()
+    pub children: Vec<Quadtree>,
+}
+
+impl Quadtree {
+    pub fn new(center: (Float, Float), dimensions: (Float, Float), max_depth: u32) -> Quadtree {
+        // Creates new root Quadtree
+        Quadtree {
+            center,
+            dimensions,
+            current_depth: 0,
+            max_depth,
+            contents: Vec::new(),
+            mass: 0.0,
+            com: (center.0, center.1, 0.0),
+            z_range: (0.0, 0.0),
+            children: Vec::new(),
+        }
+    }
+
+    pub fn from_positions(
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        max_depth: u32,
+    ) -> Quadtree {
+        // Builds a square tree enclosing all particles, with masses and centers of mass computed
+        let mut x_min = Float::INFINITY;
+        let mut x_max = Float::NEG_INFINITY;
+        let mut y_min = Float::INFINITY;
+        let mut y_max = Float::NEG_INFINITY;
+        for i in 0..position_vectors.x.len() {
+            x_min = x_min.min(position_vectors.x[i]);
+            x_max = x_max.max(position_vectors.x[i]);
+            y_min = y_min.min(position_vectors.y[i]);
+            y_max = y_max.max(position_vectors.y[i]);
+        }
+        if position_vectors.x.is_empty() {
+            (x_min, x_max, y_min, y_max) = (0.0, 0.0, 0.0, 0.0);
+        }
+
+        // Padded slightly so particles on the bounding box edge are still inside the root
+        let side = (x_max - x_min).max(y_max - y_min) * 1.01 + Float::EPSILON;
+        let center = (0.5 * (x_min + x_max), 0.5 * (y_min + y_max));
+
+        let mut quadtree = Quadtree::new(center, (side, side), max_depth);
+        for i in 0..position_vectors.x.len() {
+            quadtree.insert(i, position_vectors);
+        }
+        quadtree.compute_mass_distribution(position_vectors, mass_vectors);
+
+        quadtree
+    }
+
+    pub fn find_quadrant(&self, x: Float, y: Float) -> usize {
+        // Quadrants are indexed by the sign of the offset from center, x right, y upwards
+        // 0: ++ : right, top
+        // 1: +- : right, bottom
+        // 2: -+ : left, top
+        // 3: -- : left, bottom
+        let mut index = 0;
+        if x <= self.center.0 {
+            index += 2;
+        }
+        if y <= self.center.1 {
+            index += 1;
+        }
+
+        index
+    }
+
+    pub fn contains_point(&self, x: Float, y: Float) -> bool {
+        (x - self.center.0).abs() <= 0.5 * self.dimensions.0
+            && (y - self.center.1).abs() <= 0.5 * self.dimensions.1
+    }
+
+    pub fn insert(&mut self, index: usize, position_vectors: &VectorArray) {
+        // Inserts particle index into the tree, partitioning leaves holding more than one particle
+        if self.children.is_empty() {
+            self.contents.push(index);
+            if self.contents.len() > 1 && self.current_depth < self.max_depth {
+                self.partition(position_vectors);
+            }
+        } else {
+            let quadrant = self.find_quadrant(position_vectors.x[index], position_vectors.y[index]);
+            self.children[quadrant].insert(index, position_vectors);
+        }
+    }
+
+    fn partition(&mut self, position_vectors: &VectorArray) {
+        // Splits a leaf into four quadrants and moves its particles into them
+        let width = 0.5 * self.dimensions.0;
+        let height = 0.5 * self.dimensions.1;
+
+        for quadrant in 0..4 {
+            let x_offset = if quadrant < 2 {
+                0.5 * width
+            } else {
+                -0.5 * width
+            };
+            let y_offset = if quadrant % 2 == 0 {
+                0.5 * height
+            } else {
+                -0.5 * height
+            };
+            let center = (self.center.0 + x_offset, self.center.1 + y_offset);
+
+            self.children.push(Quadtree {
+                current_depth: self.current_depth + 1,
+                ..Quadtree::new(center, (width, height), self.max_depth)
+            });
+        }
+
+        let contents = std::mem::take(&mut self.contents);
+        for index in contents {
+            self.insert(index, position_vectors);
+        }
+    }
+
+    pub fn compute_mass_distribution(
+        &mut self,
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+    ) {
+        // Computes mass, center of mass and z range for entire tree
+        let mut mass = 0.0;
+        let mut com_x = 0.0;
+        let mut com_y = 0.0;
+        let mut com_z = 0.0;
+        let mut z_range = (Float::INFINITY, Float::NEG_INFINITY);
+
+        if self.children.is_empty() {
+            for &j in &self.contents {
+                mass += mass_vectors[j];
+                com_x += mass_vectors[j] * position_vectors.x[j];
+                com_y += mass_vectors[j] * position_vectors.y[j];
+                com_z += mass_vectors[j] * position_vectors.z[j];
+                z_range = (
+                    z_range.0.min(position_vectors.z[j]),
+                    z_range.1.max(position_vectors.z[j]),
+                );
+            }
+        } else {
+            for child in &mut self.children {
+                child.compute_mass_distribution(position_vectors, mass_vectors);
+                mass += child.mass;
+                com_x += child.mass * child.com.0;
+                com_y += child.mass * child.com.1;
+                com_z += child.mass * child.com.2;
+                if child.mass > 0.0 {
+                    z_range = (
+                        z_range.0.min(child.z_range.0),
+                        z_range.1.max(child.z_range.1),
+                    );
+                }
+            }
+        }
+
+        self.mass = mass;
+        if mass > 0.0 {
+            self.com = (com_x / mass, com_y / mass, com_z / mass);
+            self.z_range = z_range;
+        } else {
+            self.com = (self.center.0, self.center.1, 0.0);
+        }
+    }
+
+    pub fn acc_at(
+        &self,
+        i: usize,
+        position_vectors: &VectorArray,
+        mass_vectors: &[Float],
+        theta: Float,
+        softening: Softening,
+    ) -> (Float, Float, Float) {
+        // Walks the tree, summing the acceleration on particle i
+        // A node is treated as a single mass at its center of mass when its size over its
+        // distance from the particle is below theta, and the particle does not lie inside it
+        // Size is the largest of the node's width, height and the z range of its particles
+        if self.mass == 0.0 {
+            return (0.0, 0.0, 0.0);
+        }
+
+        let position = position_vectors.at(i);
+        let (x, y, z) = position;
+        let mut acc_x = 0.0;
+        let mut acc_y = 0.0;
+        let mut acc_z = 0.0;
+
+        if self.children.is_empty() {
+            for &j in &self.contents {
+                if j != i {
+                    let acc = compute_m_by_r_sq(
+                        mass_vectors[j],
+                        position,
+                        position_vectors.at(j),
+                        softening,
+                    );
+                    acc_x += acc.0;
+                    acc_y += acc.1;
+                    acc_z += acc.2;
+                }
+            }
+            return (acc_x, acc_y, acc_z);
+        }
+
+        let r =
+            ((self.com.0 - x).powi(2) + (self.com.1 - y).powi(2) + (self.com.2 - z).powi(2)).sqrt();
+        let size = self
+            .dimensions
+            .0
+            .max(self.dimensions.1)
+            .max(self.z_range.1 - self.z_range.0);
+        if !self.contains_point(x, y) && size < theta * r {
+            return compute_m_by_r_sq(self.mass, position, self.com, softening);
+        }
+
+        for child in &self.children {
+            let acc = child.acc_at(i, position_vectors, mass_vectors, theta, softening);
+            acc_x += acc.0;
+            acc_y += acc.1;
+            acc_z += acc.2;
+        }
+
+        (acc_x, acc_y, acc_z)
+    }
+}
+
+pub fn generate_acc_vectors_barnes_hut(
+    position_vectors: &VectorArray,
+    mass_vectors: &[Float],
+    theta: Float,
+    softening: Softening,
+    threads: usize,
+) -> VectorArray {
+    // Barnes-Hut approximation of generate_acc_vectors(), O(n log n) instead of O(n^2)
+    // The tree is built serially, and then walked for each body in parallel
+    let quadtree = Quadtree::from_positions(position_vectors, mass_vectors, QUADTREE_MAX_DEPTH);
+    let accs = map_bodies(mass_vectors.len(), threads, |i| {
+        quadtree.acc_at(i, position_vectors, mass_vectors, theta, softening)
+    });
+
+    let mut acc_vectors = VectorArray::zeros(0);
+    for acc in accs {
+        acc_vectors.push(acc);
+    }
+
+    acc_vectors
+}
+
+#[cfg(test)]
+mod tests {
+    use ::rand::prelude::*;
+
+    use crate::quadtree::{generate_acc_vectors_barnes_hut, Quadtree, QUADTREE_MAX_DEPTH};
+    use crate::softening::Softening;
+    use crate::test_helpers::{random_particles, relative_error};
+    use crate::{generate_acc_vectors, Float, VectorArray};
+
+    const FLOAT_TOLERANCE: Float = 0.000_1;
+
+    #[test]
+    fn test_new_quadtree() {
+        let quadtree = Quadtree::new((0.0, 0.0), (10.0, 10.0), 5);
+
+        assert_eq!(quadtree.center, (0.0, 0.0));
+        assert_eq!(quadtree.dimensions, (10.0, 10.0));
+        assert_eq!(quadtree.max_depth, 5);
+        assert_eq!(quadtree.current_depth, 0);
+        assert!(quadtree.children.is_empty());
+    }
+
+    #[test]
+    fn test_find_quadrant() {
+        let quadtree = Quadtree::new((0.0, 0.0), (10.0, 10.0), 5);
+
+        assert_eq!(quadtree.find_quadrant(1.0, 1.0), 0);
+        assert_eq!(quadtree.find_quadrant(1.0, -1.0), 1);
+        assert_eq!(quadtree.find_quadrant(-1.0, 1.0), 2);
+        assert_eq!(quadtree.find_quadrant(-1.0, -1.0), 3);
+    }
+
+    #[test]
+    fn test_compute_mass_distribution() {
+        let (position_vectors, mass_vectors) = random_particles(200, 0.0);
+        let quadtree =
+            Quadtree::from_positions(&position_vectors, &mass_vectors, QUADTREE_MAX_DEPTH);
+
+        let mut mass = 0.0;
+        let mut com_x = 0.0;
+        let mut com_y = 0.0;
+        for i in 0..mass_vectors.len() {
+            mass += mass_vectors[i];
+            com_x += mass_vectors[i] * position_vectors.x[i];
+            com_y += mass_vectors[i] * position_vectors.y[i];
+        }
+        com_x /= mass;
+        com_y /= mass;
+
+        assert!((quadtree.mass - mass).abs() / mass < FLOAT_TOLERANCE);
+        assert!((quadtree.com.0 - com_x).abs() / com_x < FLOAT_TOLERANCE);
+        assert!((quadtree.com.1 - com_y).abs() / com_y < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_coincident_particles() {
+        // Without a maximum depth, identical positions would partition forever
+        let position_vectors = VectorArray {
+            x: vec![5.0, 5.0, 5.0],
+            y: vec![5.0, 5.0, 5.0],
+            z: vec![5.0, 5.0, 5.0],
+        };
+        let mass_vectors = vec![1.0, 1.0, 1.0];
+        let quadtree = Quadtree::from_positions(&position_vectors, &mass_vectors, 8);
+
+        assert!((quadtree.mass - 3.0).abs() < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_theta_zero_matches_direct() {
+        let (position_vectors, mass_vectors) = random_particles(300, 0.0);
+        let direct =
+            generate_acc_vectors(&position_vectors, &mass_vectors, Softening::default(), 1);
+        let barnes_hut = generate_acc_vectors_barnes_hut(
+            &position_vectors,
+            &mass_vectors,
+            0.0,
+            Softening::default(),
+            1,
+        );
+
+        assert!(relative_error(&barnes_hut, &direct) < FLOAT_TOLERANCE);
+    }
+
+    #[test]
+    fn test_barnes_hut_against_direct() {
+        // Monopole truncation error scales roughly as theta^2
+        let (position_vectors, mass_vectors) = random_particles(1000, 0.0);
+        let direct =
+            generate_acc_vectors(&position_vectors, &mass_vectors, Softening::default(), 1);
+
+        for theta in [0.3, 0.5, 0.7, 1.0] {
+            let barnes_hut = generate_acc_vectors_barnes_hut(
+                &position_vectors,
+                &mass_vectors,
+                theta,
+                Softening::default(),
+                1,
+            );
+            let error = relative_error(&barnes_hut, &direct);
+            println!("theta = {theta}: relative error = {error}");
+
+            assert!(error < 0.1 * theta * theta);
+        }
+    }
+
+    #[test]
+    fn test_barnes_hut_three_dimensional() {
+        // Bodies spread through a cube, where nodes are opened based on their z range as well
+        let (mut position_vectors, mass_vectors) = random_particles(1000, 0.0);
+        let mut rng = StdRng::seed_from_u64(7);
+        for z in position_vectors.z.iter_mut() {
+            *z = rng.gen::<Float>() * 1000.0;
+        }
+        let direct =
+            generate_acc_vectors(&position_vectors, &mass_vectors, Softening::default(), 1);
+
+        for theta in [0.0, 0.5, 1.0] {
+            let barnes_hut = generate_acc_vectors_barnes_hut(
+                &position_vectors,
+                &mass_vectors,
+                theta,
+                Softening::default(),
+                1,
+            );
+            let error = relative_error(&barnes_hut, &direct);
+            println!("theta = {theta}: relative error = {error}");
+
+            assert!(error < 0.1 * theta * theta + FLOAT_TOLERANCE);
+        }
+    }
+}
@@ -0,0 +1,362 @@
+use std::io;
+use std::path::Path;
+
+use ::rand::prelude::*;
+
+use crate::diagnostics::{centre_of_mass, momentum};
+use crate::ephemeris::read_ephemeris;
+use crate::initial_conditions::{new_rng, sample_hernquist};
+use crate::softening::Softening;
+use crate::units::UnitSystem;
+use crate::{float_consts, Float, VectorArray};
+
+// Scenarios are built by composing components, each a set of masses, positions and
+// velocities from a generator, placed with an offset in position and a bulk velocity
+//
+// let (mass_vectors, position_vectors, velocity_vectors) = Scenario::new()
+//     .add_component(galaxy_a.generate(&mut rng), (-200.0, 0.0, 0.0), (0.0, 50.0, 0.0))
+//     .add_component(galaxy_b.generate(&mut rng), (200.0, 0.0, 0.0), (0.0, -50.0, 0.0))
+//     .build();
+
+#[derive(Default)]
+pub struct Scenario {
+    mass_vectors: Vec<Float>,
+    position_vectors: VectorArray,
+    velocity_vectors: VectorArray,
+}
+
+impl Scenario {
+    pub fn new() -> Scenario {
+        Scenario::default()
+    }
+
+    pub fn add_component(
+        mut self,
+        component: (Vec<Float>, VectorArray, VectorArray),
+        offset: (Float, Float, Float),
+        bulk_velocity: (Float, Float, Float),
+    ) -> Scenario {
+        let (mass_vectors, position_vectors, velocity_vectors) = component;
+
+        self.mass_vectors.extend(mass_vectors);
+        self.position_vectors
+            .x
+            .extend(position_vectors.x.iter().map(|x| x + offset.0));
+        self.position_vectors
+            .y
+            .extend(position_vectors.y.iter().map(|y| y + offset.1));
+        self.position_vectors
+            .z
+            .extend(position_vectors.z.iter().map(|z| z + offset.2));
+        self.velocity_vectors
+            .x
+            .extend(velocity_vectors.x.iter().map(|vx| vx + bulk_velocity.0));
+        self.velocity_vectors
+            .y
+            .extend(velocity_vectors.y.iter().map(|vy| vy + bulk_velocity.1));
+        self.velocity_vectors
+            .z
+            .extend(velocity_vectors.z.iter().map(|vz| vz + bulk_velocity.2));
+
+        self
+    }
+
+    pub fn centred(self) -> Scenario {
+        // Moves everything added so far to its centre of mass frame
+        let total_mass: Float = self.mass_vectors.iter().sum();
+        let (x, y, z) = centre_of_mass(&self.position_vectors, &self.mass_vectors);
+        let (px, py, pz) = momentum(&self.velocity_vectors, &self.mass_vectors);
+        let bulk_velocity = (-px / total_mass, -py / total_mass, -pz / total_mass);
+        let components = (
+            self.mass_vectors,
+            self.position_vectors,
+            self.velocity_vectors,
+        );
+
+        Scenario::new().add_component(components, (-x, -y, -z), bulk_velocity)
+    }
+
+    pub fn build(self) -> (Vec<Float>, VectorArray, VectorArray) {
+        (
+            self.mass_vectors,
+            self.position_vectors,
+            self.velocity_vectors,
+        )
+    }
+}
+
+// Rotating disk galaxy: an exponential disk of equal mass bodies on circular orbits around
+// a central body, optionally embedded in a Hernquist halo
+// The disk lies in the x-y plane, and the halo is spherical
+// Masses include G, as for mass_vectors, and a zero central_mass or halo_n leaves that part out
+pub struct DiskGalaxy {
+    pub n: u32,
+    pub disk_mass: Float,
+    pub scale_length: Float,
+    pub central_mass: Float,
+    pub halo_n: u32,
+    pub halo_mass: Float,
+    pub halo_scale_radius: Float,
+    // Disks rotate anticlockwise unless set
+    pub clockwise: bool,
+    // Should match the simulation, so orbits are circular under the softened forces
+    pub softening: Softening,
+}
+
+// Disk bodies are placed within this many scale lengths, which holds over 98% of the mass
+const DISK_MAX_SCALE_LENGTHS: Float = 6.0;
+
+impl DiskGalaxy {
+    pub fn generate(&self, rng: &mut StdRng) -> (Vec<Float>, VectorArray, VectorArray) {
+        // Central body first, then the disk, then the halo, moved to the centre of mass frame
+        // so sampling noise does not leave the galaxy drifting
+        // Circular speeds come from the mass enclosed by each orbit, treated as spherical,
+        // under the softened force. This is close to equilibrium for thin disks
+        // light compared to the central mass and halo
+        let mut scenario = Scenario::new();
+        if self.central_mass > 0.0 {
+            let central = (
+                vec![self.central_mass],
+                VectorArray::zeros(1),
+                VectorArray::zeros(1),
+            );
+            scenario = scenario.add_component(central, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+        }
+
+        let mut disk = (
+            vec![self.disk_mass / self.n as Float; self.n as usize],
+            VectorArray::zeros(0),
+            VectorArray::zeros(0),
+        );
+        let sense = if self.clockwise { -1.0 } else { 1.0 };
+        for _i in 0..self.n {
+            let r = self.sample_disk_radius(rng);
+            let phi = 2.0 * float_consts::PI * rng.gen::<Float>();
+            let speed = self.circular_speed(r);
+            disk.1.push((r * phi.cos(), r * phi.sin(), 0.0));
+            disk.2
+                .push((-sense * speed * phi.sin(), sense * speed * phi.cos(), 0.0));
+        }
+        scenario = scenario.add_component(disk, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+
+        if self.halo_n > 0 {
+            let halo = sample_hernquist(self.halo_n, self.halo_mass, self.halo_scale_radius, rng);
+            scenario = scenario.add_component(halo, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0));
+        }
+
+        scenario.centred().build()
+    }
+
+    pub fn total_mass(&self) -> Float {
+        let halo_mass = if self.halo_n > 0 { self.halo_mass } else { 0.0 };
+        self.central_mass + self.disk_mass + halo_mass
+    }
+
+    fn sample_disk_radius(&self, rng: &mut StdRng) -> Float {
+        // Surface density proportional to exp(-R / Rd) gives radii following a gamma
+        // distribution of shape 2, which is the sum of two exponential draws
+        loop {
+            let x = -(rng.gen::<Float>() * rng.gen::<Float>())
+                .max(Float::MIN_POSITIVE)
+                .ln();
+            if x < DISK_MAX_SCALE_LENGTHS {
+                return x * self.scale_length;
+            }
+        }
+    }
+
+    fn circular_speed(&self, r: Float) -> Float {
+        let x = r / self.scale_length;
+        let mut enclosed_mass = self.central_mass + self.disk_mass * (1.0 - (1.0 + x) * (-x).exp());
+        if self.halo_n > 0 {
+            enclosed_mass += self.halo_mass * (r / (r + self.halo_scale_radius)).powi(2);
+        }
+
+        (enclosed_mass * r * r * self.softening.force_factor(r * r)).sqrt()
+    }
+}
+
+pub fn toomre_encounter(
+    n: u32,
+    softening: Softening,
+    seed: u64,
+) -> (Vec<Float>, VectorArray, VectorArray) {
+    // Two equal disk galaxies on a parabolic orbit, after Toomre & Toomre (1972)
+    // One disk rotates with the orbit and one against it, so the prograde disk throws out
+    // long tidal tails while the retrograde one stays mostly intact
+    // The orbit is anticlockwise, with the galaxies starting apart along the x axis
+    // Each galaxy gets half of n including its central body, and the prograde disk takes the
+    // odd one out. Galaxies have equal disk masses either way, so stay equal
+    assert!(n >= 4, "The toomre encounter needs at least 4 bodies");
+    let mut rng = new_rng(seed);
+    let galaxy = |n, clockwise| DiskGalaxy {
+        n,
+        disk_mass: 1.0e6,
+        scale_length: 30.0,
+        central_mass: 5.0e6,
+        halo_n: 0,
+        halo_mass: 0.0,
+        halo_scale_radius: 0.0,
+        clockwise,
+        softening,
+    };
+    let prograde = galaxy(n - n / 2 - 1, false);
+    let retrograde = galaxy(n / 2 - 1, true);
+
+    let (offset, velocity) = parabolic_orbit(
+        prograde.total_mass() + retrograde.total_mass(),
+        500.0,
+        100.0,
+    );
+    // Equal masses, so each galaxy moves half of the relative separation and velocity
+    Scenario::new()
+        .add_component(
+            prograde.generate(&mut rng),
+            (-0.5 * offset.0, -0.5 * offset.1, 0.0),
+            (-0.5 * velocity.0, -0.5 * velocity.1, 0.0),
+        )
+        .add_component(
+            retrograde.generate(&mut rng),
+            (0.5 * offset.0, 0.5 * offset.1, 0.0),
+            (0.5 * velocity.0, 0.5 * velocity.1, 0.0),
+        )
+        .build()
+}
+
+pub fn solar_system(
+    path: &Path,
+    units: &UnitSystem,
+) -> io::Result<(Vec<Float>, VectorArray, VectorArray)> {
+    // Bodies from an ephemeris file such as data/solar_system.csv, moved from heliocentric
+    // coordinates to the barycentre frame so the system does not drift
+    let ephemeris = read_ephemeris(path, units)?;
+    let bodies = (
+        ephemeris.mass_vectors,
+        ephemeris.position_vectors,
+        ephemeris.velocity_vectors,
+    );
+
+    Ok(Scenario::new()
+        .add_component(bodies, (0.0, 0.0, 0.0), (0.0, 0.0, 0.0))
+        .centred()
+        .build())
+}
+
+pub fn parabolic_orbit(
+    total_mass: Float,
+    separation: Float,
+    pericentre: Float,
+) -> ((Float, Float), (Float, Float)) {
+    // Relative position and velocity of the second body with respect to the first,
+    // approaching on a parabola with the given pericentre, treating both as point masses
+    // Speed is the escape speed, with angular momentum sqrt(2 M q) fixing its tangential part
+    let speed = (2.0 * total_mass / separation).sqrt();
+    let tangential = (2.0 * total_mass * pericentre).sqrt() / separation;
+    let radial = (speed * speed - tangential * tangential).max(0.0).sqrt();
+
+    ((separation, 0.0), (-radial, tangential))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::initial_conditions::new_rng;
+    use crate::scenario::*;
+
+    #[test]
+    fn test_add_component_offsets() {
+        let component = || {
+            (
+                vec![1.0, 2.0],
+                VectorArray {
+                    x: vec![-1.0, 1.0],
+                    y: vec![0.0, 3.0],
+                    z: vec![0.0, 0.0],
+                },
+                VectorArray {
+                    x: vec![0.5, 0.0],
+                    y: vec![0.0, -0.5],
+                    z: vec![0.0, 0.0],
+                },
+            )
+        };
+
+        let (mass_vectors, position_vectors, velocity_vectors) = Scenario::new()
+            .add_component(component(), (0.0, 0.0, 0.0), (0.0, 0.0, 0.0))
+            .add_component(component(), (100.0, -50.0, 5.0), (2.0, 1.0, -1.0))
+            .build();
+
+        assert_eq!(mass_vectors, [1.0, 2.0, 1.0, 2.0]);
+        assert_eq!(position_vectors.x, [-1.0, 1.0, 99.0, 101.0]);
+        assert_eq!(position_vectors.y, [0.0, 3.0, -50.0, -47.0]);
+        assert_eq!(velocity_vectors.x, [0.5, 0.0, 2.5, 2.0]);
+        assert_eq!(velocity_vectors.y, [0.0, -0.5, 1.0, 0.5]);
+        assert_eq!(position_vectors.z, [0.0, 0.0, 5.0, 5.0]);
+        assert_eq!(velocity_vectors.z, [0.0, 0.0, -1.0, -1.0]);
+    }
+
+    #[test]
+    fn test_disk_galaxy_rotation() {
+        let galaxy = DiskGalaxy {
+            n: 500,
+            disk_mass: 1.0e6,
+            scale_length: 30.0,
+            central_mass: 5.0e6,
+            halo_n: 200,
+            halo_mass: 2.0e6,
+            halo_scale_radius: 100.0,
+            clockwise: true,
+            softening: Softening::default(),
+        };
+        let (mass_vectors, position_vectors, velocity_vectors) = galaxy.generate(&mut new_rng(3));
+
+        assert_eq!(mass_vectors.len(), 701);
+        assert!((mass_vectors.iter().sum::<Float>() - galaxy.total_mass()).abs() < 10.0);
+        // Relative to the central body, disk bodies move perpendicular to their radius with
+        // the chosen sense of rotation
+        for i in 1..=500 {
+            let x = position_vectors.x[i] - position_vectors.x[0];
+            let y = position_vectors.y[i] - position_vectors.y[0];
+            let vx = velocity_vectors.x[i] - velocity_vectors.x[0];
+            let vy = velocity_vectors.y[i] - velocity_vectors.y[0];
+            let speed = (vx * vx + vy * vy).sqrt();
+            let r = (x * x + y * y).sqrt();
+            assert!((x * vx + y * vy).abs() < 1e-3 * r * speed);
+            assert!(x * vy - y * vx < 0.0);
+            assert!(r <= 6.0 * 30.0);
+        }
+    }
+
+    #[test]
+    fn test_toomre_encounter() {
+        for n in [1000, 1001, 4] {
+            let (mass_vectors, position_vectors, velocity_vectors) =
+                toomre_encounter(n, Softening::default(), 11);
+
+            assert_eq!(mass_vectors.len(), n as usize);
+            // Equal galaxies placed symmetrically leave the system at rest at the origin
+            let total_mass: Float = mass_vectors.iter().sum();
+            let (x, y, _) = centre_of_mass(&position_vectors, &mass_vectors);
+            let (px, py, _) = momentum(&velocity_vectors, &mass_vectors);
+            assert!(x.abs() < 1e-2 && y.abs() < 1e-2, "{n}");
+            assert!(px.abs() < 1e-3 * total_mass && py.abs() < 1e-3 * total_mass);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 4 bodies")]
+    fn test_toomre_encounter_too_few_bodies() {
+        toomre_encounter(3, Softening::default(), 11);
+    }
+
+    #[test]
+    fn test_parabolic_orbit() {
+        let (offset, velocity) = parabolic_orbit(1.0e7, 500.0, 100.0);
+        let speed_sq = velocity.0 * velocity.0 + velocity.1 * velocity.1;
+
+        // Zero orbital energy, and the pericentre set by the angular momentum
+        assert!((0.5 * speed_sq - 1.0e7 / offset.0).abs() < 1e-3 * 1.0e7 / offset.0);
+        let angular_momentum = offset.0 * velocity.1;
+        let pericentre = angular_momentum * angular_momentum / (2.0 * 1.0e7);
+        assert!((pericentre - 100.0).abs() < 1e-2);
+    }
+}
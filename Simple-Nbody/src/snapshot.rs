@@ -0,0 +1,370 @@
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+
+use crate::checkpoint::{checkpoint_path, write_checkpoint};
+use crate::units::UnitSystem;
+use crate::{active_count, Float, Simulation, VectorArray};
+
+// Snapshots are plain text, so they can be post-processed with anything that reads csv
+// Header lines start with '#' and hold "key value" pairs, followed by a row per body:
+//
+// # iteration 100
+// # t 1
+// # seed 259
+// # dimensions 3
+// # units nbody
+// index,mass,x,y,z,vx,vy,vz
+// 0,0.0123,-12.5,40.25,3,0.5,-1,0.75
+// ...
+//
+// Two dimensional runs still write z columns, which are all 0
+// Older snapshots without z columns or the dimensions header are read as two dimensional
+// Masses are written in the units of the run rather than multiplied by G as in mass_vectors,
+// and converted back on reading. Snapshots without a units header are in nbody units
+// Test particles can be left out, in which case the massive bodies keep their indices
+
+pub struct Snapshot {
+    pub iteration: u64,
+    pub t: Float,
+    pub seed: u64,
+    pub dimensions: u32,
+    pub units: UnitSystem,
+    pub mass_vectors: Vec<Float>,
+    pub position_vectors: VectorArray,
+    pub velocity_vectors: VectorArray,
+}
+
+pub fn snapshot_path(output_dir: &Path, iteration: u64) -> std::path::PathBuf {
+    output_dir.join(format!("snapshot_{:08}.csv", iteration))
+}
+
+pub fn write_snapshot(
+    path: &Path,
+    simulation: &Simulation,
+    include_test_particles: bool,
+) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+
+    writeln!(file, "# iteration {}", simulation.iteration)?;
+    writeln!(file, "# t {}", simulation.t)?;
+    writeln!(file, "# seed {}", simulation.seed)?;
+    writeln!(file, "# dimensions {}", simulation.dimensions)?;
+    writeln!(file, "# units {}", simulation.units.name)?;
+    writeln!(file, "index,mass,x,y,z,vx,vy,vz")?;
+    let mut count = simulation.mass_vectors.len();
+    if !include_test_particles {
+        count = active_count(&simulation.mass_vectors);
+    }
+    for i in 0..count {
+        writeln!(
+            file,
+            "{},{},{},{},{},{},{},{}",
+            i,
+            simulation.units.physical_mass(simulation.mass_vectors[i]),
+            simulation.position_vectors.x[i],
+            simulation.position_vectors.y[i],
+            simulation.position_vectors.z[i],
+            simulation.velocity_vectors.x[i],
+            simulation.velocity_vectors.y[i],
+            simulation.velocity_vectors.z[i],
+        )?;
+    }
+
+    file.flush()
+}
+
+pub fn read_snapshot(path: &Path) -> io::Result<Snapshot> {
+    let file = BufReader::new(File::open(path)?);
+    let mut snapshot = Snapshot {
+        iteration: 0,
+        t: 0.0,
+        seed: 0,
+        dimensions: 2,
+        units: UnitSystem::NBODY,
+        mass_vectors: Vec::new(),
+        position_vectors: VectorArray::default(),
+        velocity_vectors: VectorArray::default(),
+    };
+
+    for line in file.lines() {
+        let line = line?;
+        if let Some(header) = line.strip_prefix('#') {
+            let mut parts = header.split_whitespace();
+            let key = parts.next().unwrap_or("");
+            let value = parts.next().unwrap_or("");
+            if key == "iteration" {
+                snapshot.iteration = value.parse().map_err(invalid_data)?;
+            } else if key == "t" {
+                snapshot.t = value.parse().map_err(invalid_data)?;
+            } else if key == "seed" {
+                snapshot.seed = value.parse().map_err(invalid_data)?;
+            } else if key == "dimensions" {
+                snapshot.dimensions = value.parse().map_err(invalid_data)?;
+            } else if key == "units" {
+                snapshot.units = UnitSystem::from_name(value).map_err(invalid_data)?;
+            }
+            continue;
+        }
+        if line.starts_with("index") || line.is_empty() {
+            continue;
+        }
+
+        let values: Vec<Float> = line
+            .split(',')
+            .skip(1)
+            .map(|value| value.parse().map_err(invalid_data))
+            .collect::<io::Result<Vec<Float>>>()?;
+        if values.len() == 7 {
+            snapshot.mass_vectors.push(values[0]);
+            snapshot
+                .position_vectors
+                .push((values[1], values[2], values[3]));
+            snapshot
+                .velocity_vectors
+                .push((values[4], values[5], values[6]));
+        } else if values.len() == 5 {
+            snapshot.mass_vectors.push(values[0]);
+            snapshot.position_vectors.push((values[1], values[2], 0.0));
+            snapshot.velocity_vectors.push((values[3], values[4], 0.0));
+        } else {
+            return Err(invalid_data(format!(
+                "Expected 6 or 8 columns in row: {line}"
+            )));
+        }
+    }
+    for mass in snapshot.mass_vectors.iter_mut() {
+        *mass = snapshot.units.simulation_mass(*mass);
+    }
+
+    Ok(snapshot)
+}
+
+fn invalid_data<E: ToString>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error.to_string())
+}
+
+pub fn run_headless(
+    simulation: &mut Simulation,
+    steps: u64,
+    snapshot_every: u64,
+    checkpoint_every: u64,
+    output_dir: &Path,
+    include_test_particles: bool,
+    mut on_snapshot: impl FnMut(&Simulation),
+) -> io::Result<()> {
+    // Runs without a window until the simulation reaches iteration steps
+    // Snapshots are written at the start, every snapshot_every iterations, and at the end
+    // on_snapshot is called after each snapshot written while running, to report progress
+    // Checkpoints are written every checkpoint_every iterations, and at the end, if non zero
+    // Counting by iteration rather than by step means a restarted run writes the same files
+    fs::create_dir_all(output_dir)?;
+    write_snapshot(
+        &snapshot_path(output_dir, simulation.iteration),
+        simulation,
+        include_test_particles,
+    )?;
+
+    while simulation.iteration < steps {
+        simulation.update();
+        let iteration = simulation.iteration;
+        if (snapshot_every > 0 && iteration.is_multiple_of(snapshot_every)) || iteration == steps {
+            write_snapshot(
+                &snapshot_path(output_dir, iteration),
+                simulation,
+                include_test_particles,
+            )?;
+            on_snapshot(simulation);
+        }
+        if checkpoint_every > 0
+            && (iteration.is_multiple_of(checkpoint_every) || iteration == steps)
+        {
+            write_checkpoint(&checkpoint_path(output_dir, iteration), simulation)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::snapshot::*;
+    use crate::softening::Softening;
+    use crate::{Simulation, VectorArray};
+
+    fn small_simulation() -> Simulation {
+        let mass_vectors = vec![100.0, 200.0, 300.0];
+        let position_vectors = VectorArray {
+            x: vec![-10.0, 0.5, 20.0],
+            y: vec![3.0, -40.0, 0.0],
+            z: vec![5.0, 0.0, -2.5],
+        };
+        let velocity_vectors = VectorArray {
+            x: vec![0.1, 0.0, -0.3],
+            y: vec![0.0, 1.0, 0.25],
+            z: vec![0.0, -0.5, 0.0],
+        };
+
+        Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            "direct",
+            0.0,
+            "leap_frog",
+            Softening::default(),
+        )
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let output_dir = std::env::temp_dir().join("simple_nbody_test_snapshot_round_trip");
+        fs::create_dir_all(&output_dir).unwrap();
+        let mut simulation = small_simulation();
+        simulation.seed = 259;
+        simulation.dimensions = 3;
+        for _i in 0..7 {
+            simulation.update();
+        }
+
+        let path = snapshot_path(&output_dir, simulation.iteration);
+        write_snapshot(&path, &simulation, true).unwrap();
+        let snapshot = read_snapshot(&path).unwrap();
+
+        assert_eq!(snapshot.iteration, 7);
+        assert_eq!(snapshot.t, simulation.t);
+        assert_eq!(snapshot.seed, 259);
+        assert_eq!(snapshot.dimensions, 3);
+        assert_eq!(snapshot.mass_vectors, simulation.mass_vectors);
+        assert_eq!(snapshot.position_vectors.x, simulation.position_vectors.x);
+        assert_eq!(snapshot.position_vectors.y, simulation.position_vectors.y);
+        assert_eq!(snapshot.velocity_vectors.x, simulation.velocity_vectors.x);
+        assert_eq!(snapshot.velocity_vectors.y, simulation.velocity_vectors.y);
+        assert_eq!(snapshot.position_vectors.z, simulation.position_vectors.z);
+        assert_eq!(snapshot.velocity_vectors.z, simulation.velocity_vectors.z);
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_units() {
+        // Masses are written in solar masses, and read back multiplied by G
+        let path = std::env::temp_dir().join("simple_nbody_test_snapshot_units.csv");
+        let mut simulation = small_simulation();
+        simulation.units = UnitSystem::ASTRONOMICAL;
+        simulation.mass_vectors = vec![
+            UnitSystem::ASTRONOMICAL.simulation_mass(1.0),
+            UnitSystem::ASTRONOMICAL.simulation_mass(3.0e-6),
+            0.0,
+        ];
+
+        write_snapshot(&path, &simulation, true).unwrap();
+        let contents = fs::read_to_string(&path).unwrap();
+        let snapshot = read_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert!(contents.contains("# units astronomical\n"));
+        let first_row = contents
+            .lines()
+            .find(|line| line.starts_with("0,"))
+            .unwrap();
+        let solar_mass: f64 = first_row.split(',').nth(1).unwrap().parse().unwrap();
+        assert!((solar_mass - 1.0).abs() < 1e-6);
+        assert_eq!(snapshot.units, UnitSystem::ASTRONOMICAL);
+        for i in 0..3 {
+            let difference = snapshot.mass_vectors[i] - simulation.mass_vectors[i];
+            assert!(difference.abs() <= 1e-6 * simulation.mass_vectors[i]);
+        }
+    }
+
+    #[test]
+    fn test_snapshot_without_test_particles() {
+        let path = std::env::temp_dir().join("simple_nbody_test_snapshot_test_particles.csv");
+        let mut simulation = small_simulation();
+        simulation.add_test_particles(4, 100.0, 1);
+
+        write_snapshot(&path, &simulation, false).unwrap();
+        let hidden = read_snapshot(&path).unwrap();
+        write_snapshot(&path, &simulation, true).unwrap();
+        let shown = read_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(hidden.mass_vectors, [100.0, 200.0, 300.0]);
+        assert_eq!(
+            hidden.position_vectors.x,
+            simulation.position_vectors.x[..3]
+        );
+        assert_eq!(shown.mass_vectors.len(), 7);
+        assert_eq!(shown.position_vectors.x, simulation.position_vectors.x);
+    }
+
+    #[test]
+    fn test_read_two_dimensional_snapshot() {
+        let path = std::env::temp_dir().join("simple_nbody_test_two_dimensional_snapshot.csv");
+        fs::write(
+            &path,
+            "# iteration 3\n# t 0.3\n# seed 1\nindex,mass,x,y,vx,vy\n0,2,-1.5,4,0.5,-1\n",
+        )
+        .unwrap();
+
+        let snapshot = read_snapshot(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(snapshot.dimensions, 2);
+        assert_eq!(snapshot.units, UnitSystem::NBODY);
+        assert_eq!(snapshot.mass_vectors, [2.0]);
+        assert_eq!(snapshot.position_vectors.at(0), (-1.5, 4.0, 0.0));
+        assert_eq!(snapshot.velocity_vectors.at(0), (0.5, -1.0, 0.0));
+    }
+
+    #[test]
+    fn test_read_snapshot_rejects_short_rows() {
+        let path = std::env::temp_dir().join("simple_nbody_test_short_row_snapshot.csv");
+        for row in ["5", "5,", "5,2,1,1"] {
+            fs::write(&path, format!("index,mass,x,y,vx,vy\n{row}\n")).unwrap();
+            let error = read_snapshot(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        }
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_run_headless() {
+        let output_dir = std::env::temp_dir().join("simple_nbody_test_run_headless");
+        let _ = fs::remove_dir_all(&output_dir);
+        let mut simulation = small_simulation();
+
+        let mut reported = Vec::new();
+        run_headless(
+            &mut simulation,
+            25,
+            10,
+            20,
+            &output_dir,
+            true,
+            |simulation| reported.push(simulation.iteration),
+        )
+        .unwrap();
+        assert_eq!(reported, [10, 20, 25]);
+        let mut files: Vec<String> = fs::read_dir(&output_dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+
+        assert_eq!(simulation.iteration, 25);
+        assert_eq!(
+            files,
+            [
+                "checkpoint_00000020.bin",
+                "checkpoint_00000025.bin",
+                "snapshot_00000000.csv",
+                "snapshot_00000010.csv",
+                "snapshot_00000020.csv",
+                "snapshot_00000025.csv"
+            ]
+        );
+        fs::remove_dir_all(&output_dir).unwrap();
+    }
+}
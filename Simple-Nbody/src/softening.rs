@@ -0,0 +1,236 @@
+use crate::{Float, EPSILON};
+
+// Softening kernels, which keep forces finite when two bodies pass close to each other
+// Each kernel is described by the factor g(r) in the acceleration m g(r) r_vec on a body
+// from a mass m at separation r_vec, along with the potential it derives from, so the
+// energy diagnostics stay consistent with the forces
+//
+// None: the Newtonian force, g = 1 / r^3
+// Plummer: g = 1 / (r^2 + epsilon^2)^(3/2), softened at all separations
+// Spline: the cubic spline kernel of Monaghan & Lattanzio (1985), in the form used by
+//         Gadget-2 (Springel 2005), with support h. Exactly Newtonian for r >= h, and the
+//         potential at r = 0 matches a Plummer potential of epsilon = h / 2.8
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Softening {
+    None,
+    Plummer(Float),
+    Spline(Float),
+}
+
+impl Default for Softening {
+    fn default() -> Softening {
+        Softening::Plummer(EPSILON)
+    }
+}
+
+impl Softening {
+    pub fn from_name(name: &str, length: Float) -> Result<Softening, String> {
+        // Kernel names as given on the command line and stored in checkpoints
+        // Plummer softening of length 0 is Newtonian, while the spline divides by its support
+        if name == "none" {
+            Ok(Softening::None)
+        } else if name == "plummer" && length >= 0.0 {
+            Ok(Softening::Plummer(length))
+        } else if name == "spline" && length > 0.0 {
+            Ok(Softening::Spline(length))
+        } else if name == "plummer" || name == "spline" {
+            Err(format!("Invalid softening length {length} for {name}"))
+        } else {
+            Err(format!("Invalid softening kernel {name}"))
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Softening::None => "none",
+            Softening::Plummer(_) => "plummer",
+            Softening::Spline(_) => "spline",
+        }
+    }
+
+    pub fn length(&self) -> Float {
+        match *self {
+            Softening::None => 0.0,
+            Softening::Plummer(epsilon) => epsilon,
+            Softening::Spline(h) => h,
+        }
+    }
+
+    pub fn force_factor(&self, r_sq: Float) -> Float {
+        // g(r), so the acceleration from a mass m at separation r_vec is m g(r) r_vec
+        match *self {
+            Softening::None => 1.0 / r_sq.powf(1.5),
+            Softening::Plummer(epsilon) => 1.0 / (r_sq + epsilon * epsilon).powf(1.5),
+            Softening::Spline(h) => {
+                let r = r_sq.sqrt();
+                let u = r / h;
+                if u >= 1.0 {
+                    1.0 / (r_sq * r)
+                } else if u < 0.5 {
+                    (32.0 / 3.0 + u * u * (32.0 * u - 38.4)) / h.powi(3)
+                } else {
+                    (64.0 / 3.0 - 48.0 * u + 38.4 * u * u
+                        - 32.0 / 3.0 * u.powi(3)
+                        - 1.0 / 15.0 / u.powi(3))
+                        / h.powi(3)
+                }
+            }
+        }
+    }
+
+    pub fn jerk_factor(&self, r_sq: Float) -> Float {
+        // (dg/dr) / r, so the jerk from a mass m is m (g v_vec + (dg/dr) / r (r_vec.v_vec) r_vec)
+        match *self {
+            Softening::None => -3.0 / r_sq.powf(2.5),
+            Softening::Plummer(epsilon) => -3.0 / (r_sq + epsilon * epsilon).powf(2.5),
+            Softening::Spline(h) => {
+                let r = r_sq.sqrt();
+                let u = r / h;
+                if u >= 1.0 {
+                    -3.0 / (r_sq * r_sq * r)
+                } else if u < 0.5 {
+                    (96.0 * u - 76.8) / h.powi(5)
+                } else {
+                    (-48.0 / u + 76.8 - 32.0 * u + 0.2 / u.powi(5)) / h.powi(5)
+                }
+            }
+        }
+    }
+
+    pub fn potential(&self, r_sq: Float) -> Float {
+        // Potential between two unit masses, whose negative gradient gives force_factor()
+        match *self {
+            Softening::None => -1.0 / r_sq.sqrt(),
+            Softening::Plummer(epsilon) => -1.0 / (r_sq + epsilon * epsilon).sqrt(),
+            Softening::Spline(h) => {
+                let r = r_sq.sqrt();
+                let u = r / h;
+                if u >= 1.0 {
+                    -1.0 / r
+                } else if u < 0.5 {
+                    (16.0 / 3.0 * u * u - 48.0 / 5.0 * u.powi(4) + 32.0 / 5.0 * u.powi(5)
+                        - 14.0 / 5.0)
+                        / h
+                } else {
+                    (1.0 / (15.0 * u) + 32.0 / 3.0 * u * u - 16.0 * u.powi(3)
+                        + 48.0 / 5.0 * u.powi(4)
+                        - 32.0 / 15.0 * u.powi(5)
+                        - 16.0 / 5.0)
+                        / h
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::softening::Softening;
+    use crate::Float;
+
+    fn assert_close(a: Float, b: Float, tolerance: Float) {
+        assert!(
+            (a - b).abs() <= tolerance * b.abs(),
+            "{a} and {b} differ by more than {tolerance}"
+        );
+    }
+
+    #[test]
+    fn test_none_is_newtonian() {
+        for r in [0.1 as Float, 1.0, 7.5, 300.0] {
+            assert_close(Softening::None.force_factor(r * r) * r, 1.0 / (r * r), 1e-6);
+            assert_close(Softening::None.potential(r * r), -1.0 / r, 1e-6);
+            assert_close(Softening::None.jerk_factor(r * r), -3.0 / r.powi(5), 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_plummer() {
+        let softening = Softening::Plummer(5.0);
+        for r in [0.0 as Float, 1.0, 5.0, 300.0] {
+            let s = r * r + 25.0;
+            assert_close(softening.force_factor(r * r), 1.0 / s.powf(1.5), 1e-6);
+            assert_close(softening.potential(r * r), -1.0 / s.sqrt(), 1e-6);
+        }
+        // Approaches the Newtonian force far outside the softening length
+        assert_close(
+            softening.force_factor(1.0e6),
+            Softening::None.force_factor(1.0e6),
+            1e-4,
+        );
+    }
+
+    #[test]
+    fn test_spline() {
+        let h = 2.0;
+        let softening = Softening::Spline(h);
+
+        // Exactly Newtonian from the softening length outwards
+        for r in [2.0 as Float, 2.5, 40.0] {
+            assert_eq!(softening.force_factor(r * r), 1.0 / (r * r * r));
+            assert_eq!(softening.potential(r * r), -1.0 / r);
+        }
+        // Finite at the centre, where the potential is that of Plummer softening with h / 2.8
+        assert_close(softening.potential(0.0), -2.8 / h, 1e-6);
+        assert_close(softening.force_factor(0.0), 32.0 / 3.0 / h.powi(3), 1e-6);
+        // Continuous where the pieces of the kernel meet
+        for u in [0.5 as Float, 1.0] {
+            let (below, above) = ((u * h - 1e-4).powi(2), (u * h + 1e-4).powi(2));
+            assert_close(softening.potential(below), softening.potential(above), 1e-3);
+            assert_close(
+                softening.force_factor(below),
+                softening.force_factor(above),
+                1e-3,
+            );
+        }
+    }
+
+    #[test]
+    fn test_force_is_gradient_of_potential() {
+        // -dphi/dr = -g(r) r and d(g)/dr / r = jerk_factor, checked by central differences
+        let dr = 1e-2;
+        for softening in [
+            Softening::None,
+            Softening::Plummer(5.0),
+            Softening::Spline(8.0),
+        ] {
+            for r in [1.0 as Float, 2.5, 3.9, 4.1, 6.0, 7.9, 8.1, 12.0] {
+                let potential_gradient = (softening.potential((r + dr).powi(2))
+                    - softening.potential((r - dr).powi(2)))
+                    / (2.0 * dr);
+                assert_close(
+                    -potential_gradient,
+                    -softening.force_factor(r * r) * r,
+                    1e-2,
+                );
+
+                let force_gradient = (softening.force_factor((r + dr).powi(2))
+                    - softening.force_factor((r - dr).powi(2)))
+                    / (2.0 * dr);
+                assert_close(force_gradient / r, softening.jerk_factor(r * r), 1e-2);
+            }
+        }
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(
+            Softening::from_name("spline", 3.0),
+            Ok(Softening::Spline(3.0))
+        );
+        assert_eq!(Softening::from_name("none", 3.0), Ok(Softening::None));
+        assert!(Softening::from_name("gaussian", 3.0).is_err());
+        assert!(Softening::from_name("plummer", -1.0).is_err());
+        assert!(Softening::from_name("spline", 0.0).is_err());
+        for softening in [
+            Softening::None,
+            Softening::Plummer(5.0),
+            Softening::Spline(2.0),
+        ] {
+            assert_eq!(
+                Softening::from_name(softening.name(), softening.length()),
+                Ok(softening)
+            );
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use ::rand::prelude::*;
+
+use crate::{Float, VectorArray};
+
+// Fixtures shared by the tests of the approximate force solvers
+
+pub fn random_particles(n: usize, depth: Float) -> (VectorArray, Vec<Float>) {
+    // Bodies with masses up to 100 in a 1000 wide square, or a box of the given depth
+    // Planar bodies draw no z, so keep the same x, y and masses whatever the depth
+    let mut rng = StdRng::seed_from_u64(259);
+    let mut position_vectors = VectorArray::zeros(n);
+    let mut mass_vectors = Vec::new();
+    for i in 0..n {
+        position_vectors.x[i] = rng.gen::<Float>() * 1000.0;
+        position_vectors.y[i] = rng.gen::<Float>() * 1000.0;
+        if depth > 0.0 {
+            position_vectors.z[i] = rng.gen::<Float>() * depth;
+        }
+        mass_vectors.push(rng.gen::<Float>() * 100.0);
+    }
+
+    (position_vectors, mass_vectors)
+}
+
+pub fn relative_error(acc_vectors: &VectorArray, reference: &VectorArray) -> Float {
+    // Sum of errors in acceleration magnitude over sum of reference magnitudes
+    let mut error = 0.0;
+    let mut total = 0.0;
+    for i in 0..reference.x.len() {
+        let dx = acc_vectors.x[i] - reference.x[i];
+        let dy = acc_vectors.y[i] - reference.y[i];
+        let dz = acc_vectors.z[i] - reference.z[i];
+        error += (dx.powi(2) + dy.powi(2) + dz.powi(2)).sqrt();
+        total += (reference.x[i].powi(2) + reference.y[i].powi(2) + reference.z[i].powi(2)).sqrt();
+    }
+
+    error / total
+}
@@ -0,0 +1,154 @@
+use ::rand::prelude::*;
+
+use crate::initial_conditions::new_rng;
+use crate::{active_count, float_consts, Float, Simulation, VectorArray};
+
+// Test particles are massless tracers: they move in the field of the massive bodies and any
+// external potential, but exert no forces themselves. They are stored after the massive
+// bodies, so active_count() marks where they start, and direct sums only take forces from
+// the bodies before it. Forces then cost O(active * n) instead of O(n^2), and thousands of
+// tracers add little to a run with few massive bodies
+//
+// Tree methods skip cells holding no mass, and particle mesh and FMM costs barely depend on
+// the number of bodies, so those handle test particles cheaply as well
+
+// Inner edge of the tracer disk, as a fraction of its radius
+pub const TEST_DISK_INNER: Float = 0.1;
+
+impl Simulation {
+    pub fn add_test_particles(&mut self, count: u32, radius: Float, seed: u64) {
+        // Adds tracers on circular orbits in the x-y plane around the origin, spread evenly
+        // over the area between TEST_DISK_INNER * radius and radius, moving anticlockwise
+        // Their speeds come from the radial pull of the bodies and external potential at
+        // their positions, so the disk starts in equilibrium with whatever field it is in
+        let mut rng = new_rng(seed);
+        let first = self.mass_vectors.len();
+        for _i in 0..count {
+            let area_fraction: Float = rng.gen();
+            let inner_sq = TEST_DISK_INNER * TEST_DISK_INNER;
+            let r = radius * (inner_sq + (1.0 - inner_sq) * area_fraction).sqrt();
+            let angle = 2.0 * float_consts::PI * rng.gen::<Float>();
+            self.mass_vectors.push(0.0);
+            self.position_vectors
+                .push((r * angle.cos(), r * angle.sin(), 0.0));
+            self.velocity_vectors.push((0.0, 0.0, 0.0));
+        }
+
+        self.acc_vectors = self.compute_acc_vectors();
+        self.jerk_vectors = VectorArray::zeros(0);
+        for i in first..self.mass_vectors.len() {
+            let (x, y, _) = self.position_vectors.at(i);
+            let (ax, ay, _) = self.acc_vectors.at(i);
+            let r = (x * x + y * y).sqrt();
+            let inward = -(ax * x + ay * y) / r;
+            let speed = (inward.max(0.0) * r).sqrt();
+            self.velocity_vectors.x[i] = -speed * y / r;
+            self.velocity_vectors.y[i] = speed * x / r;
+        }
+    }
+
+    pub fn test_particle_count(&self) -> usize {
+        self.mass_vectors.len() - active_count(&self.mass_vectors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::softening::Softening;
+    use crate::test_particles::*;
+    use crate::{active_count, generate_acc_vectors, initial_conditions::generate_uniform_box};
+
+    fn cluster(force_method: &str) -> Simulation {
+        let (mass_vectors, position_vectors, velocity_vectors) =
+            generate_uniform_box(40, 200.0, 200.0, 0.0, 3);
+        Simulation::new(
+            mass_vectors,
+            position_vectors,
+            velocity_vectors,
+            force_method,
+            0.5,
+            "leap_frog",
+            Softening::default(),
+        )
+    }
+
+    #[test]
+    fn test_active_count() {
+        assert_eq!(active_count(&[]), 0);
+        assert_eq!(active_count(&[0.0, 0.0]), 0);
+        assert_eq!(active_count(&[1.0, 2.0, 0.0, 0.0]), 2);
+        // A massive body after test particles still counts, so nothing is missed
+        assert_eq!(active_count(&[1.0, 0.0, 3.0, 0.0]), 3);
+    }
+
+    #[test]
+    fn test_test_particles_feel_but_do_not_pull() {
+        for force_method in ["direct", "pairwise", "barnes_hut", "fmm"] {
+            let mut massive = cluster(force_method);
+            let mut traced = massive.clone();
+            traced.add_test_particles(500, 300.0, 11);
+            assert_eq!(traced.test_particle_count(), 500);
+
+            // Tracers feel the same forces as from the direct sum over every body, to within
+            // the accuracy of the approximate methods. Errors are measured against each force
+            // plus the mean force, as forces nearly cancel on some bodies
+            let reference = generate_acc_vectors(
+                &traced.position_vectors,
+                &traced.mass_vectors,
+                Softening::default(),
+                1,
+            );
+            let size = |(x, y, _): (Float, Float, Float)| (x * x + y * y).sqrt();
+            let n = traced.mass_vectors.len();
+            let mean = (0..n).map(|i| size(reference.at(i))).sum::<Float>() / n as Float;
+            for i in 0..n {
+                let (ax, ay, _) = traced.acc_vectors.at(i);
+                let (rx, ry, _) = reference.at(i);
+                let error = size((ax - rx, ay - ry, 0.0));
+                let scale = size(reference.at(i)) + mean;
+                assert!(error < 0.03 * scale, "{force_method} body {i}");
+            }
+
+            // Direct sums also leave the massive bodies exactly as they would be without
+            // them, while trees are built around the tracers too, so group bodies differently
+            if force_method == "direct" || force_method == "pairwise" {
+                for _i in 0..20 {
+                    massive.update();
+                    traced.update();
+                }
+                let n = massive.mass_vectors.len();
+                assert_eq!(massive.position_vectors.x, traced.position_vectors.x[..n]);
+                assert_eq!(massive.position_vectors.y, traced.position_vectors.y[..n]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_tracer_disk_stays_circular() {
+        let mut simulation = Simulation::new(
+            vec![1.0e6],
+            VectorArray::zeros(1),
+            VectorArray::zeros(1),
+            "direct",
+            0.0,
+            "yoshida",
+            Softening::None,
+        );
+        simulation.add_test_particles(200, 100.0, 5);
+        let radius = |simulation: &Simulation, i: usize| {
+            let (x, y, _) = simulation.position_vectors.at(i);
+            (x * x + y * y).sqrt()
+        };
+        let initial: Vec<Float> = (1..201).map(|i| radius(&simulation, i)).collect();
+
+        // An orbit at the outer edge takes about 0.63
+        simulation.h = 0.001;
+        for _i in 0..700 {
+            simulation.update();
+        }
+        for i in 1..201 {
+            assert!((radius(&simulation, i) / initial[i - 1] - 1.0).abs() < 1e-3);
+        }
+        assert_eq!(simulation.position_vectors.at(0), (0.0, 0.0, 0.0));
+    }
+}
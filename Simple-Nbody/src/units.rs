@@ -0,0 +1,213 @@
+use crate::Float;
+
+// Systems of units the simulation can run in
+// Positions, velocities and times are stored in the system's units as they are, while masses
+// are stored multiplied by G in the system's units, so the simulation itself always has G = 1
+// Each system is described by the size of its units in SI, from which G follows
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitSystem {
+    pub name: &'static str,
+    // Size of the units in metres, seconds and kilograms
+    pub length: f64,
+    pub time: f64,
+    pub mass: f64,
+}
+
+// CODATA 2018, in m^3 kg^-1 s^-2. G in lib.rs is the same value in the simulation's precision
+pub const G_SI: f64 = 6.674_30e-11;
+pub const ASTRONOMICAL_UNIT: f64 = 1.495_978_707e11;
+pub const DAY: f64 = 86_400.0;
+// Julian year
+pub const YEAR: f64 = 365.25 * DAY;
+// From the IAU nominal solar mass parameter, 1.3271244e20 m^3 s^-2
+pub const SOLAR_MASS: f64 = 1.327_124_4e20 / G_SI;
+pub const KILOPARSEC: f64 = 3.085_677_581_491_367e19;
+// Exact, in m s^-1
+pub const SPEED_OF_LIGHT: f64 = 299_792_458.0;
+
+impl UnitSystem {
+    pub const SI: UnitSystem = UnitSystem {
+        name: "si",
+        length: 1.0,
+        time: 1.0,
+        mass: 1.0,
+    };
+    // Astronomical units, years and solar masses, suited to planetary systems
+    pub const ASTRONOMICAL: UnitSystem = UnitSystem {
+        name: "astronomical",
+        length: ASTRONOMICAL_UNIT,
+        time: YEAR,
+        mass: SOLAR_MASS,
+    };
+    // Kiloparsecs, gigayears and 1e10 solar masses, suited to galaxies
+    pub const GALACTIC: UnitSystem = UnitSystem {
+        name: "galactic",
+        length: KILOPARSEC,
+        time: 1.0e9 * YEAR,
+        mass: 1.0e10 * SOLAR_MASS,
+    };
+    // N-body units, where G = 1 and masses are stored as they are given
+    // Lengths and times are left unscaled, so the mass unit is 1 / G kilograms
+    pub const NBODY: UnitSystem = UnitSystem {
+        name: "nbody",
+        length: 1.0,
+        time: 1.0,
+        mass: 1.0 / G_SI,
+    };
+
+    pub fn from_name(name: &str) -> Result<UnitSystem, String> {
+        // Names as given on the command line and stored in output files
+        for units in [
+            UnitSystem::SI,
+            UnitSystem::ASTRONOMICAL,
+            UnitSystem::GALACTIC,
+            UnitSystem::NBODY,
+        ] {
+            if units.name == name {
+                return Ok(units);
+            }
+        }
+
+        Err(format!("Invalid unit system {name}"))
+    }
+
+    pub fn gravitational_constant(&self) -> f64 {
+        // G in this system's units of length^3 mass^-1 time^-2
+        G_SI * self.mass * self.time.powi(2) / self.length.powi(3)
+    }
+
+    pub fn speed_of_light(&self) -> f64 {
+        // c in this system's units of length time^-1, for post-Newtonian corrections
+        UnitSystem::SI.convert_velocity(SPEED_OF_LIGHT, self)
+    }
+
+    pub fn simulation_mass(&self, mass: Float) -> Float {
+        // Converts a mass in this system's units to the G times mass stored by the simulation
+        (self.gravitational_constant() * mass as f64) as Float
+    }
+
+    pub fn physical_mass(&self, simulation_mass: Float) -> Float {
+        // Inverse of simulation_mass()
+        (simulation_mass as f64 / self.gravitational_constant()) as Float
+    }
+
+    pub fn convert(
+        &self,
+        value: f64,
+        length_power: i32,
+        time_power: i32,
+        mass_power: i32,
+        to: &UnitSystem,
+    ) -> f64 {
+        // Converts a quantity of dimensions length^length_power time^time_power
+        // mass^mass_power from this system's units to those of another system
+        value
+            * (self.length / to.length).powi(length_power)
+            * (self.time / to.time).powi(time_power)
+            * (self.mass / to.mass).powi(mass_power)
+    }
+
+    pub fn convert_length(&self, value: f64, to: &UnitSystem) -> f64 {
+        self.convert(value, 1, 0, 0, to)
+    }
+
+    pub fn convert_time(&self, value: f64, to: &UnitSystem) -> f64 {
+        self.convert(value, 0, 1, 0, to)
+    }
+
+    pub fn convert_mass(&self, value: f64, to: &UnitSystem) -> f64 {
+        self.convert(value, 0, 0, 1, to)
+    }
+
+    pub fn convert_velocity(&self, value: f64, to: &UnitSystem) -> f64 {
+        self.convert(value, 1, -1, 0, to)
+    }
+
+    pub fn convert_energy(&self, value: f64, to: &UnitSystem) -> f64 {
+        self.convert(value, 2, -2, 1, to)
+    }
+}
+
+impl Default for UnitSystem {
+    fn default() -> UnitSystem {
+        UnitSystem::NBODY
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::units::*;
+
+    fn assert_close(a: f64, b: f64, tolerance: f64) {
+        assert!(
+            (a - b).abs() <= tolerance * b.abs(),
+            "{a} and {b} differ by more than {tolerance}"
+        );
+    }
+
+    #[test]
+    fn test_gravitational_constant() {
+        // G is 4 pi^2 AU^3 / (solar mass yr^2) by Kepler's third law, up to the difference
+        // between the Julian and sidereal year
+        let four_pi_sq = 4.0 * std::f64::consts::PI.powi(2);
+        assert_close(UnitSystem::SI.gravitational_constant(), 6.6743e-11, 1e-6);
+        assert_close(
+            UnitSystem::ASTRONOMICAL.gravitational_constant(),
+            four_pi_sq,
+            1e-4,
+        );
+        assert_close(
+            UnitSystem::GALACTIC.gravitational_constant(),
+            4.4985e4,
+            1e-4,
+        );
+        assert_close(UnitSystem::NBODY.gravitational_constant(), 1.0, 1e-6);
+    }
+
+    #[test]
+    fn test_conversions() {
+        let si = UnitSystem::SI;
+        let astronomical = UnitSystem::ASTRONOMICAL;
+        let galactic = UnitSystem::GALACTIC;
+
+        assert_close(
+            astronomical.convert_length(1.0, &si),
+            ASTRONOMICAL_UNIT,
+            1e-12,
+        );
+        assert_close(si.convert_time(YEAR, &astronomical), 1.0, 1e-12);
+        assert_close(galactic.convert_mass(1.0, &astronomical), 1.0e10, 1e-12);
+        // 1 km/s is close to 1 pc/Myr, 1.0227 kpc/Gyr
+        assert_close(si.convert_velocity(1000.0, &galactic), 1.0227, 1e-4);
+        // Earth's orbital speed of 2 pi AU/yr is about 29.8 km/s
+        let earth_speed = 2.0 * std::f64::consts::PI;
+        assert_close(
+            astronomical.convert_velocity(earth_speed, &si),
+            29_785.0,
+            1e-3,
+        );
+        // Light travels an astronomical unit in about 499 seconds
+        assert_close(astronomical.speed_of_light(), YEAR / 499.005, 1e-5);
+        // Converting there and back is the identity
+        let energy = 3.5;
+        let converted = galactic.convert_energy(energy, &si);
+        assert_close(si.convert_energy(converted, &galactic), energy, 1e-12);
+    }
+
+    #[test]
+    fn test_simulation_mass() {
+        let earth_mass = 3.0035e-6;
+        let astronomical = UnitSystem::ASTRONOMICAL;
+        let simulation_mass = astronomical.simulation_mass(earth_mass);
+
+        assert!((simulation_mass / earth_mass - 39.476).abs() < 0.01);
+        assert!((astronomical.physical_mass(simulation_mass) - earth_mass).abs() < 1e-10);
+        assert!((UnitSystem::NBODY.simulation_mass(2.5) - 2.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(UnitSystem::from_name("galactic"), Ok(UnitSystem::GALACTIC));
+        assert!(UnitSystem::from_name("imperial").is_err());
+    }
+}